@@ -0,0 +1,284 @@
+// AIDEV-NOTE: Expands `#[derive(BigtableRow)]` into an implementation of
+// `bigtable::mapping::BigtableRow`. Field types are recognised by their syntax:
+// `Option<T>` is an optional cell (deleted when `None`), `Vec<(Ts, T)>` a
+// versioned history (the column is cleared, then one cell is written per
+// timestamp), anything else a required cell.
+// Values go through a codec module with `encode`/`decode` functions, by default
+// `bigtable::mapping::cell`, or the one named by `with = "..."`.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, GenericArgument, Ident, LitByteStr, LitStr, Path,
+    PathArguments, Type,
+};
+
+/// Derives `bigtable::mapping::BigtableRow`.
+///
+/// ```ignore
+/// #[derive(BigtableRow)]
+/// #[bigtable(family = "cf1")]
+/// struct User {
+///     #[bigtable(row_key)]
+///     id: String,
+///     name: String,
+///     #[bigtable(qualifier = "mail")]
+///     email: Option<String>,
+///     #[bigtable(family = "meta", with = "bigtable::mapping::json")]
+///     tags: Vec<String>,
+///     logins: Vec<(SystemTime, String)>,
+/// }
+/// ```
+#[proc_macro_derive(BigtableRow, attributes(bigtable))]
+pub fn derive_bigtable_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+enum Shape<'a> {
+    Required,
+    Optional,
+    Versioned(&'a Type),
+}
+
+struct Column<'a> {
+    ident: &'a Ident,
+    family: String,
+    qualifier: String,
+    codec: Path,
+    shape: Shape<'a>,
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    row_key: bool,
+    skip: bool,
+    family: Option<String>,
+    qualifier: Option<String>,
+    with: Option<Path>,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(error(input, "BigtableRow needs a struct with named fields")),
+        },
+        _ => return Err(error(input, "BigtableRow can only be derived for structs")),
+    };
+
+    let mut default_family = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("bigtable")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("family") {
+                default_family = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `family = \"...\"`"))
+            }
+        })?;
+    }
+
+    let default_codec: Path = syn::parse_quote!(::bigtable::mapping::cell);
+    let mut row_key = None;
+    let mut skipped = Vec::new();
+    let mut columns = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let attrs = field_attrs(field)?;
+        let codec = attrs.with.clone().unwrap_or_else(|| default_codec.clone());
+        if attrs.skip {
+            skipped.push(ident);
+            continue;
+        }
+        if attrs.row_key {
+            if row_key.is_some() {
+                return Err(error(field, "only one field can be the row_key"));
+            }
+            row_key = Some((ident, codec));
+            continue;
+        }
+        let family =
+            match attrs.family.or_else(|| default_family.clone()) {
+                Some(family) => family,
+                None => return Err(error(
+                    field,
+                    "no column family: set `#[bigtable(family = \"...\")]` on the field or struct",
+                )),
+            };
+        let qualifier = attrs
+            .qualifier
+            .unwrap_or_else(|| ident.to_string().trim_start_matches("r#").to_string());
+        let shape = match (wrapped(&field.ty, "Option"), wrapped(&field.ty, "Vec")) {
+            (Some(_), _) => Shape::Optional,
+            (_, Some(Type::Tuple(pair))) if pair.elems.len() == 2 => {
+                Shape::Versioned(&pair.elems[0])
+            }
+            _ => Shape::Required,
+        };
+        columns.push(Column {
+            ident,
+            family,
+            qualifier,
+            codec,
+            shape,
+        });
+    }
+    let (key_ident, key_codec) = match row_key {
+        Some(key) => key,
+        None => {
+            return Err(error(
+                input,
+                "mark the row key field with `#[bigtable(row_key)]`",
+            ))
+        }
+    };
+
+    let writes = columns.iter().map(|c| {
+        let (ident, family, codec) = (c.ident, &c.family, &c.codec);
+        let qualifier = LitByteStr::new(c.qualifier.as_bytes(), Span::call_site());
+        match c.shape {
+            Shape::Required => quote! {
+                mutations = mutations.set_cell(#family, #qualifier, &#codec::encode(&self.#ident)?);
+            },
+            Shape::Optional => quote! {
+                mutations = match &self.#ident {
+                    ::std::option::Option::Some(value) => {
+                        mutations.set_cell(#family, #qualifier, &#codec::encode(value)?)
+                    }
+                    ::std::option::Option::None => mutations.delete_cells(#family, #qualifier),
+                };
+            },
+            Shape::Versioned(_) => quote! {
+                mutations = mutations.delete_cells(#family, #qualifier);
+                for (timestamp, value) in &self.#ident {
+                    mutations = mutations.set_cell_at(
+                        #family,
+                        #qualifier,
+                        ::bigtable::mapping::CellTimestamp::to_micros(timestamp),
+                        &#codec::encode(value)?,
+                    );
+                }
+            },
+        }
+    });
+
+    let reads = columns.iter().map(|c| {
+        let (ident, family, codec) = (c.ident, &c.family, &c.codec);
+        let qualifier = LitByteStr::new(c.qualifier.as_bytes(), Span::call_site());
+        let column = format!("{}:{}", c.family, c.qualifier);
+        let value = match c.shape {
+            Shape::Required => quote! {
+                match ::bigtable::rows::latest_cell(row, #family, #qualifier) {
+                    ::std::option::Option::Some(cell) => #codec::decode(&cell.value)?,
+                    ::std::option::Option::None => {
+                        return ::std::result::Result::Err(::bigtable::mapping::missing_cell(#column))
+                    }
+                }
+            },
+            Shape::Optional => quote! {
+                match ::bigtable::rows::latest_cell(row, #family, #qualifier) {
+                    ::std::option::Option::Some(cell) => {
+                        ::std::option::Option::Some(#codec::decode(&cell.value)?)
+                    }
+                    ::std::option::Option::None => ::std::option::Option::None,
+                }
+            },
+            Shape::Versioned(timestamp) => quote! {
+                ::bigtable::mapping::cells(row, #family, #qualifier)
+                    .into_iter()
+                    .map(|cell| {
+                        ::std::result::Result::Ok((
+                            <#timestamp as ::bigtable::mapping::CellTimestamp>::from_micros(
+                                cell.timestamp_micros,
+                            ),
+                            #codec::decode(&cell.value)?,
+                        ))
+                    })
+                    .collect::<::std::result::Result<::std::vec::Vec<_>, ::bigtable::error::BTErr>>()?
+            },
+        };
+        quote!(#ident: #value,)
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bigtable::mapping::BigtableRow for #name #ty_generics #where_clause {
+            fn row_key(
+                &self,
+            ) -> ::std::result::Result<::std::vec::Vec<u8>, ::bigtable::error::BTErr> {
+                #key_codec::encode(&self.#key_ident)
+            }
+
+            fn to_mutations(
+                &self,
+            ) -> ::std::result::Result<::bigtable::wraps::RowMutation, ::bigtable::error::BTErr> {
+                let mut mutations = ::bigtable::wraps::RowMutation::new();
+                #(#writes)*
+                ::std::result::Result::Ok(mutations)
+            }
+
+            fn from_row(
+                row: &::bigtable::protos::data::Row,
+            ) -> ::std::result::Result<Self, ::bigtable::error::BTErr> {
+                ::std::result::Result::Ok(#name {
+                    #key_ident: #key_codec::decode(&row.key)?,
+                    #(#reads)*
+                    #(#skipped: ::std::default::Default::default(),)*
+                })
+            }
+        }
+    })
+}
+
+fn field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("bigtable")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("row_key") {
+                attrs.row_key = true;
+            } else if meta.path.is_ident("skip") {
+                attrs.skip = true;
+            } else if meta.path.is_ident("family") {
+                attrs.family = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("qualifier") {
+                attrs.qualifier = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("with") {
+                attrs.with = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+            } else {
+                return Err(
+                    meta.error("expected `row_key`, `skip`, `family`, `qualifier` or `with`")
+                );
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+// `T` if `ty` is `wrapper<T>` (matched on the last path segment).
+fn wrapped<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != wrapper {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn error<T: quote::ToTokens>(tokens: T, message: &str) -> syn::Error {
+    syn::Error::new_spanned(tokens, message)
+}
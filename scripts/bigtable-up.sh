@@ -8,6 +8,7 @@ CLUSTER_ID="${CLUSTER_ID:-test-cluster}"
 ZONE="${ZONE:-us-central1-c}"
 TABLE_NAME="${TABLE_NAME:-my-table}"
 COLUMN_FAMILY="${COLUMN_FAMILY:-cf1}"
+AGGREGATE_FAMILY="${AGGREGATE_FAMILY:-agg}"
 
 echo "Creating Bigtable instance..."
 echo "  Project: $PROJECT_ID"
@@ -46,8 +47,17 @@ else
     echo "Column family created."
 fi
 
+# Check if aggregate (Int64 sum) column family exists
+if cbt -project="$PROJECT_ID" -instance="$INSTANCE_ID" ls "$TABLE_NAME" 2>/dev/null | grep -q "$AGGREGATE_FAMILY"; then
+    echo "Column family '$AGGREGATE_FAMILY' already exists, skipping creation."
+else
+    cbt -project="$PROJECT_ID" -instance="$INSTANCE_ID" createfamily "$TABLE_NAME" "$AGGREGATE_FAMILY:intsum"
+    echo "Aggregate column family created."
+fi
+
 echo ""
 echo "Bigtable setup complete!"
 echo "  Instance: $INSTANCE_ID"
 echo "  Table: $TABLE_NAME"
 echo "  Column Family: $COLUMN_FAMILY"
+echo "  Aggregate Family: $AGGREGATE_FAMILY"
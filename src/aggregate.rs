@@ -0,0 +1,124 @@
+// AIDEV-NOTE: Aggregate column families (types.proto `Type.Aggregate`) accumulate
+// `AddToCell` inputs server-side. Sum/Min/Max state is an Int64 stored as 8-byte
+// big-endian; HLL++ state is opaque and only round-trips through `MergeToCell`.
+use crate::error::BTErr;
+use crate::protos::data::{mutation, value, Mutation, Value};
+use crate::protos::types::type_;
+use crate::protos::types::Type;
+use crate::utils::{decode_i64, encode_i64};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregator {
+    Sum,
+    Min,
+    Max,
+    HllppUniqueCount,
+}
+
+impl Aggregator {
+    /// Reads the aggregator from a column family's `Type`, if it is an aggregate.
+    pub fn from_type(ty: &Type) -> Option<Aggregator> {
+        match &ty.kind {
+            Some(type_::Kind::AggregateType(agg)) => match agg.aggregator {
+                Some(type_::aggregate::Aggregator::Sum(_)) => Some(Aggregator::Sum),
+                Some(type_::aggregate::Aggregator::Min(_)) => Some(Aggregator::Min),
+                Some(type_::aggregate::Aggregator::Max(_)) => Some(Aggregator::Max),
+                Some(type_::aggregate::Aggregator::HllppUniqueCount(_)) => {
+                    Some(Aggregator::HllppUniqueCount)
+                }
+                None => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Decoded state of an aggregate cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AggregateValue {
+    Sum(i64),
+    Min(i64),
+    Max(i64),
+    HllppUniqueCount(Vec<u8>),
+}
+
+impl AggregateValue {
+    /// Decodes the cell value returned by `ReadRows` for an aggregate family.
+    pub fn decode(aggregator: Aggregator, state: &[u8]) -> Result<AggregateValue, BTErr> {
+        Ok(match aggregator {
+            Aggregator::Sum => AggregateValue::Sum(decode_i64(state)?),
+            Aggregator::Min => AggregateValue::Min(decode_i64(state)?),
+            Aggregator::Max => AggregateValue::Max(decode_i64(state)?),
+            Aggregator::HllppUniqueCount => AggregateValue::HllppUniqueCount(state.to_vec()),
+        })
+    }
+
+    /// Encodes the state as accepted by `MergeToCell`.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            AggregateValue::Sum(v) | AggregateValue::Min(v) | AggregateValue::Max(v) => {
+                encode_i64(*v)
+            }
+            AggregateValue::HllppUniqueCount(state) => state.clone(),
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            AggregateValue::Sum(v) | AggregateValue::Min(v) | AggregateValue::Max(v) => Some(*v),
+            AggregateValue::HllppUniqueCount(_) => None,
+        }
+    }
+}
+
+/// `AddToCell` mutation feeding an Int64 input into a Sum/Min/Max (or HLL++) family.
+///
+/// Inputs sharing `timestamp_micros` are combined into a single cell; use a
+/// fixed timestamp (e.g. `0`) for a plain counter or a bucket start for time series.
+pub fn add_to_cell(family: &str, qualifier: &[u8], timestamp_micros: i64, input: i64) -> Mutation {
+    let mut add = mutation::AddToCell::new();
+    add.family_name = String::from(family);
+    add.column_qualifier = Some(raw_value(qualifier)).into();
+    add.timestamp = Some(raw_timestamp(timestamp_micros)).into();
+    add.input = Some(int_value(input)).into();
+
+    let mut m = Mutation::new();
+    m.mutation = Some(mutation::Mutation::AddToCell(add));
+    m
+}
+
+/// `MergeToCell` mutation merging previously read aggregate state into a cell.
+pub fn merge_to_cell(
+    family: &str,
+    qualifier: &[u8],
+    timestamp_micros: i64,
+    state: &AggregateValue,
+) -> Mutation {
+    let mut merge = mutation::MergeToCell::new();
+    merge.family_name = String::from(family);
+    merge.column_qualifier = Some(raw_value(qualifier)).into();
+    merge.timestamp = Some(raw_timestamp(timestamp_micros)).into();
+    merge.input = Some(raw_value(&state.encode())).into();
+
+    let mut m = Mutation::new();
+    m.mutation = Some(mutation::Mutation::MergeToCell(merge));
+    m
+}
+
+fn raw_value(bytes: &[u8]) -> Value {
+    let mut v = Value::new();
+    v.kind = Some(value::Kind::RawValue(bytes.to_vec()));
+    v
+}
+
+fn raw_timestamp(micros: i64) -> Value {
+    let mut v = Value::new();
+    v.kind = Some(value::Kind::RawTimestampMicros(micros));
+    v
+}
+
+fn int_value(i: i64) -> Value {
+    let mut v = Value::new();
+    v.kind = Some(value::Kind::IntValue(i));
+    v
+}
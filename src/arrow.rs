@@ -0,0 +1,351 @@
+// AIDEV-NOTE: Arrow output, behind the `arrow` feature. Query results keep their
+// SQL types (ProtoSchema column types map onto Arrow types, nested ones
+// included). ReadRows rows have no schema, so they come either long, one Arrow
+// row per cell, or wide, one Arrow row per Bigtable row with a column per
+// family:qualifier holding the newest cell's value.
+use crate::convert::{check_value, type_name};
+use crate::error::BTErr;
+use crate::protos::data::{value, ColumnMetadata, Row, Value};
+use crate::protos::date;
+use crate::protos::types::{type_, Type};
+use crate::query::ResultSet;
+use arrow_array::builder::{BinaryBuilder, StringBuilder, TimestampMicrosecondBuilder};
+use arrow_array::{
+    ArrayRef, BinaryArray, BooleanArray, Date32Array, Float32Array, Float64Array, Int64Array,
+    ListArray, MapArray, RecordBatch, StringArray, StructArray, TimestampMicrosecondArray,
+};
+use arrow_buffer::{NullBuffer, OffsetBuffer};
+use arrow_schema::{ArrowError, DataType, Field, Fields, Schema, TimeUnit};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// Arrow type of a Bigtable SQL type.
+pub fn arrow_type(ty: &Type) -> Result<DataType, BTErr> {
+    use type_::Kind as T;
+    Ok(match &ty.kind {
+        Some(T::BytesType(_)) => DataType::Binary,
+        Some(T::StringType(_)) => DataType::Utf8,
+        Some(T::Int64Type(_)) => DataType::Int64,
+        Some(T::Float32Type(_)) => DataType::Float32,
+        Some(T::Float64Type(_)) => DataType::Float64,
+        Some(T::BoolType(_)) => DataType::Boolean,
+        Some(T::TimestampType(_)) => timestamp_type(),
+        Some(T::DateType(_)) => DataType::Date32,
+        Some(T::AggregateType(agg)) => arrow_type(&agg.state_type)?,
+        Some(T::ArrayType(array)) => DataType::List(Arc::new(Field::new(
+            "item",
+            arrow_type(&array.element_type)?,
+            true,
+        ))),
+        Some(T::StructType(st)) => DataType::Struct(struct_fields(st)?),
+        Some(T::MapType(map)) => DataType::Map(map_entries_field(map)?, false),
+        _ => {
+            return Err(BTErr::TypeErr(format!(
+                "{} has no Arrow type",
+                type_name(ty)
+            )))
+        }
+    })
+}
+
+/// Arrow schema of a query result.
+pub fn arrow_schema(columns: &[ColumnMetadata]) -> Result<Schema, BTErr> {
+    let fields = columns
+        .iter()
+        .map(|c| Ok(Field::new(c.name.as_str(), arrow_type(&c.type_)?, true)))
+        .collect::<Result<Vec<_>, BTErr>>()?;
+    Ok(Schema::new(fields))
+}
+
+impl ResultSet {
+    /// Converts the result into a single `RecordBatch`.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, BTErr> {
+        let schema = Arc::new(arrow_schema(&self.columns)?);
+        let mut arrays = Vec::with_capacity(self.columns.len());
+        for (i, column) in self.columns.iter().enumerate() {
+            let mut values = Vec::with_capacity(self.rows.len());
+            for row in &self.rows {
+                let value = row.values.get(i).ok_or_else(|| {
+                    BTErr::TypeErr(format!("row has no value for column {}", column.name))
+                })?;
+                check_value(value, &column.type_)?;
+                values.push(value);
+            }
+            arrays.push(build_array(&column.type_, &values)?);
+        }
+        RecordBatch::try_new(schema, arrays).map_err(arrow_err)
+    }
+}
+
+/// Schema of `long_record_batch`.
+pub fn long_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("row_key", DataType::Binary, false),
+        Field::new("family", DataType::Utf8, false),
+        Field::new("qualifier", DataType::Binary, false),
+        Field::new("timestamp", timestamp_type(), false),
+        Field::new("value", DataType::Binary, false),
+    ])
+}
+
+/// One Arrow row per cell: `(row_key, family, qualifier, timestamp, value)`.
+pub fn long_record_batch(rows: &[Row]) -> Result<RecordBatch, BTErr> {
+    let mut keys = BinaryBuilder::new();
+    let mut families = StringBuilder::new();
+    let mut qualifiers = BinaryBuilder::new();
+    let mut timestamps = TimestampMicrosecondBuilder::new().with_timezone("UTC");
+    let mut values = BinaryBuilder::new();
+    for row in rows {
+        for family in &row.families {
+            for column in &family.columns {
+                for cell in &column.cells {
+                    keys.append_value(&row.key);
+                    families.append_value(&family.name);
+                    qualifiers.append_value(&column.qualifier);
+                    timestamps.append_value(cell.timestamp_micros);
+                    values.append_value(&cell.value);
+                }
+            }
+        }
+    }
+    let arrays: Vec<ArrayRef> = vec![
+        Arc::new(keys.finish()),
+        Arc::new(families.finish()),
+        Arc::new(qualifiers.finish()),
+        Arc::new(timestamps.finish()),
+        Arc::new(values.finish()),
+    ];
+    RecordBatch::try_new(Arc::new(long_schema()), arrays).map_err(arrow_err)
+}
+
+/// One Arrow row per Bigtable row: `row_key`, then a `family:qualifier` column
+/// per column present in `rows` (sorted), holding the newest cell's value or
+/// null. Qualifiers that are not UTF-8 are named lossily.
+pub fn wide_record_batch(rows: &[Row]) -> Result<RecordBatch, BTErr> {
+    let mut columns = BTreeSet::new();
+    for row in rows {
+        for family in &row.families {
+            for column in &family.columns {
+                columns.insert((family.name.as_str(), column.qualifier.as_slice()));
+            }
+        }
+    }
+
+    let mut fields = vec![Field::new("row_key", DataType::Binary, false)];
+    let mut arrays: Vec<ArrayRef> = vec![Arc::new(BinaryArray::from_iter_values(
+        rows.iter().map(|r| &r.key),
+    ))];
+    for (family, qualifier) in columns {
+        let name = format!("{}:{}", family, String::from_utf8_lossy(qualifier));
+        fields.push(Field::new(name, DataType::Binary, true));
+        let values: BinaryArray = rows
+            .iter()
+            .map(|row| newest_value(row, family, qualifier))
+            .collect();
+        arrays.push(Arc::new(values));
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).map_err(arrow_err)
+}
+
+fn newest_value<'a>(row: &'a Row, family: &str, qualifier: &[u8]) -> Option<&'a [u8]> {
+    row.families
+        .iter()
+        .filter(|f| f.name == family)
+        .flat_map(|f| f.columns.iter())
+        .filter(|c| c.qualifier == qualifier)
+        .flat_map(|c| c.cells.iter())
+        .max_by_key(|c| c.timestamp_micros)
+        .map(|c| c.value.as_slice())
+}
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Microsecond, Some(Arc::from("UTC")))
+}
+
+fn struct_fields(st: &type_::Struct) -> Result<Fields, BTErr> {
+    st.fields
+        .iter()
+        .map(|f| {
+            Ok(Field::new(
+                f.field_name.as_str(),
+                arrow_type(&f.type_)?,
+                true,
+            ))
+        })
+        .collect::<Result<Vec<_>, BTErr>>()
+        .map(Fields::from)
+}
+
+fn map_entries_field(map: &type_::Map) -> Result<Arc<Field>, BTErr> {
+    let entries = Fields::from(vec![
+        Field::new("key", arrow_type(&map.key_type)?, false),
+        Field::new("value", arrow_type(&map.value_type)?, true),
+    ]);
+    Ok(Arc::new(Field::new(
+        "entries",
+        DataType::Struct(entries),
+        false,
+    )))
+}
+
+fn arrow_err(e: ArrowError) -> BTErr {
+    BTErr::DecodeErr(format!("cannot build Arrow batch: {}", e))
+}
+
+fn validity(values: &[&Value]) -> Option<NullBuffer> {
+    let valid: Vec<bool> = values.iter().map(|v| v.kind.is_some()).collect();
+    if valid.iter().all(|v| *v) {
+        None
+    } else {
+        Some(NullBuffer::from(valid))
+    }
+}
+
+// Values have been checked against `ty`, so kinds other than the expected one
+// are NULLs.
+fn build_array(ty: &Type, values: &[&Value]) -> Result<ArrayRef, BTErr> {
+    use type_::Kind as T;
+    use value::Kind as V;
+    macro_rules! scalars {
+        ($array: ty, $kind: pat => $v: expr) => {
+            Arc::new(
+                values
+                    .iter()
+                    .map(|v| match &v.kind {
+                        Some($kind) => Some($v),
+                        _ => None,
+                    })
+                    .collect::<$array>(),
+            )
+        };
+    }
+
+    Ok(match &ty.kind {
+        Some(T::BytesType(_)) => scalars!(BinaryArray, V::BytesValue(b) => b.as_slice()),
+        Some(T::StringType(_)) => scalars!(StringArray, V::StringValue(s) => s.as_str()),
+        Some(T::Int64Type(_)) => scalars!(Int64Array, V::IntValue(i) => *i),
+        Some(T::Float32Type(_)) => scalars!(Float32Array, V::FloatValue(f) => *f as f32),
+        Some(T::Float64Type(_)) => scalars!(Float64Array, V::FloatValue(f) => *f),
+        Some(T::BoolType(_)) => scalars!(BooleanArray, V::BoolValue(b) => *b),
+        Some(T::TimestampType(_)) => Arc::new(
+            values
+                .iter()
+                .map(|v| match &v.kind {
+                    Some(V::TimestampValue(ts)) => {
+                        Some(ts.seconds * 1_000_000 + i64::from(ts.nanos / 1000))
+                    }
+                    _ => None,
+                })
+                .collect::<TimestampMicrosecondArray>()
+                .with_timezone("UTC"),
+        ),
+        Some(T::DateType(_)) => scalars!(Date32Array, V::DateValue(d) => days_from_epoch(d)),
+        Some(T::AggregateType(agg)) => build_array(&agg.state_type, values)?,
+        Some(T::ArrayType(array)) => {
+            let (lengths, elements) = flatten(values);
+            let child = build_array(&array.element_type, &elements)?;
+            let field = Arc::new(Field::new("item", arrow_type(&array.element_type)?, true));
+            Arc::new(
+                ListArray::try_new(
+                    field,
+                    OffsetBuffer::from_lengths(lengths),
+                    child,
+                    validity(values),
+                )
+                .map_err(arrow_err)?,
+            )
+        }
+        Some(T::StructType(st)) => Arc::new(build_struct(st, values)?),
+        Some(T::MapType(map)) => {
+            let (lengths, entries) = flatten(values);
+            let mut keys = Vec::with_capacity(entries.len());
+            let mut items = Vec::with_capacity(entries.len());
+            for entry in &entries {
+                if let Some(V::ArrayValue(pair)) = &entry.kind {
+                    keys.push(&pair.values[0]);
+                    items.push(&pair.values[1]);
+                }
+            }
+            let field = map_entries_field(map)?;
+            let entry_fields = match field.data_type() {
+                DataType::Struct(fields) => fields.clone(),
+                _ => unreachable!(),
+            };
+            let entries = StructArray::try_new(
+                entry_fields,
+                vec![
+                    build_array(&map.key_type, &keys)?,
+                    build_array(&map.value_type, &items)?,
+                ],
+                None,
+            )
+            .map_err(arrow_err)?;
+            Arc::new(
+                MapArray::try_new(
+                    field,
+                    OffsetBuffer::from_lengths(lengths),
+                    entries,
+                    validity(values),
+                    false,
+                )
+                .map_err(arrow_err)?,
+            )
+        }
+        _ => {
+            return Err(BTErr::TypeErr(format!(
+                "{} has no Arrow type",
+                type_name(ty)
+            )))
+        }
+    })
+}
+
+// Lengths of the arrays in `values` (0 for NULL) and their elements in order.
+fn flatten<'a>(values: &[&'a Value]) -> (Vec<usize>, Vec<&'a Value>) {
+    let mut lengths = Vec::with_capacity(values.len());
+    let mut elements = Vec::new();
+    for v in values {
+        match &v.kind {
+            Some(value::Kind::ArrayValue(array)) => {
+                lengths.push(array.values.len());
+                elements.extend(array.values.iter());
+            }
+            _ => lengths.push(0),
+        }
+    }
+    (lengths, elements)
+}
+
+fn build_struct(st: &type_::Struct, values: &[&Value]) -> Result<StructArray, BTErr> {
+    let fields = struct_fields(st)?;
+    if fields.is_empty() {
+        return Ok(StructArray::new_empty_fields(
+            values.len(),
+            validity(values),
+        ));
+    }
+    let null = Value::new();
+    let mut children = Vec::with_capacity(st.fields.len());
+    for (i, field) in st.fields.iter().enumerate() {
+        let column: Vec<&Value> = values
+            .iter()
+            .map(|v| match &v.kind {
+                Some(value::Kind::ArrayValue(array)) => &array.values[i],
+                _ => &null,
+            })
+            .collect();
+        children.push(build_array(&field.type_, &column)?);
+    }
+    StructArray::try_new(fields, children, validity(values)).map_err(arrow_err)
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_epoch(d: &date::Date) -> i32 {
+    let y = if d.month <= 2 { d.year - 1 } else { d.year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (d.month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d.day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
@@ -0,0 +1,535 @@
+// AIDEV-NOTE: ReadChangeStream is a long-lived server stream, but REST responses
+// are only handed to us once complete. Each partition reader therefore polls in
+// windows: it sets `end_time` a `poll_window` ahead, reads what the window
+// produced, and resumes from the latest continuation token (or the start time if
+// none has been seen yet). Events lag by up to one window.
+//
+// Partitions end with a CloseStream naming the new partitions and the tokens to
+// resume them with. A new partition may be covered by tokens from several closed
+// partitions (a merge), so it is only opened once its tokens cover its range.
+use crate::error::{ApiError, BTErr};
+use crate::method::{BigTable, GenerateInitialChangeStreamPartitions, ReadChangeStream};
+use crate::protos::bigtable::read_change_stream_request::Start_from;
+use crate::protos::bigtable::read_change_stream_response::{CloseStream, Heartbeat, Stream_record};
+use crate::protos::bigtable::{
+    GenerateInitialChangeStreamPartitionsResponse, ReadChangeStreamResponse,
+};
+use crate::protos::data::{
+    row_range, StreamContinuationToken, StreamContinuationTokens, StreamPartition,
+};
+use crate::request::{parse_stream, BTRequest, CurlTransport, Transport};
+use crate::support::Table;
+use crate::utils::to_timestamp;
+use goauth::auth::Token;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, Scope};
+use std::time::{Duration, SystemTime};
+
+mod checkpoint;
+mod materialize;
+mod record;
+mod watermark;
+
+use self::checkpoint::Checkpointer;
+pub use self::checkpoint::{
+    CheckpointStore, FileCheckpointStore, MemoryCheckpointStore, TableCheckpointStore,
+};
+pub use self::materialize::{Materializer, MemoryRowStore, RowImage, RowStore};
+pub use self::record::{ChangeDecoder, ChangeKind, ChangeRecord};
+pub use self::watermark::WatermarkTracker;
+
+/// A message from one partition's stream, in the order it was received.
+#[derive(Clone, Debug)]
+pub enum ChangeEvent {
+    /// A complete change, reassembled from its `DataChange` messages.
+    Change(ChangeRecord),
+    Heartbeat(Heartbeat),
+    /// The partition ended and was replaced by `new_partitions`, which the
+    /// reader opens once their continuation tokens are in.
+    Closed(CloseStream),
+    /// The global low watermark advanced: no partition is expected to deliver
+    /// changes committed before this time. Sent after the event that moved it.
+    Watermark(SystemTime),
+}
+
+/// Reads a table's change stream, following partition splits and merges.
+///
+/// ```ignore
+/// use bigtable::changestream::{ChangeEvent, ChangeStreamReader};
+///
+/// ChangeStreamReader::new(&table, &token).run(|partition, event| {
+///     if let ChangeEvent::Change(change) = event {
+///         handle(partition, change);
+///     }
+///     true // keep reading
+/// })?;
+/// ```
+pub struct ChangeStreamReader<'a> {
+    table: Table,
+    token: &'a Token,
+    start_time: Option<SystemTime>,
+    end_time: Option<SystemTime>,
+    heartbeat_duration: Option<Duration>,
+    poll_window: Duration,
+    checkpoints: Option<&'a dyn CheckpointStore>,
+    checkpoint_interval: Duration,
+    transport: &'a dyn Transport,
+}
+
+enum Position {
+    Time(SystemTime),
+    Tokens(Vec<StreamContinuationToken>),
+}
+
+enum Msg {
+    Event(StreamPartition, ChangeEvent),
+    // The partition reached the reader's end time
+    Finished(StreamPartition),
+    Failed(BTErr),
+}
+
+impl<'a> ChangeStreamReader<'a> {
+    pub fn new(table: &Table, token: &'a Token) -> Self {
+        ChangeStreamReader {
+            table: table.clone(),
+            token,
+            start_time: None,
+            end_time: None,
+            heartbeat_duration: None,
+            poll_window: Duration::from_secs(10),
+            checkpoints: None,
+            checkpoint_interval: Duration::from_secs(10),
+            transport: &CurlTransport,
+        }
+    }
+
+    /// Where to start reading (default: when `run` is called). Must be within
+    /// the table's change stream retention period.
+    pub fn start_time(mut self, start_time: SystemTime) -> Self {
+        self.start_time = Some(start_time);
+        self
+    }
+
+    /// Stops each partition once it has read past `end_time`; `run` returns when
+    /// all of them have. Without it the reader follows the stream indefinitely.
+    pub fn end_time(mut self, end_time: SystemTime) -> Self {
+        self.end_time = Some(end_time);
+        self
+    }
+
+    /// Interval between `Heartbeat` messages (server default 5s). Heartbeats
+    /// carry checkpoints and watermarks, so shorter intervals let them advance
+    /// on quiet partitions.
+    pub fn heartbeat_duration(mut self, heartbeat_duration: Duration) -> Self {
+        self.heartbeat_duration = Some(heartbeat_duration);
+        self
+    }
+
+    /// How far ahead each `ReadChangeStream` request reads (default 10s).
+    pub fn poll_window(mut self, poll_window: Duration) -> Self {
+        self.poll_window = poll_window;
+        self
+    }
+
+    /// Commits progress to `store` and, if it holds checkpoints, resumes from
+    /// them instead of `start_time`. Progress is committed after the callback
+    /// returns, so delivery is at-least-once.
+    pub fn checkpoints(mut self, store: &'a dyn CheckpointStore) -> Self {
+        self.checkpoints = Some(store);
+        self
+    }
+
+    /// How often tokens are committed to the checkpoint store (default 10s).
+    /// Partition splits and merges are always committed right away.
+    pub fn checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.checkpoint_interval = interval;
+        self
+    }
+
+    pub fn transport(mut self, transport: &'a dyn Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// The table's current partitions, from `GenerateInitialChangeStreamPartitions`.
+    pub fn partitions(&self) -> Result<Vec<StreamPartition>, BTErr> {
+        let req = BTRequest {
+            base: None,
+            table: self.table.clone(),
+            method: GenerateInitialChangeStreamPartitions::new(),
+        };
+        let responses = parse_stream::<GenerateInitialChangeStreamPartitionsResponse>(
+            &req.execute_with(self.token, self.transport)?,
+        )?;
+        Ok(responses
+            .into_iter()
+            .filter_map(|r| r.partition.into_option())
+            .collect())
+    }
+
+    /// Reads every partition on its own thread and calls `f` with each event on
+    /// the calling thread. Events of one partition arrive in stream order.
+    /// Returns when `f` returns `false`, every partition has reached `end_time`,
+    /// or a request fails.
+    pub fn run<F>(&self, mut f: F) -> Result<(), BTErr>
+    where
+        F: FnMut(&StreamPartition, ChangeEvent) -> bool,
+    {
+        let saved = match self.checkpoints {
+            Some(store) => store.load()?,
+            None => Vec::new(),
+        };
+        let initial: Vec<(StreamPartition, Position)> = if saved.is_empty() {
+            let start = self.start_time.unwrap_or_else(SystemTime::now);
+            self.partitions()?
+                .into_iter()
+                .map(|p| (p, Position::Time(start)))
+                .collect()
+        } else {
+            saved
+                .into_iter()
+                .filter_map(|t| {
+                    Some((
+                        t.partition.clone().into_option()?,
+                        Position::Tokens(vec![t]),
+                    ))
+                })
+                .collect()
+        };
+        let mut checkpointer = Checkpointer::new(self.checkpoints, self.checkpoint_interval);
+        let mut watermarks = WatermarkTracker::new();
+        let mut emitted: Option<SystemTime> = None;
+        let stop = AtomicBool::new(false);
+        let (tx, rx) = mpsc::channel();
+
+        let result = thread::scope(|s| {
+            let mut active = 0;
+            for (partition, from) in initial {
+                // Nothing committed before the start time will be delivered
+                let initial = match &from {
+                    Position::Time(t) => Some(*t),
+                    Position::Tokens(_) => None,
+                };
+                watermarks.add(&partition, initial);
+                self.spawn_reader(s, &stop, tx.clone(), partition, from);
+                active += 1;
+            }
+
+            let mut pending = PendingPartitions::default();
+            let result = loop {
+                if active == 0 {
+                    break Ok(());
+                }
+                let (partition, event) = match rx.recv() {
+                    Ok(Msg::Event(partition, event)) => (partition, event),
+                    Ok(Msg::Finished(partition)) => {
+                        watermarks.remove(&partition);
+                        active -= 1;
+                        continue;
+                    }
+                    Ok(Msg::Failed(e)) => break Err(e),
+                    Err(_) => break Ok(()),
+                };
+                let (token, closed) = match &event {
+                    ChangeEvent::Change(record) if !record.token.is_empty() => {
+                        let mut token = StreamContinuationToken::new();
+                        token.partition = Some(partition.clone()).into();
+                        token.token = record.token.clone();
+                        (Some(token), None)
+                    }
+                    ChangeEvent::Heartbeat(heartbeat) => {
+                        (heartbeat.continuation_token.clone().into_option(), None)
+                    }
+                    ChangeEvent::Closed(close) => (None, Some(close.clone())),
+                    _ => (None, None),
+                };
+                watermarks.observe(&partition, &event);
+                let mut more = f(&partition, event);
+                if let Some(watermark) = watermarks.watermark() {
+                    if more && emitted.is_none_or(|e| watermark > e) {
+                        emitted = Some(watermark);
+                        more = f(&partition, ChangeEvent::Watermark(watermark));
+                    }
+                }
+                if let Err(e) = checkpointer.handled(&partition, token, closed.as_ref()) {
+                    break Err(e);
+                }
+                if !more {
+                    break Ok(());
+                }
+                if let Some(close) = closed {
+                    active -= 1;
+                    for (partition, tokens) in pending.close(close) {
+                        checkpointer.opened(&partition, &tokens);
+                        self.spawn_reader(
+                            s,
+                            &stop,
+                            tx.clone(),
+                            partition,
+                            Position::Tokens(tokens),
+                        );
+                        active += 1;
+                    }
+                }
+            };
+            stop.store(true, Ordering::Relaxed);
+            result
+        });
+        let flushed = checkpointer.flush();
+        result.and(flushed)
+    }
+
+    /// Reads every change committed between `from` and `to` (inclusive) and
+    /// returns them ordered by commit time. Changes to the same row keep their
+    /// stream order.
+    pub fn replay(self, from: SystemTime, to: SystemTime) -> Result<Vec<ChangeRecord>, BTErr> {
+        let mut records = Vec::new();
+        self.start_time(from).end_time(to).run(|_, event| {
+            if let ChangeEvent::Change(record) = event {
+                records.push(record);
+            }
+            true
+        })?;
+        records.sort_by_key(|r| r.commit_timestamp);
+        Ok(records)
+    }
+
+    fn spawn_reader<'scope>(
+        &'scope self,
+        s: &'scope Scope<'scope, '_>,
+        stop: &'scope AtomicBool,
+        tx: Sender<Msg>,
+        partition: StreamPartition,
+        from: Position,
+    ) {
+        s.spawn(move || {
+            if let Err(e) = self.read_partition(&partition, from, stop, &tx) {
+                let _ = tx.send(Msg::Failed(e));
+            }
+        });
+    }
+
+    fn read_partition(
+        &self,
+        partition: &StreamPartition,
+        mut position: Position,
+        stop: &AtomicBool,
+        tx: &Sender<Msg>,
+    ) -> Result<(), BTErr> {
+        let send = |event| tx.send(Msg::Event(partition.clone(), event)).is_ok();
+
+        while !stop.load(Ordering::Relaxed) {
+            let mut req = BTRequest {
+                base: None,
+                table: self.table.clone(),
+                method: ReadChangeStream::new(),
+            };
+            let payload = req.method.payload_mut();
+            payload.partition = Some(partition.clone()).into();
+            payload.start_from = Some(match &position {
+                Position::Time(t) => Start_from::StartTime(to_timestamp(*t)),
+                Position::Tokens(tokens) => {
+                    let mut t = StreamContinuationTokens::new();
+                    t.tokens = tokens.clone();
+                    Start_from::ContinuationTokens(t)
+                }
+            });
+            let window_end = SystemTime::now() + self.poll_window;
+            let last_window = self.end_time.is_some_and(|end| end <= window_end);
+            let end = match self.end_time {
+                Some(end) if last_window => end,
+                _ => window_end,
+            };
+            payload.end_time = Some(to_timestamp(end)).into();
+            if let Some(heartbeat) = self.heartbeat_duration {
+                let mut d = protobuf::well_known_types::duration::Duration::new();
+                d.seconds = heartbeat.as_secs() as i64;
+                d.nanos = heartbeat.subsec_nanos() as i32;
+                payload.heartbeat_duration = Some(d).into();
+            }
+
+            let responses = parse_stream::<ReadChangeStreamResponse>(
+                &req.execute_with(self.token, self.transport)?,
+            )?;
+            // A change cut off by the end of the window is re-read in full from
+            // the last token, so partial changes are not carried over.
+            let mut decoder = ChangeDecoder::new();
+            for response in responses {
+                let event = match response.stream_record {
+                    Some(Stream_record::DataChange(change)) => {
+                        let record = match decoder.push(change)? {
+                            Some(record) => record,
+                            None => continue,
+                        };
+                        if !record.token.is_empty() {
+                            let mut token = StreamContinuationToken::new();
+                            token.partition = Some(partition.clone()).into();
+                            token.token = record.token.clone();
+                            position = Position::Tokens(vec![token]);
+                        }
+                        ChangeEvent::Change(record)
+                    }
+                    Some(Stream_record::Heartbeat(heartbeat)) => {
+                        if let Some(token) = heartbeat.continuation_token.as_ref() {
+                            position = Position::Tokens(vec![token.clone()]);
+                        }
+                        ChangeEvent::Heartbeat(heartbeat)
+                    }
+                    Some(Stream_record::CloseStream(close)) => {
+                        // Splits and merges may come with a non-OK status such as
+                        // OUT_OF_RANGE; only a close with nowhere to continue fails.
+                        let failed = close
+                            .status
+                            .as_ref()
+                            .filter(|s| s.code != 0 && close.continuation_tokens.is_empty());
+                        if let Some(status) = failed {
+                            return Err(BTErr::ApiErr(ApiError {
+                                code: status.code as i64,
+                                message: status.message.clone(),
+                                status: String::from("CLOSE_STREAM"),
+                                details: Vec::new(),
+                            }));
+                        }
+                        if close.new_partitions.is_empty() && close.continuation_tokens.is_empty() {
+                            // End of this poll window
+                            continue;
+                        }
+                        send(ChangeEvent::Closed(close));
+                        return Ok(());
+                    }
+                    _ => continue,
+                };
+                if !send(event) {
+                    return Ok(());
+                }
+            }
+            if last_window {
+                let _ = tx.send(Msg::Finished(partition.clone()));
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the changes committed to `table` between `from` and `to`, e.g. to
+/// backfill a derived table. See `ChangeStreamReader::replay`.
+///
+/// ```ignore
+/// let day = Duration::from_secs(86_400);
+/// let changes = bt::changestream::replay(&table, &token, SystemTime::now() - day, SystemTime::now())?;
+/// ```
+pub fn replay(
+    table: &Table,
+    token: &Token,
+    from: SystemTime,
+    to: SystemTime,
+) -> Result<Vec<ChangeRecord>, BTErr> {
+    ChangeStreamReader::new(table, token).replay(from, to)
+}
+
+/// New partitions waiting for continuation tokens that cover them. Each token
+/// is kept with the new partition it was issued for, so a split child's token
+/// never opens a merged partition that happens to contain it.
+#[derive(Default)]
+struct PendingPartitions {
+    partitions: Vec<StreamPartition>,
+    tokens: Vec<(KeyRange, StreamContinuationToken)>,
+}
+
+/// A partition's `[start, end)` row keys; an empty end is unbounded.
+type KeyRange = (Vec<u8>, Vec<u8>);
+
+impl PendingPartitions {
+    /// Records a closed stream, returning the partitions that can now be opened.
+    fn close(
+        &mut self,
+        close: CloseStream,
+    ) -> Vec<(StreamPartition, Vec<StreamContinuationToken>)> {
+        for token in close.continuation_tokens {
+            let (s, e) = token_range(&token);
+            let target = close
+                .new_partitions
+                .iter()
+                .map(range)
+                .find(|(start, end)| {
+                    s >= *start && (end.is_empty() || (!e.is_empty() && e <= *end))
+                })
+                .unwrap_or((s, e));
+            self.tokens.push((target, token));
+        }
+        for partition in close.new_partitions {
+            if !self
+                .partitions
+                .iter()
+                .any(|p| range(p) == range(&partition))
+            {
+                self.partitions.push(partition);
+            }
+        }
+
+        let mut ready = Vec::new();
+        let mut i = 0;
+        while i < self.partitions.len() {
+            match self.take_cover(&self.partitions[i].clone()) {
+                Some(tokens) => ready.push((self.partitions.remove(i), tokens)),
+                None => i += 1,
+            }
+        }
+        ready
+    }
+
+    /// Removes and returns tokens that exactly tile `partition`, if there are any.
+    fn take_cover(&mut self, partition: &StreamPartition) -> Option<Vec<StreamContinuationToken>> {
+        let (start, end) = range(partition);
+        let mut inside: Vec<usize> = (0..self.tokens.len())
+            .filter(|&i| self.tokens[i].0 == (start.clone(), end.clone()))
+            .collect();
+        inside.sort_by_key(|&i| token_range(&self.tokens[i].1).0);
+
+        let mut at = start;
+        for &i in &inside {
+            let (s, e) = token_range(&self.tokens[i].1);
+            if s != at {
+                return None;
+            }
+            at = e;
+            if at.is_empty() {
+                break;
+            }
+        }
+        if at != end || inside.is_empty() {
+            return None;
+        }
+
+        inside.sort_unstable_by(|a, b| b.cmp(a));
+        let mut tokens: Vec<_> = inside
+            .into_iter()
+            .map(|i| self.tokens.remove(i).1)
+            .collect();
+        tokens.reverse();
+        Some(tokens)
+    }
+}
+
+// Partition ranges are [start_key_closed, end_key_open), an empty end being unbounded.
+fn range(partition: &StreamPartition) -> KeyRange {
+    let range = match partition.row_range.as_ref() {
+        Some(range) => range,
+        None => return (Vec::new(), Vec::new()),
+    };
+    let start = match &range.start_key {
+        Some(row_range::Start_key::StartKeyClosed(k)) => k.clone(),
+        _ => Vec::new(),
+    };
+    let end = match &range.end_key {
+        Some(row_range::End_key::EndKeyOpen(k)) => k.clone(),
+        _ => Vec::new(),
+    };
+    (start, end)
+}
+
+fn token_range(token: &StreamContinuationToken) -> KeyRange {
+    token.partition.as_ref().map(range).unwrap_or_default()
+}
@@ -0,0 +1,348 @@
+// AIDEV-NOTE: Checkpoints are continuation tokens keyed by the partition range
+// they belong to. When a partition closes, the tokens for its successors are
+// saved before its own checkpoint is removed, so a restart never loses track of
+// a range. A merged partition's first own token replaces its parents' entries.
+// A restart opens one reader per saved token; stale partitions are answered
+// with a CloseStream and re-split by the reader as usual.
+use super::range;
+use crate::error::BTErr;
+use crate::filters;
+use crate::protos::bigtable::read_change_stream_response::CloseStream;
+use crate::protos::data::{row_range, RowRange, RowSet, StreamContinuationToken, StreamPartition};
+use crate::request::{parse_stream, CurlTransport, Transport};
+use crate::rows::latest_cell;
+use crate::support::Table;
+use crate::wraps::{mutate_row_via, read_rows_via, RowMutation};
+use goauth::auth::Token;
+use protobuf::Message;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Persists change stream progress per partition.
+pub trait CheckpointStore: Send + Sync {
+    /// Saves `token`, replacing any checkpoint for the same partition.
+    fn save(&self, token: &StreamContinuationToken) -> Result<(), BTErr>;
+    /// Forgets the checkpoint of a partition that has ended.
+    fn remove(&self, partition: &StreamPartition) -> Result<(), BTErr>;
+    /// All saved checkpoints.
+    fn load(&self) -> Result<Vec<StreamContinuationToken>, BTErr>;
+}
+
+type Tokens = BTreeMap<(Vec<u8>, Vec<u8>), StreamContinuationToken>;
+
+fn key(token: &StreamContinuationToken) -> (Vec<u8>, Vec<u8>) {
+    token.partition.as_ref().map(range).unwrap_or_default()
+}
+
+/// Keeps checkpoints in memory, e.g. for tests or short-lived readers.
+#[derive(Default)]
+pub struct MemoryCheckpointStore {
+    tokens: Mutex<Tokens>,
+}
+
+impl MemoryCheckpointStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl CheckpointStore for MemoryCheckpointStore {
+    fn save(&self, token: &StreamContinuationToken) -> Result<(), BTErr> {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.insert(key(token), token.clone());
+        Ok(())
+    }
+
+    fn remove(&self, partition: &StreamPartition) -> Result<(), BTErr> {
+        self.tokens.lock().unwrap().remove(&range(partition));
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<StreamContinuationToken>, BTErr> {
+        Ok(self.tokens.lock().unwrap().values().cloned().collect())
+    }
+}
+
+/// Keeps checkpoints in a local JSON file, rewritten (via a temporary file and
+/// a rename) on every change.
+pub struct FileCheckpointStore {
+    path: PathBuf,
+    tokens: Mutex<Tokens>,
+}
+
+impl FileCheckpointStore {
+    /// Opens `path`, starting empty if it does not exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BTErr> {
+        let path = path.as_ref().to_path_buf();
+        let mut tokens = Tokens::new();
+        if path.exists() {
+            let json: serde_json::Value = serde_json::from_slice(&fs::read(&path)?)?;
+            for token in parse_stream::<StreamContinuationToken>(&json)? {
+                tokens.insert(key(&token), token);
+            }
+        }
+        Ok(FileCheckpointStore {
+            path,
+            tokens: Mutex::new(tokens),
+        })
+    }
+
+    fn write(&self, tokens: &Tokens) -> Result<(), BTErr> {
+        let mut json = Vec::new();
+        for token in tokens.values() {
+            json.push(protobuf_json_mapping::print_to_string(token)?);
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, format!("[{}]", json.join(",")))?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    fn save(&self, token: &StreamContinuationToken) -> Result<(), BTErr> {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.insert(key(token), token.clone());
+        self.write(&tokens)
+    }
+
+    fn remove(&self, partition: &StreamPartition) -> Result<(), BTErr> {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.remove(&range(partition)).is_some() {
+            self.write(&tokens)?;
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<StreamContinuationToken>, BTErr> {
+        Ok(self.tokens.lock().unwrap().values().cloned().collect())
+    }
+}
+
+/// Keeps checkpoints in a Bigtable table, one row per partition under `prefix`,
+/// so readers on other hosts can take over.
+///
+/// ```ignore
+/// let store = TableCheckpointStore::new(&checkpoint_table, &token, "orders-cdc/");
+/// ChangeStreamReader::new(&table, &token).checkpoints(&store).run(handle)?;
+/// ```
+pub struct TableCheckpointStore<'a> {
+    table: Table,
+    token: &'a Token,
+    prefix: Vec<u8>,
+    family: String,
+    transport: &'a dyn Transport,
+}
+
+const TOKEN: &[u8] = b"token";
+
+impl<'a> TableCheckpointStore<'a> {
+    pub fn new(table: &Table, token: &'a Token, prefix: &str) -> Self {
+        TableCheckpointStore {
+            table: table.clone(),
+            token,
+            prefix: prefix.as_bytes().to_vec(),
+            family: String::from("checkpoint"),
+            transport: &CurlTransport,
+        }
+    }
+
+    /// Column family holding the checkpoint cells (default `checkpoint`).
+    pub fn family(mut self, family: &str) -> Self {
+        self.family = String::from(family);
+        self
+    }
+
+    pub fn transport(mut self, transport: &'a dyn Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    fn row_key(&self, partition: &StreamPartition) -> Vec<u8> {
+        let (start, end) = range(partition);
+        let mut key = self.prefix.clone();
+        key.extend_from_slice(hex(&start).as_bytes());
+        key.push(b'-');
+        key.extend_from_slice(hex(&end).as_bytes());
+        key
+    }
+}
+
+impl<'a> CheckpointStore for TableCheckpointStore<'a> {
+    fn save(&self, token: &StreamContinuationToken) -> Result<(), BTErr> {
+        let partition = token.partition.clone().unwrap_or_default();
+        let mutations = RowMutation::new()
+            .delete_cells(&self.family, TOKEN)
+            .set_cell(&self.family, TOKEN, &token.write_to_bytes()?);
+        mutate_row_via(
+            self.transport,
+            &self.table,
+            self.token,
+            &self.row_key(&partition),
+            mutations,
+        )
+    }
+
+    fn remove(&self, partition: &StreamPartition) -> Result<(), BTErr> {
+        mutate_row_via(
+            self.transport,
+            &self.table,
+            self.token,
+            &self.row_key(partition),
+            RowMutation::new().delete_row(),
+        )
+    }
+
+    fn load(&self) -> Result<Vec<StreamContinuationToken>, BTErr> {
+        let mut under_prefix = RowRange::new();
+        under_prefix.start_key = Some(row_range::Start_key::StartKeyClosed(self.prefix.clone()));
+        if let Some(end) = prefix_end(&self.prefix) {
+            under_prefix.end_key = Some(row_range::End_key::EndKeyOpen(end));
+        }
+        let mut rows = RowSet::new();
+        rows.row_ranges.push(under_prefix);
+
+        let filter = filters::chain(vec![
+            filters::column(&self.family, TOKEN),
+            filters::latest(),
+        ]);
+        let mut tokens = Vec::new();
+        for row in read_rows_via(
+            self.transport,
+            &self.table,
+            self.token,
+            rows,
+            Some(filter),
+            0,
+        )? {
+            if let Some(cell) = latest_cell(&row, &self.family, TOKEN) {
+                tokens.push(StreamContinuationToken::parse_from_bytes(&cell.value)?);
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Smallest key greater than every key starting with `prefix`.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Commits the reader's progress to a store: successors of a closed partition
+/// right away, other tokens at most once per `interval`.
+///
+/// A merged partition resumes from its parents' tokens, which stay saved under
+/// the parents' ranges until the merged partition commits a token of its own.
+/// That first token is saved right away and replaces the parents' checkpoints,
+/// so a restart never opens the parents and the merged partition together.
+pub(super) struct Checkpointer<'a> {
+    store: Option<&'a dyn CheckpointStore>,
+    interval: Duration,
+    dirty: Tokens,
+    last_commit: Instant,
+    // merged partition range -> parents whose checkpoints it replaces
+    merged: BTreeMap<(Vec<u8>, Vec<u8>), Vec<StreamPartition>>,
+}
+
+impl<'a> Checkpointer<'a> {
+    pub(super) fn new(store: Option<&'a dyn CheckpointStore>, interval: Duration) -> Self {
+        Checkpointer {
+            store,
+            interval,
+            dirty: Tokens::new(),
+            last_commit: Instant::now(),
+            merged: BTreeMap::new(),
+        }
+    }
+
+    /// Records that `partition` was opened from `tokens`, the checkpoints of the
+    /// partitions it follows.
+    pub(super) fn opened(
+        &mut self,
+        partition: &StreamPartition,
+        tokens: &[StreamContinuationToken],
+    ) {
+        let parents: Vec<StreamPartition> = tokens
+            .iter()
+            .filter(|t| key(t) != range(partition))
+            .filter_map(|t| t.partition.clone().into_option())
+            .collect();
+        if !parents.is_empty() {
+            self.merged.insert(range(partition), parents);
+        }
+    }
+
+    /// Records progress once the consumer has handled an event of `partition`.
+    pub(super) fn handled(
+        &mut self,
+        partition: &StreamPartition,
+        token: Option<StreamContinuationToken>,
+        closed: Option<&CloseStream>,
+    ) -> Result<(), BTErr> {
+        let store = match self.store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+        if let Some(token) = token {
+            match self.merged.remove(&key(&token)) {
+                Some(parents) => {
+                    store.save(&token)?;
+                    for parent in &parents {
+                        store.remove(parent)?;
+                    }
+                }
+                None => {
+                    self.dirty.insert(key(&token), token);
+                }
+            }
+        }
+        if let Some(close) = closed {
+            self.dirty.remove(&range(partition));
+            for token in &close.continuation_tokens {
+                store.save(token)?;
+            }
+            // A merge hands on a token for the closed range itself, and a
+            // merged partition may split back into its parents' ranges.
+            let mut ended = self.merged.remove(&range(partition)).unwrap_or_default();
+            ended.push(partition.clone());
+            for ended in ended {
+                if !close
+                    .continuation_tokens
+                    .iter()
+                    .any(|t| key(t) == range(&ended))
+                {
+                    store.remove(&ended)?;
+                }
+            }
+        }
+        if self.last_commit.elapsed() >= self.interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn flush(&mut self) -> Result<(), BTErr> {
+        if let Some(store) = self.store {
+            for token in self.dirty.values() {
+                store.save(token)?;
+            }
+        }
+        self.dirty.clear();
+        self.last_commit = Instant::now();
+        Ok(())
+    }
+}
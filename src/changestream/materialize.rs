@@ -0,0 +1,329 @@
+// AIDEV-NOTE: The materializer replays change records onto a local copy of the
+// rows, the way the server applied them: cells are keyed by (family, qualifier,
+// timestamp), a SetCell with the same key replaces the cell, deletes drop cells,
+// and rows left without cells disappear. Garbage collection records are applied
+// too, so the copy ages out like the table. Records of one row must be applied
+// in stream order, which the reader preserves per partition.
+use super::ChangeRecord;
+use crate::aggregate::Aggregator;
+use crate::error::BTErr;
+use crate::protos::data::{mutation, value, Cell, Column, Family, Mutation, Row, Value};
+use crate::utils::{decode_i64, encode_i64};
+use std::collections::btree_map::{self, BTreeMap};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where a `Materializer` keeps the current row images.
+pub trait RowStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Row>, BTErr>;
+    /// Stores `row` under `row.key`, replacing any previous image.
+    fn put(&mut self, row: Row) -> Result<(), BTErr>;
+    fn delete(&mut self, key: &[u8]) -> Result<(), BTErr>;
+}
+
+/// Keeps row images in memory, ordered by row key.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryRowStore {
+    rows: BTreeMap<Vec<u8>, Row>,
+}
+
+impl MemoryRowStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// All rows in key order.
+    pub fn rows(&self) -> btree_map::Values<'_, Vec<u8>, Row> {
+        self.rows.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+impl RowStore for MemoryRowStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Row>, BTErr> {
+        Ok(self.rows.get(key).cloned())
+    }
+
+    fn put(&mut self, row: Row) -> Result<(), BTErr> {
+        self.rows.insert(row.key.clone(), row);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), BTErr> {
+        self.rows.remove(key);
+        Ok(())
+    }
+}
+
+/// A row before and after one change; `None` when the row had, or has, no cells.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowImage {
+    pub row_key: Vec<u8>,
+    pub commit_timestamp: SystemTime,
+    pub before: Option<Row>,
+    pub after: Option<Row>,
+}
+
+/// Applies change records to a `RowStore`, yielding the row images they produce.
+///
+/// ```ignore
+/// let mut images = Materializer::new().aggregate("counters", Aggregator::Sum);
+/// ChangeStreamReader::new(&table, &token).run(|_, event| {
+///     if let ChangeEvent::Change(change) = event {
+///         let image = images.apply(&change).expect("apply");
+///         index.update(&image.row_key, image.after.as_ref());
+///     }
+///     true
+/// })?;
+/// ```
+pub struct Materializer<S = MemoryRowStore> {
+    store: S,
+    aggregators: HashMap<String, Aggregator>,
+}
+
+impl Materializer<MemoryRowStore> {
+    pub fn new() -> Self {
+        Materializer::with_store(MemoryRowStore::new())
+    }
+}
+
+impl Default for Materializer<MemoryRowStore> {
+    fn default() -> Self {
+        Materializer::new()
+    }
+}
+
+impl<S: RowStore> Materializer<S> {
+    pub fn with_store(store: S) -> Self {
+        Materializer {
+            store,
+            aggregators: HashMap::new(),
+        }
+    }
+
+    /// Declares `family` as an aggregate family, so `AddToCell` and
+    /// `MergeToCell` changes to it can be applied. Only Sum, Min and Max state
+    /// can be combined locally.
+    pub fn aggregate(mut self, family: &str, aggregator: Aggregator) -> Self {
+        self.aggregators.insert(String::from(family), aggregator);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Applies `change` to its row and returns the images around it.
+    pub fn apply(&mut self, change: &ChangeRecord) -> Result<RowImage, BTErr> {
+        let before = self.store.get(&change.row_key)?;
+        let mut cells = before.as_ref().map(Cells::from_row).unwrap_or_default();
+        let now = micros(change.commit_timestamp);
+        for m in &change.mutations {
+            self.apply_mutation(&mut cells, m, now)?;
+        }
+
+        let after = cells.into_row(&change.row_key);
+        match &after {
+            Some(row) => self.store.put(row.clone())?,
+            None if before.is_some() => self.store.delete(&change.row_key)?,
+            None => {}
+        }
+        Ok(RowImage {
+            row_key: change.row_key.clone(),
+            commit_timestamp: change.commit_timestamp,
+            before,
+            after,
+        })
+    }
+
+    fn apply_mutation(&self, cells: &mut Cells, m: &Mutation, now: i64) -> Result<(), BTErr> {
+        match &m.mutation {
+            Some(mutation::Mutation::SetCell(set)) => {
+                let ts = if set.timestamp_micros == -1 {
+                    now
+                } else {
+                    set.timestamp_micros
+                };
+                cells
+                    .column(&set.family_name, &set.column_qualifier)
+                    .insert(ts, set.value.clone());
+            }
+            Some(mutation::Mutation::AddToCell(add)) => {
+                let input = match add.input.as_ref().and_then(|v| v.kind.as_ref()) {
+                    Some(value::Kind::IntValue(i)) => *i,
+                    _ => return Err(invalid("AddToCell input is not an Int64")),
+                };
+                self.combine(
+                    cells,
+                    &add.family_name,
+                    &add.column_qualifier,
+                    &add.timestamp,
+                    input,
+                )?;
+            }
+            Some(mutation::Mutation::MergeToCell(merge)) => {
+                let input = match merge.input.as_ref().and_then(|v| v.kind.as_ref()) {
+                    Some(value::Kind::RawValue(state)) => decode_i64(state)?,
+                    _ => return Err(invalid("MergeToCell input is not raw aggregate state")),
+                };
+                self.combine(
+                    cells,
+                    &merge.family_name,
+                    &merge.column_qualifier,
+                    &merge.timestamp,
+                    input,
+                )?;
+            }
+            Some(mutation::Mutation::DeleteFromColumn(delete)) => {
+                let (start, end) = match delete.time_range.as_ref() {
+                    Some(range) => (range.start_timestamp_micros, range.end_timestamp_micros),
+                    None => (0, 0),
+                };
+                if let Some(column) = cells
+                    .0
+                    .get_mut(&delete.family_name)
+                    .and_then(|family| family.get_mut(&delete.column_qualifier))
+                {
+                    column.retain(|&ts, _| ts < start || (end != 0 && ts >= end));
+                }
+            }
+            Some(mutation::Mutation::DeleteFromFamily(delete)) => {
+                cells.0.remove(&delete.family_name);
+            }
+            Some(mutation::Mutation::DeleteFromRow(_)) => cells.0.clear(),
+            _ => return Err(invalid("unknown mutation")),
+        }
+        Ok(())
+    }
+
+    fn combine(
+        &self,
+        cells: &mut Cells,
+        family: &str,
+        qualifier: &protobuf::MessageField<Value>,
+        timestamp: &protobuf::MessageField<Value>,
+        input: i64,
+    ) -> Result<(), BTErr> {
+        let aggregator = match self.aggregators.get(family) {
+            Some(aggregator) => *aggregator,
+            None => {
+                return Err(invalid(&format!(
+                    "family {} is not declared as an aggregate",
+                    family
+                )))
+            }
+        };
+        let qualifier = match qualifier.as_ref().and_then(|v| v.kind.as_ref()) {
+            Some(value::Kind::RawValue(q)) => q,
+            _ => return Err(invalid("aggregate qualifier is not a raw value")),
+        };
+        let ts = match timestamp.as_ref().and_then(|v| v.kind.as_ref()) {
+            Some(value::Kind::RawTimestampMicros(ts)) => *ts,
+            _ => return Err(invalid("aggregate timestamp is not raw micros")),
+        };
+
+        let column = cells.column(family, qualifier);
+        let current = match column.get(&ts) {
+            Some(state) => Some(decode_i64(state)?),
+            None => None,
+        };
+        let combined = match (aggregator, current) {
+            (_, None) => input,
+            (Aggregator::Sum, Some(v)) => v.wrapping_add(input),
+            (Aggregator::Min, Some(v)) => v.min(input),
+            (Aggregator::Max, Some(v)) => v.max(input),
+            (Aggregator::HllppUniqueCount, Some(_)) => {
+                return Err(invalid("HLL++ state cannot be combined locally"))
+            }
+        };
+        column.insert(ts, encode_i64(combined));
+        Ok(())
+    }
+}
+
+// timestamp -> value
+type Versions = BTreeMap<i64, Vec<u8>>;
+
+// family -> qualifier -> versions
+#[derive(Default)]
+struct Cells(BTreeMap<String, BTreeMap<Vec<u8>, Versions>>);
+
+impl Cells {
+    fn from_row(row: &Row) -> Cells {
+        let mut cells = Cells::default();
+        for family in &row.families {
+            for column in &family.columns {
+                let target = cells.column(&family.name, &column.qualifier);
+                for cell in &column.cells {
+                    target.insert(cell.timestamp_micros, cell.value.clone());
+                }
+            }
+        }
+        cells
+    }
+
+    fn column(&mut self, family: &str, qualifier: &[u8]) -> &mut Versions {
+        self.0
+            .entry(String::from(family))
+            .or_default()
+            .entry(qualifier.to_vec())
+            .or_default()
+    }
+
+    // Families and columns sorted, newest cell first, empty ones pruned.
+    fn into_row(self, key: &[u8]) -> Option<Row> {
+        let mut row = Row::new();
+        row.key = key.to_vec();
+        for (name, columns) in self.0 {
+            let mut family = Family::new();
+            family.name = name;
+            for (qualifier, cells) in columns {
+                if cells.is_empty() {
+                    continue;
+                }
+                let mut column = Column::new();
+                column.qualifier = qualifier;
+                for (timestamp_micros, value) in cells.into_iter().rev() {
+                    let mut cell = Cell::new();
+                    cell.timestamp_micros = timestamp_micros;
+                    cell.value = value;
+                    column.cells.push(cell);
+                }
+                family.columns.push(column);
+            }
+            if !family.columns.is_empty() {
+                row.families.push(family);
+            }
+        }
+        if row.families.is_empty() {
+            None
+        } else {
+            Some(row)
+        }
+    }
+}
+
+fn micros(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+fn invalid(msg: &str) -> BTErr {
+    BTErr::DecodeErr(format!("cannot materialize change: {}", msg))
+}
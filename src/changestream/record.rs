@@ -0,0 +1,173 @@
+// AIDEV-NOTE: A logical DataChange may be split over several messages: the first
+// carries the row key, type and timestamps, the rest have type CONTINUATION and
+// only chunks, and the last has `done` set. Within them a large SetCell value may
+// itself be split into MutationChunks with ChunkInfo; those chunks are contiguous
+// and only the first carries the SetCell's family, qualifier and timestamp.
+use crate::error::BTErr;
+use crate::protos::bigtable::read_change_stream_response::mutation_chunk::ChunkInfo;
+use crate::protos::bigtable::read_change_stream_response::{data_change, DataChange};
+use crate::protos::data::{mutation, Mutation};
+use crate::utils::from_timestamp;
+use std::convert::TryFrom;
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    User,
+    GarbageCollection,
+}
+
+/// One complete change to a row, as committed on a cluster.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeRecord {
+    pub row_key: Vec<u8>,
+    pub commit_timestamp: SystemTime,
+    /// Breaks ties between changes to the same cell committed at the same time
+    /// on different clusters; the larger one wins.
+    pub tiebreaker: i32,
+    /// Empty for garbage collection.
+    pub source_cluster_id: String,
+    pub kind: ChangeKind,
+    pub mutations: Vec<Mutation>,
+    /// Position to resume the partition from after this change.
+    pub token: String,
+    pub low_watermark: Option<SystemTime>,
+}
+
+/// Stitches `DataChange` messages of one partition into `ChangeRecord`s.
+#[derive(Default)]
+pub struct ChangeDecoder {
+    record: Option<ChangeRecord>,
+    // SetCell being assembled from chunks, and its announced value size
+    chunked: Option<(mutation::SetCell, usize)>,
+}
+
+impl ChangeDecoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Feeds one message, returning the record it completes, if any.
+    pub fn push(&mut self, change: DataChange) -> Result<Option<ChangeRecord>, BTErr> {
+        let kind = match change.type_.enum_value() {
+            Ok(data_change::Type::CONTINUATION) => None,
+            Ok(data_change::Type::USER) => Some(ChangeKind::User),
+            Ok(data_change::Type::GARBAGE_COLLECTION) => Some(ChangeKind::GarbageCollection),
+            _ => return Err(invalid("unknown change type")),
+        };
+
+        match (kind, self.record.is_some()) {
+            (Some(_), true) => return Err(invalid("new change before the previous one was done")),
+            (None, false) => return Err(invalid("continuation without a change in progress")),
+            (Some(kind), false) => {
+                let commit_timestamp = change
+                    .commit_timestamp
+                    .as_ref()
+                    .map(from_timestamp)
+                    .ok_or_else(|| invalid("change is missing its commit timestamp"))?;
+                self.record = Some(ChangeRecord {
+                    row_key: change.row_key,
+                    commit_timestamp,
+                    tiebreaker: change.tiebreaker,
+                    source_cluster_id: change.source_cluster_id,
+                    kind,
+                    mutations: Vec::new(),
+                    token: String::new(),
+                    low_watermark: None,
+                });
+            }
+            (None, true) => {}
+        }
+
+        for chunk in change.chunks {
+            let m = chunk.mutation.into_option();
+            match chunk.chunk_info.into_option() {
+                Some(info) => self.push_chunk(m, &info)?,
+                None => {
+                    if self.chunked.is_some() {
+                        return Err(invalid("mutation interleaved with a chunked value"));
+                    }
+                    let m = m.ok_or_else(|| invalid("chunk is missing its mutation"))?;
+                    self.record.as_mut().unwrap().mutations.push(m);
+                }
+            }
+        }
+
+        let record = self.record.as_mut().unwrap();
+        if !change.token.is_empty() {
+            record.token = change.token;
+        }
+        if let Some(ts) = change.estimated_low_watermark.as_ref() {
+            record.low_watermark = Some(from_timestamp(ts));
+        }
+
+        if !change.done {
+            return Ok(None);
+        }
+        if self.chunked.is_some() {
+            return Err(invalid("change done in the middle of a chunked value"));
+        }
+        Ok(self.record.take())
+    }
+
+    /// Drops a partially received change, e.g. when its stream is cut off and
+    /// will be re-read from the last token.
+    pub fn reset(&mut self) {
+        self.record = None;
+        self.chunked = None;
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.record.is_some()
+    }
+
+    fn push_chunk(&mut self, m: Option<Mutation>, info: &ChunkInfo) -> Result<(), BTErr> {
+        let set_cell = match m.and_then(|m| m.mutation) {
+            Some(mutation::Mutation::SetCell(set_cell)) => set_cell,
+            _ => return Err(invalid("only SetCell values may be chunked")),
+        };
+        let size = usize::try_from(info.chunked_value_size)
+            .map_err(|_| invalid("negative chunked value size"))?;
+        let offset = usize::try_from(info.chunked_value_offset)
+            .map_err(|_| invalid("negative chunked value offset"))?;
+
+        match self.chunked.as_mut() {
+            None => {
+                if offset != 0 {
+                    return Err(invalid("chunked value does not start at offset 0"));
+                }
+                let mut first = set_cell;
+                first.value.reserve(size.saturating_sub(first.value.len()));
+                self.chunked = Some((first, size));
+            }
+            Some((partial, expected)) => {
+                if offset == 0 {
+                    return Err(invalid("new chunked value before the previous one ended"));
+                }
+                if offset != partial.value.len() || size != *expected {
+                    return Err(invalid("chunk offset or size does not match"));
+                }
+                partial.value.extend_from_slice(&set_cell.value);
+            }
+        }
+
+        let (partial, expected) = self.chunked.as_ref().unwrap();
+        if partial.value.len() > *expected {
+            return Err(invalid("chunked value exceeds its announced size"));
+        }
+        if info.last_chunk {
+            if partial.value.len() != *expected {
+                return Err(invalid("chunked value is shorter than its announced size"));
+            }
+            let (set_cell, _) = self.chunked.take().unwrap();
+            let mut m = Mutation::new();
+            m.mutation = Some(mutation::Mutation::SetCell(set_cell));
+            self.record.as_mut().unwrap().mutations.push(m);
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> BTErr {
+    BTErr::DecodeErr(format!("invalid DataChange: {}", msg))
+}
@@ -0,0 +1,78 @@
+// AIDEV-NOTE: The global watermark is the minimum over partitions that may still
+// deliver records. A closed partition's successors inherit its watermark (the
+// lowest parent's for a merge) until they report their own, so the watermark
+// never jumps ahead while a split or merge is being picked up.
+use super::{range, ChangeEvent};
+use crate::protos::bigtable::read_change_stream_response::CloseStream;
+use crate::protos::data::StreamPartition;
+use crate::utils::from_timestamp;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+/// Tracks the low watermark of every active partition.
+#[derive(Clone, Debug, Default)]
+pub struct WatermarkTracker {
+    partitions: BTreeMap<(Vec<u8>, Vec<u8>), Option<SystemTime>>,
+}
+
+impl WatermarkTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Starts tracking `partition`, with `initial` as its watermark if known.
+    pub fn add(&mut self, partition: &StreamPartition, initial: Option<SystemTime>) {
+        self.partitions.insert(range(partition), initial);
+    }
+
+    pub fn update(&mut self, partition: &StreamPartition, watermark: SystemTime) {
+        self.partitions.insert(range(partition), Some(watermark));
+    }
+
+    /// Stops tracking a partition that will not deliver further records.
+    pub fn remove(&mut self, partition: &StreamPartition) {
+        self.partitions.remove(&range(partition));
+    }
+
+    /// Replaces a closed partition with its successors.
+    pub fn close(&mut self, partition: &StreamPartition, close: &CloseStream) {
+        let inherited = self.partitions.remove(&range(partition)).flatten();
+        for successor in &close.new_partitions {
+            let entry = self.partitions.entry(range(successor)).or_insert(inherited);
+            *entry = match (*entry, inherited) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                _ => None,
+            };
+        }
+    }
+
+    /// Applies the watermark or partition change carried by `event`.
+    pub fn observe(&mut self, partition: &StreamPartition, event: &ChangeEvent) {
+        match event {
+            ChangeEvent::Change(record) => {
+                if let Some(watermark) = record.low_watermark {
+                    self.update(partition, watermark);
+                }
+            }
+            ChangeEvent::Heartbeat(heartbeat) => {
+                if let Some(ts) = heartbeat.estimated_low_watermark.as_ref() {
+                    self.update(partition, from_timestamp(ts));
+                }
+            }
+            ChangeEvent::Closed(close) => self.close(partition, close),
+            _ => {}
+        }
+    }
+
+    /// Time before which no further records are expected on any partition.
+    /// `None` while some partition has not reported a watermark yet, or when
+    /// there are no active partitions.
+    pub fn watermark(&self) -> Option<SystemTime> {
+        let mut min: Option<SystemTime> = None;
+        for watermark in self.partitions.values() {
+            let watermark = (*watermark)?;
+            min = Some(min.map_or(watermark, |m| m.min(watermark)));
+        }
+        min
+    }
+}
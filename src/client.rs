@@ -0,0 +1,383 @@
+// AIDEV-NOTE: BigtableClient holds what every call needs (endpoint, credentials,
+// app profile, retry policy, transport) so call sites stop building BTRequests.
+// It is cheap to clone; TableHandle owns a clone plus the table. Only idempotent
+// requests are retried: reads, and mutations whose cells all carry explicit
+// timestamps (a server-assigned timestamp would write a second version).
+// ReadModifyWriteRow and CheckAndMutateRow are never retried.
+use crate::error::{ApiError, BTErr};
+use crate::mapping::BigtableRow;
+use crate::method::{
+    BigTable, CheckAndMutateRow, MutateRow, MutateRows, ReadModifyWriteRow, ReadRows, SampleRowKeys,
+};
+use crate::protos::bigtable::{
+    mutate_rows_request, CheckAndMutateRowResponse, MutateRowResponse, MutateRowsResponse,
+    ReadModifyWriteRowResponse, ReadRowsResponse, SampleRowKeysResponse,
+};
+use crate::protos::data::{mutation, Mutation, Row, RowFilter, RowSet};
+use crate::query::Retries;
+use crate::request::{
+    check_response, parse_message, parse_stream, BTRequest, CurlTransport, Transport,
+};
+use crate::rows;
+use crate::support::{Instance, Table};
+use crate::utils::get_auth_token;
+use crate::wraps::{ModifiedRow, ReadModifyWrite, RowMutation};
+use goauth::auth::Token;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_ENDPOINT: &str = "https://bigtable.googleapis.com/v2";
+
+/// Supplies the access token for each request.
+pub trait TokenSource: Send + Sync {
+    fn token(&self) -> Result<Token, BTErr>;
+}
+
+/// A fixed token, e.g. one from `utils::get_auth_token`. It is not refreshed.
+impl TokenSource for Token {
+    fn token(&self) -> Result<Token, BTErr> {
+        Ok(self.clone())
+    }
+}
+
+/// Service account credentials, fetching a new token shortly before the
+/// current one expires.
+pub struct ServiceAccount {
+    credentials: String,
+    cached: Mutex<Option<(Token, Instant)>>,
+}
+
+impl ServiceAccount {
+    pub fn from_file(path: &str) -> Result<Self, BTErr> {
+        Ok(ServiceAccount::from_json(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn from_json(credentials: &str) -> Self {
+        ServiceAccount {
+            credentials: String::from(credentials),
+            cached: Mutex::new(None),
+        }
+    }
+}
+
+impl TokenSource for ServiceAccount {
+    fn token(&self) -> Result<Token, BTErr> {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((token, fetched)) = &*cached {
+            let lifetime = Duration::from_secs(u64::from(token.expires_in()));
+            if fetched.elapsed() + Duration::from_secs(60) < lifetime {
+                return Ok(token.clone());
+            }
+        }
+        let token = get_auth_token(&self.credentials, false)?;
+        *cached = Some((token.clone(), Instant::now()));
+        Ok(token)
+    }
+}
+
+/// Entry of a `bulk_mutate` call that was not applied.
+#[derive(Debug, Clone)]
+pub struct FailedMutation {
+    /// Position of the entry in the request.
+    pub index: usize,
+    pub error: ApiError,
+}
+
+/// Client for one instance.
+///
+/// ```ignore
+/// use bigtable::client::{BigtableClient, ServiceAccount};
+///
+/// let client = BigtableClient::new(&instance, ServiceAccount::from_file("credentials.json")?)
+///     .app_profile("batch")
+///     .max_retries(5);
+/// let table = client.table("my-table")?;
+/// table.mutate_row(b"user#1", RowMutation::new().set_cell("cf1", b"name", b"durch"))?;
+/// let row = table.read_row(b"user#1", None)?;
+/// ```
+#[derive(Clone)]
+pub struct BigtableClient {
+    instance: Instance,
+    endpoint: String,
+    tokens: Arc<dyn TokenSource>,
+    app_profile_id: String,
+    retries: Retries,
+    transport: Arc<dyn Transport>,
+}
+
+impl BigtableClient {
+    pub fn new<S: TokenSource + 'static>(instance: &Instance, tokens: S) -> Self {
+        BigtableClient {
+            instance: instance.clone(),
+            endpoint: String::from(DEFAULT_ENDPOINT),
+            tokens: Arc::new(tokens),
+            app_profile_id: String::new(),
+            retries: Default::default(),
+            transport: Arc::new(CurlTransport),
+        }
+    }
+
+    /// Base URL of the REST API (default `https://bigtable.googleapis.com/v2`).
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = String::from(endpoint.trim_end_matches('/'));
+        self
+    }
+
+    /// App profile used by every request (default: the instance's default profile).
+    pub fn app_profile(mut self, app_profile_id: &str) -> Self {
+        self.app_profile_id = String::from(app_profile_id);
+        self
+    }
+
+    /// How many times an idempotent request is retried after a retryable
+    /// error (default 3).
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.retries.max_retries = max_retries;
+        self
+    }
+
+    /// Wait before the first retry, doubled for each further one (default 100ms).
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retries.delay = delay;
+        self
+    }
+
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn instance(&self) -> &Instance {
+        &self.instance
+    }
+
+    pub fn app_profile_id(&self) -> &str {
+        &self.app_profile_id
+    }
+
+    /// A token from the client's `TokenSource`, for APIs taking `&Token`.
+    pub fn token(&self) -> Result<Token, BTErr> {
+        self.tokens.token()
+    }
+
+    /// Handle on the table `name`; fails if it is not a valid table ID.
+    pub fn table(&self, name: &str) -> Result<TableHandle, BTErr> {
+        Ok(TableHandle {
+            table: self.instance.table(name)?.app_profile(&self.app_profile_id),
+            client: self.clone(),
+        })
+    }
+
+    /// Handle on the authorized view `view` of `table`.
+    pub fn authorized_view(&self, table: &str, view: &str) -> Result<TableHandle, BTErr> {
+        let mut handle = self.table(table)?;
+        handle.table = handle.table.authorized_view(view)?;
+        Ok(handle)
+    }
+
+    /// Handle on the materialized view `view`; only reads and
+    /// `sample_row_keys` are supported.
+    pub fn materialized_view(&self, view: &str) -> Result<TableHandle, BTErr> {
+        Ok(TableHandle {
+            table: self
+                .instance
+                .materialized_view(view)?
+                .app_profile(&self.app_profile_id),
+            client: self.clone(),
+        })
+    }
+}
+
+/// Data API calls on one table, made through a `BigtableClient`.
+#[derive(Clone)]
+pub struct TableHandle {
+    client: BigtableClient,
+    table: Table,
+}
+
+impl TableHandle {
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    /// Reads the rows in `rows` (the whole table if empty), up to `limit` rows
+    /// (0 for no limit).
+    pub fn read_rows(
+        &self,
+        rows: RowSet,
+        filter: Option<RowFilter>,
+        limit: i64,
+    ) -> Result<Vec<Row>, BTErr> {
+        let mut method = ReadRows::new();
+        let payload = method.payload_mut();
+        payload.rows = Some(rows).into();
+        payload.filter = filter.into();
+        payload.rows_limit = limit;
+        let responses = parse_stream::<ReadRowsResponse>(&self.execute(method, true)?)?;
+        rows::merge_rows(responses)
+    }
+
+    pub fn read_row(
+        &self,
+        row_key: &[u8],
+        filter: Option<RowFilter>,
+    ) -> Result<Option<Row>, BTErr> {
+        let mut rows = RowSet::new();
+        rows.row_keys.push(row_key.to_vec());
+        Ok(self.read_rows(rows, filter, 1)?.pop())
+    }
+
+    pub fn mutate_row(&self, row_key: &[u8], mutations: RowMutation) -> Result<(), BTErr> {
+        let idempotent = is_idempotent(&mutations.mutations);
+        let mut method = MutateRow::new();
+        let payload = method.payload_mut();
+        payload.row_key = row_key.to_vec();
+        payload.mutations = mutations.mutations;
+        parse_message::<MutateRowResponse>(&self.execute(method, idempotent)?)?;
+        Ok(())
+    }
+
+    /// Applies each entry atomically, returning the entries that failed. Entries
+    /// failing with a retryable status are re-sent on their own; entries the
+    /// response has no status for fail with `UNKNOWN`.
+    pub fn bulk_mutate(
+        &self,
+        entries: Vec<mutate_rows_request::Entry>,
+    ) -> Result<Vec<FailedMutation>, BTErr> {
+        let mut pending: Vec<(usize, mutate_rows_request::Entry)> =
+            entries.into_iter().enumerate().collect();
+        let mut failed = Vec::new();
+        let mut attempt = 0;
+        while !pending.is_empty() {
+            let idempotent = pending.iter().all(|(_, e)| is_idempotent(&e.mutations));
+            let mut method = MutateRows::new();
+            method.payload_mut().entries = pending.iter().map(|(_, e)| e.clone()).collect();
+            let responses = parse_stream::<MutateRowsResponse>(&self.execute(method, idempotent)?)?;
+
+            let mut sent: Vec<Option<(usize, mutate_rows_request::Entry)>> =
+                pending.into_iter().map(Some).collect();
+            let mut errors = Vec::new();
+            for entry in responses.iter().flat_map(|r| r.entries.iter()) {
+                let sent = match sent.get_mut(entry.index as usize).and_then(Option::take) {
+                    Some(sent) => sent,
+                    None => continue,
+                };
+                if let Some(status) = entry.status.as_ref().filter(|s| s.code != 0) {
+                    errors.push((sent, ApiError::from_status(status)));
+                }
+            }
+            // Entries the response never mentions may or may not have been applied
+            errors.extend(sent.into_iter().flatten().map(|sent| {
+                let error = ApiError {
+                    code: 500,
+                    message: String::from("no status returned for entry"),
+                    status: String::from("UNKNOWN"),
+                    details: Vec::new(),
+                };
+                (sent, error)
+            }));
+
+            let mut retry = Vec::new();
+            for ((index, e), error) in errors {
+                if BTErr::ApiErr(error.clone()).is_retryable()
+                    && is_idempotent(&e.mutations)
+                    && attempt < self.client.retries.max_retries
+                {
+                    retry.push((index, e));
+                } else {
+                    failed.push(FailedMutation { index, error });
+                }
+            }
+            if !retry.is_empty() {
+                debug!("Retrying {} MutateRows entries", retry.len());
+                thread::sleep(self.client.retries.delay * 2u32.saturating_pow(attempt));
+                attempt += 1;
+            }
+            pending = retry;
+        }
+        failed.sort_by_key(|f| f.index);
+        Ok(failed)
+    }
+
+    /// Applies `on_match` if `predicate` yields any cell for the row,
+    /// `on_no_match` otherwise, and returns whether the predicate matched.
+    pub fn check_and_mutate(
+        &self,
+        row_key: &[u8],
+        predicate: RowFilter,
+        on_match: RowMutation,
+        on_no_match: RowMutation,
+    ) -> Result<bool, BTErr> {
+        let mut method = CheckAndMutateRow::new();
+        let payload = method.payload_mut();
+        payload.row_key = row_key.to_vec();
+        payload.predicate_filter = Some(predicate).into();
+        payload.true_mutations = on_match.mutations;
+        payload.false_mutations = on_no_match.mutations;
+        let response = parse_message::<CheckAndMutateRowResponse>(&self.execute(method, false)?)?;
+        Ok(response.predicate_matched)
+    }
+
+    /// Applies `rules` atomically and returns the post-modification cells.
+    pub fn read_modify_write(&self, rules: ReadModifyWrite) -> Result<ModifiedRow, BTErr> {
+        let mut method = ReadModifyWriteRow::new();
+        method.set_payload(rules.into_request());
+        let mut response =
+            parse_message::<ReadModifyWriteRowResponse>(&self.execute(method, false)?)?;
+        Ok(ModifiedRow {
+            row: response.row.take().unwrap_or_default(),
+        })
+    }
+
+    pub fn sample_row_keys(&self) -> Result<Vec<SampleRowKeysResponse>, BTErr> {
+        parse_stream::<SampleRowKeysResponse>(&self.execute(SampleRowKeys::new(), true)?)
+    }
+
+    /// Writes `value` to its row.
+    pub fn put<T: BigtableRow>(&self, value: &T) -> Result<(), BTErr> {
+        self.mutate_row(&value.row_key()?, value.to_mutations()?)
+    }
+
+    /// Reads the row at `row_key` into a `T`.
+    pub fn get<T: BigtableRow>(&self, row_key: &[u8]) -> Result<Option<T>, BTErr> {
+        self.read_row(row_key, None)?
+            .map(|row| T::from_row(&row))
+            .transpose()
+    }
+
+    fn execute<M: BigTable>(&self, method: M, idempotent: bool) -> Result<Value, BTErr> {
+        let client = &self.client;
+        let req = BTRequest {
+            base: Some(&client.endpoint),
+            table: self.table.clone(),
+            method,
+        };
+        let mut attempt = 0;
+        loop {
+            let result = client
+                .token()
+                .and_then(|token| req.execute_with(&token, &*client.transport))
+                .and_then(|response| check_response(&response).map(|_| response));
+            match result {
+                Err(e)
+                    if idempotent && e.is_retryable() && attempt < client.retries.max_retries =>
+                {
+                    debug!("Retrying {} after: {}", req.method.url_method(), e);
+                    thread::sleep(client.retries.delay * 2u32.saturating_pow(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn is_idempotent(mutations: &[Mutation]) -> bool {
+    mutations.iter().all(|m| match &m.mutation {
+        Some(mutation::Mutation::SetCell(set)) => set.timestamp_micros != -1,
+        Some(mutation::Mutation::AddToCell(_)) | Some(mutation::Mutation::MergeToCell(_)) => false,
+        _ => true,
+    })
+}
@@ -0,0 +1,483 @@
+// AIDEV-NOTE: `Value` carries only the data; its SQL type travels separately
+// (declared parameter types, result column metadata). `SqlType` gives the type a
+// Rust type maps to, `ToValue`/`FromValue` convert the data, and `decode` checks
+// a value against its declared type before converting it. NULL is a `Value`
+// without a kind and only converts to `Option`. Arrays, structs and maps are all
+// `ArrayValue`s; a map is an array of `[key, value]` pairs.
+use crate::error::BTErr;
+use crate::protos::data::{value, ArrayValue, Value};
+use crate::protos::date;
+use crate::protos::types::{type_, Type};
+use crate::utils::{from_timestamp, to_timestamp};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::time::SystemTime;
+
+/// The SQL type a Rust type is sent and read as.
+pub trait SqlType {
+    fn sql_type() -> Type;
+}
+
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, BTErr>;
+}
+
+/// A calendar date, without time zone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+/// Converts `value` to a `Value` tagged with its SQL type.
+pub fn typed_value<T: ToValue + SqlType>(value: &T) -> Value {
+    let mut v = value.to_value();
+    v.type_ = Some(T::sql_type()).into();
+    v
+}
+
+/// Converts `value`, declared as `ty` (e.g. by a result column), into `T`,
+/// failing if `T` is not read as `ty` or the value does not match it.
+pub fn decode<T: FromValue + SqlType>(value: &Value, ty: &Type) -> Result<T, BTErr> {
+    let expected = T::sql_type();
+    if !same_type(&expected, ty) {
+        return Err(BTErr::TypeErr(format!(
+            "cannot read {} as {}",
+            type_name(ty),
+            type_name(&expected)
+        )));
+    }
+    check_value(value, ty)?;
+    T::from_value(value)
+}
+
+// Types match by kind; encodings and field names are not compared.
+fn same_type(a: &Type, b: &Type) -> bool {
+    use type_::Kind as T;
+    match (&a.kind, &b.kind) {
+        (Some(T::ArrayType(a)), Some(T::ArrayType(b))) => {
+            same_type(&a.element_type, &b.element_type)
+        }
+        (Some(T::MapType(a)), Some(T::MapType(b))) => {
+            same_type(&a.key_type, &b.key_type) && same_type(&a.value_type, &b.value_type)
+        }
+        (Some(T::StructType(a)), Some(T::StructType(b))) => {
+            a.fields.len() == b.fields.len()
+                && a.fields
+                    .iter()
+                    .zip(&b.fields)
+                    .all(|(a, b)| same_type(&a.type_, &b.type_))
+        }
+        (Some(a), Some(b)) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        _ => false,
+    }
+}
+
+fn type_of(kind: type_::Kind) -> Type {
+    let mut ty = Type::new();
+    ty.kind = Some(kind);
+    ty
+}
+
+fn value_of(kind: value::Kind) -> Value {
+    let mut v = Value::new();
+    v.kind = Some(kind);
+    v
+}
+
+fn mismatch(expected: &str, value: &Value) -> BTErr {
+    let got = value.kind.as_ref().map_or("NULL", kind_name);
+    BTErr::TypeErr(format!("expected {}, got {}", expected, got))
+}
+
+fn array_values<'a>(expected: &str, value: &'a Value) -> Result<&'a [Value], BTErr> {
+    match &value.kind {
+        Some(value::Kind::ArrayValue(array)) => Ok(&array.values),
+        _ => Err(mismatch(expected, value)),
+    }
+}
+
+fn array_of(values: Vec<Value>) -> Value {
+    let mut array = ArrayValue::new();
+    array.values = values;
+    value_of(value::Kind::ArrayValue(array))
+}
+
+macro_rules! scalar {
+    ($rust: ty, $type_kind: ident, $name: expr, |$v: ident| $to: expr, |$k: ident| $from: expr) => {
+        impl SqlType for $rust {
+            fn sql_type() -> Type {
+                type_of(type_::Kind::$type_kind(Default::default()))
+            }
+        }
+
+        impl ToValue for $rust {
+            fn to_value(&self) -> Value {
+                let $v = self;
+                value_of($to)
+            }
+        }
+
+        impl FromValue for $rust {
+            fn from_value(value: &Value) -> Result<Self, BTErr> {
+                match &value.kind {
+                    Some($k) => $from.ok_or_else(|| mismatch($name, value)),
+                    None => Err(mismatch($name, value)),
+                }
+            }
+        }
+    };
+}
+
+scalar!(
+    String,
+    StringType,
+    "string",
+    |v| value::Kind::StringValue(v.clone()),
+    |k| match k {
+        value::Kind::StringValue(s) => Some(s.clone()),
+        _ => None,
+    }
+);
+scalar!(
+    Vec<u8>,
+    BytesType,
+    "bytes",
+    |v| value::Kind::BytesValue(v.clone()),
+    |k| match k {
+        value::Kind::BytesValue(b) => Some(b.clone()),
+        _ => None,
+    }
+);
+scalar!(
+    i64,
+    Int64Type,
+    "int64",
+    |v| value::Kind::IntValue(*v),
+    |k| match k {
+        value::Kind::IntValue(i) => Some(*i),
+        _ => None,
+    }
+);
+scalar!(
+    i32,
+    Int64Type,
+    "int64 within i32",
+    |v| value::Kind::IntValue(i64::from(*v)),
+    |k| match k {
+        value::Kind::IntValue(i) => i32::try_from(*i).ok(),
+        _ => None,
+    }
+);
+scalar!(
+    f64,
+    Float64Type,
+    "float",
+    |v| value::Kind::FloatValue(*v),
+    |k| match k {
+        value::Kind::FloatValue(f) => Some(*f),
+        _ => None,
+    }
+);
+scalar!(
+    f32,
+    Float32Type,
+    "float",
+    |v| value::Kind::FloatValue(f64::from(*v)),
+    |k| match k {
+        value::Kind::FloatValue(f) => Some(*f as f32),
+        _ => None,
+    }
+);
+scalar!(
+    bool,
+    BoolType,
+    "bool",
+    |v| value::Kind::BoolValue(*v),
+    |k| match k {
+        value::Kind::BoolValue(b) => Some(*b),
+        _ => None,
+    }
+);
+scalar!(
+    SystemTime,
+    TimestampType,
+    "timestamp",
+    |v| value::Kind::TimestampValue(to_timestamp(*v)),
+    |k| match k {
+        value::Kind::TimestampValue(ts) => Some(from_timestamp(ts)),
+        _ => None,
+    }
+);
+scalar!(
+    Date,
+    DateType,
+    "date",
+    |v| {
+        let mut d = date::Date::new();
+        d.year = v.year;
+        d.month = v.month as i32;
+        d.day = v.day as i32;
+        value::Kind::DateValue(d)
+    },
+    |k| match k {
+        value::Kind::DateValue(d) => Some(Date {
+            year: d.year,
+            month: d.month as u32,
+            day: d.day as u32,
+        }),
+        _ => None,
+    }
+);
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        value_of(value::Kind::StringValue(String::from(self)))
+    }
+}
+
+impl ToValue for [u8] {
+    fn to_value(&self) -> Value {
+        value_of(value::Kind::BytesValue(self.to_vec()))
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+/// A `Value` converts as is; its type is checked where it is used.
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl<T: SqlType> SqlType for Option<T> {
+    fn sql_type() -> Type {
+        T::sql_type()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            Some(v) => v.to_value(),
+            None => Value::new(),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, BTErr> {
+        match value.kind {
+            Some(_) => T::from_value(value).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// `Vec<u8>` is BYTES; `u8` must therefore never implement these traits, or the
+// impls below would overlap.
+impl<T: SqlType> SqlType for Vec<T> {
+    fn sql_type() -> Type {
+        array_type(T::sql_type())
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        array_of(self.iter().map(ToValue::to_value).collect())
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, BTErr> {
+        array_values("array", value)?
+            .iter()
+            .map(T::from_value)
+            .collect()
+    }
+}
+
+fn array_type(element: Type) -> Type {
+    let mut array = type_::Array::new();
+    array.element_type = Some(element).into();
+    type_of(type_::Kind::ArrayType(array))
+}
+
+fn map_type(key: Type, value: Type) -> Type {
+    let mut map = type_::Map::new();
+    map.key_type = Some(key).into();
+    map.value_type = Some(value).into();
+    type_of(type_::Kind::MapType(map))
+}
+
+fn map_entries<'a, K: ToValue + 'a, V: ToValue + 'a>(
+    entries: impl Iterator<Item = (&'a K, &'a V)>,
+) -> Value {
+    array_of(
+        entries
+            .map(|(k, v)| array_of(vec![k.to_value(), v.to_value()]))
+            .collect(),
+    )
+}
+
+fn map_pairs<K: FromValue, V: FromValue>(value: &Value) -> Result<Vec<(K, V)>, BTErr> {
+    let mut pairs = Vec::new();
+    for entry in array_values("map", value)? {
+        match array_values("map entry", entry)? {
+            [k, v] => pairs.push((K::from_value(k)?, V::from_value(v)?)),
+            _ => return Err(mismatch("[key, value] pair", entry)),
+        }
+    }
+    Ok(pairs)
+}
+
+impl<K: SqlType, V: SqlType> SqlType for HashMap<K, V> {
+    fn sql_type() -> Type {
+        map_type(K::sql_type(), V::sql_type())
+    }
+}
+
+impl<K: ToValue, V: ToValue> ToValue for HashMap<K, V> {
+    fn to_value(&self) -> Value {
+        map_entries(self.iter())
+    }
+}
+
+impl<K: FromValue + Eq + Hash, V: FromValue> FromValue for HashMap<K, V> {
+    fn from_value(value: &Value) -> Result<Self, BTErr> {
+        Ok(map_pairs(value)?.into_iter().collect())
+    }
+}
+
+impl<K: SqlType, V: SqlType> SqlType for BTreeMap<K, V> {
+    fn sql_type() -> Type {
+        map_type(K::sql_type(), V::sql_type())
+    }
+}
+
+impl<K: ToValue, V: ToValue> ToValue for BTreeMap<K, V> {
+    fn to_value(&self) -> Value {
+        map_entries(self.iter())
+    }
+}
+
+impl<K: FromValue + Ord, V: FromValue> FromValue for BTreeMap<K, V> {
+    fn from_value(value: &Value) -> Result<Self, BTErr> {
+        Ok(map_pairs(value)?.into_iter().collect())
+    }
+}
+
+/// Checks that `value` can be sent as a parameter of type `ty`. A value
+/// without a kind is NULL and matches every type.
+pub fn check_value(value: &Value, ty: &Type) -> Result<(), BTErr> {
+    check(value, ty).map_err(BTErr::TypeErr)
+}
+
+fn check(value: &Value, ty: &Type) -> Result<(), String> {
+    use type_::Kind as T;
+    use value::Kind as V;
+
+    let kind = match &value.kind {
+        Some(kind) => kind,
+        None => return Ok(()),
+    };
+    let matches = match (&ty.kind, kind) {
+        (Some(T::BytesType(_)), V::BytesValue(_))
+        | (Some(T::StringType(_)), V::StringValue(_))
+        | (Some(T::Int64Type(_)), V::IntValue(_))
+        | (Some(T::Float32Type(_)), V::FloatValue(_))
+        | (Some(T::Float64Type(_)), V::FloatValue(_))
+        | (Some(T::BoolType(_)), V::BoolValue(_))
+        | (Some(T::TimestampType(_)), V::TimestampValue(_))
+        | (Some(T::DateType(_)), V::DateValue(_)) => true,
+        (Some(T::ArrayType(array)), V::ArrayValue(elements)) => {
+            for element in &elements.values {
+                check(element, &array.element_type)?;
+            }
+            true
+        }
+        (Some(T::StructType(st)), V::ArrayValue(fields)) => {
+            if fields.values.len() != st.fields.len() {
+                return Err(format!(
+                    "struct has {} fields, got {} values",
+                    st.fields.len(),
+                    fields.values.len()
+                ));
+            }
+            for (field, value) in st.fields.iter().zip(&fields.values) {
+                check(value, &field.type_)
+                    .map_err(|e| format!("field {}: {}", field.field_name, e))?;
+            }
+            true
+        }
+        (Some(T::MapType(map)), V::ArrayValue(entries)) => {
+            for entry in &entries.values {
+                match &entry.kind {
+                    Some(V::ArrayValue(pair)) if pair.values.len() == 2 => {
+                        check(&pair.values[0], &map.key_type)?;
+                        check(&pair.values[1], &map.value_type)?;
+                    }
+                    _ => return Err(String::from("map entries must be [key, value] pairs")),
+                }
+            }
+            true
+        }
+        _ => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} does not match type {}",
+            kind_name(kind),
+            type_name(ty)
+        ))
+    }
+}
+
+pub(crate) fn kind_name(kind: &value::Kind) -> &'static str {
+    match kind {
+        value::Kind::RawValue(_) => "raw value",
+        value::Kind::RawTimestampMicros(_) => "raw timestamp",
+        value::Kind::BytesValue(_) => "bytes",
+        value::Kind::StringValue(_) => "string",
+        value::Kind::IntValue(_) => "int64",
+        value::Kind::BoolValue(_) => "bool",
+        value::Kind::FloatValue(_) => "float",
+        value::Kind::TimestampValue(_) => "timestamp",
+        value::Kind::DateValue(_) => "date",
+        value::Kind::ArrayValue(_) => "array",
+    }
+}
+
+pub(crate) fn type_name(ty: &Type) -> &'static str {
+    use type_::Kind as T;
+    match &ty.kind {
+        Some(T::BytesType(_)) => "BYTES",
+        Some(T::StringType(_)) => "STRING",
+        Some(T::Int64Type(_)) => "INT64",
+        Some(T::Float32Type(_)) => "FLOAT32",
+        Some(T::Float64Type(_)) => "FLOAT64",
+        Some(T::BoolType(_)) => "BOOL",
+        Some(T::TimestampType(_)) => "TIMESTAMP",
+        Some(T::DateType(_)) => "DATE",
+        Some(T::AggregateType(_)) => "AGGREGATE",
+        Some(T::StructType(_)) => "STRUCT",
+        Some(T::ArrayType(_)) => "ARRAY",
+        Some(T::MapType(_)) => "MAP",
+        Some(T::ProtoType(_)) => "PROTO",
+        Some(T::EnumType(_)) => "ENUM",
+        None => "unspecified",
+    }
+}
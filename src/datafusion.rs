@@ -0,0 +1,501 @@
+// AIDEV-NOTE: DataFusion TableProvider, behind the `datafusion` feature. A table
+// is exposed wide: `row_key` plus one binary `family:qualifier` column per declared
+// column, holding the newest cell's value. Row-key predicates become the scan's
+// RowSet, projections a column RowFilter, limits `rows_limit`, and partitions come
+// from SampleRowKeys like ParallelScan shards. ReadRows is blocking, so each page
+// is read on DataFusion's blocking pool and becomes one RecordBatch.
+// The projection filter is interleaved with a labelled keys-only branch so rows
+// without any projected column still come back; labelled cells are not values.
+use crate::client::TableHandle;
+use crate::error::BTErr;
+use crate::filters;
+use crate::protos::data::{Row, RowFilter, RowSet};
+use crate::scan::{self, KeyPredicate};
+use ::datafusion::arrow::array::{ArrayRef, BinaryArray, RecordBatch};
+use ::datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use ::datafusion::catalog::{Session, TableProvider};
+use ::datafusion::common::runtime::SpawnedTask;
+use ::datafusion::common::{DataFusionError, Result, ScalarValue};
+use ::datafusion::execution::TaskContext;
+use ::datafusion::logical_expr::{
+    Between, BinaryExpr, Cast, Expr, Like, Operator, TableProviderFilterPushDown, TableType,
+    TryCast,
+};
+use ::datafusion::physical_expr::EquivalenceProperties;
+use ::datafusion::physical_plan::empty::EmptyExec;
+use ::datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use ::datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use ::datafusion::physical_plan::{
+    DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, PlanProperties,
+    SendableRecordBatchStream,
+};
+use async_trait::async_trait;
+use std::any::Any;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Name of the row key column.
+pub const ROW_KEY: &str = "row_key";
+
+// Label of the cells read only so that every row shows up.
+const KEY_LABEL: &str = "row_key";
+
+/// A Bigtable table as a DataFusion table.
+///
+/// ```ignore
+/// use bigtable::datafusion::BigtableTable;
+/// use datafusion::prelude::SessionContext;
+///
+/// let users = BigtableTable::new(client.table("users")?)
+///     .column("cf1", b"name")
+///     .column("cf1", b"email");
+/// let ctx = SessionContext::new();
+/// ctx.register_table("users", Arc::new(users))?;
+/// let df = ctx
+///     .sql(r#"SELECT row_key, "cf1:name" FROM users WHERE row_key LIKE 'user#%' LIMIT 10"#)
+///     .await?;
+/// ```
+#[derive(Clone)]
+pub struct BigtableTable {
+    handle: TableHandle,
+    columns: Vec<(String, Vec<u8>)>,
+    schema: SchemaRef,
+}
+
+impl BigtableTable {
+    /// The table behind `handle`, with only its `row_key` column.
+    pub fn new(handle: TableHandle) -> Self {
+        BigtableTable {
+            handle,
+            columns: Vec::new(),
+            schema: Arc::new(wide_schema(&[])),
+        }
+    }
+
+    /// Adds the column `family:qualifier`, holding the newest cell's value or null.
+    pub fn column(mut self, family: &str, qualifier: &[u8]) -> Self {
+        let column = (String::from(family), qualifier.to_vec());
+        if !self.columns.contains(&column) {
+            self.columns.push(column);
+            self.schema = Arc::new(wide_schema(&self.columns));
+        }
+        self
+    }
+}
+
+impl fmt::Debug for BigtableTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BigtableTable")
+            .field("table", &self.handle.table().to_string())
+            .field("schema", &self.schema)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl TableProvider for BigtableTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> Result<Vec<TableProviderFilterPushDown>> {
+        Ok(filters
+            .iter()
+            .map(|f| match key_predicate(f) {
+                Some(_) => TableProviderFilterPushDown::Exact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let projection: Vec<usize> = match projection {
+            Some(p) => p.clone(),
+            None => (0..self.schema.fields().len()).collect(),
+        };
+        let schema = Arc::new(self.schema.project(&projection)?);
+        let sources: Vec<Source> = projection
+            .iter()
+            .map(|i| match i {
+                0 => Source::Key,
+                i => Source::Column(self.columns[i - 1].clone()),
+            })
+            .collect();
+
+        let predicates: Vec<KeyPredicate> = filters.iter().filter_map(key_predicate).collect();
+        let rows = match scan::key_row_set(&predicates) {
+            Some(rows) => rows,
+            None => return Ok(Arc::new(EmptyExec::new(schema))),
+        };
+        let shards = if rows.row_ranges.is_empty() && !rows.row_keys.is_empty() {
+            vec![rows]
+        } else {
+            let partitions = state.config().target_partitions();
+            let handle = self.handle.clone();
+            let samples = SpawnedTask::spawn_blocking(move || handle.sample_row_keys())
+                .await
+                .map_err(|e| DataFusionError::External(Box::new(e)))?
+                .map_err(bt_err)?;
+            scan::split_row_set(&rows, &samples, partitions)
+        };
+        if shards.is_empty() {
+            return Ok(Arc::new(EmptyExec::new(schema)));
+        }
+
+        Ok(Arc::new(BigtableExec {
+            handle: self.handle.clone(),
+            properties: PlanProperties::new(
+                EquivalenceProperties::new(schema.clone()),
+                Partitioning::UnknownPartitioning(shards.len()),
+                EmissionType::Incremental,
+                Boundedness::Bounded,
+            ),
+            filter: projection_filter(&sources),
+            sources,
+            schema,
+            shards,
+            limit: limit.map_or(0, |l| l as i64),
+        }))
+    }
+}
+
+/// Reads the shards of a `BigtableTable` scan, one per partition.
+#[derive(Clone)]
+pub struct BigtableExec {
+    handle: TableHandle,
+    schema: SchemaRef,
+    sources: Vec<Source>,
+    filter: RowFilter,
+    shards: Vec<RowSet>,
+    // rows per shard, 0 for no limit
+    limit: i64,
+    properties: PlanProperties,
+}
+
+// Where an output column comes from.
+#[derive(Clone, Debug)]
+enum Source {
+    Key,
+    Column((String, Vec<u8>)),
+}
+
+impl fmt::Debug for BigtableExec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BigtableExec")
+            .field("table", &self.handle.table().to_string())
+            .field("shards", &self.shards.len())
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl DisplayAs for BigtableExec {
+    fn fmt_as(&self, _: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BigtableExec: table={}, shards={}",
+            self.handle.table(),
+            self.shards.len()
+        )?;
+        if self.limit > 0 {
+            write!(f, ", limit={}", self.limit)?;
+        }
+        Ok(())
+    }
+}
+
+impl ExecutionPlan for BigtableExec {
+    fn name(&self) -> &'static str {
+        "BigtableExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        let shard = self.shards.get(partition).cloned().ok_or_else(|| {
+            DataFusionError::Internal(format!("BigtableExec has no partition {}", partition))
+        })?;
+        let page_size = context.session_config().batch_size() as i64;
+        let exec = self.clone();
+        let pages = futures::stream::unfold(Some((shard, exec.limit)), move |next| {
+            let exec = exec.clone();
+            async move {
+                let (rows, remaining) = next?;
+                let read =
+                    SpawnedTask::spawn_blocking(move || exec.read_page(rows, remaining, page_size))
+                        .await;
+                Some(match read {
+                    Ok(Ok((batch, next))) => (Ok(batch), next),
+                    Ok(Err(e)) => (Err(bt_err(e)), None),
+                    Err(e) => (Err(DataFusionError::External(Box::new(e))), None),
+                })
+            }
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema.clone(),
+            pages,
+        )))
+    }
+}
+
+impl BigtableExec {
+    // Reads up to `page_size` rows of `rows`, returning them with the rows
+    // and row budget left, if any.
+    fn read_page(
+        &self,
+        rows: RowSet,
+        remaining: i64,
+        page_size: i64,
+    ) -> Result<(RecordBatch, Option<(RowSet, i64)>), BTErr> {
+        let page_size = match remaining {
+            0 => page_size,
+            remaining => page_size.min(remaining),
+        };
+        let page = self
+            .handle
+            .read_rows(rows.clone(), Some(self.filter.clone()), page_size)?;
+        let batch = record_batch(&page, &self.schema, &self.sources)?;
+
+        let remaining = match remaining {
+            0 => 0,
+            remaining if remaining <= page.len() as i64 => return Ok((batch, None)),
+            remaining => remaining - page.len() as i64,
+        };
+        let next = match page.last() {
+            Some(row) if page.len() as i64 >= page_size => {
+                scan::restrict(&rows, &Bound::Excluded(row.key.clone()), &Bound::Unbounded)
+                    .map(|rest| (rest, remaining))
+            }
+            _ => None,
+        };
+        Ok((batch, next))
+    }
+}
+
+fn wide_schema(columns: &[(String, Vec<u8>)]) -> Schema {
+    let mut fields = vec![Field::new(ROW_KEY, DataType::Binary, false)];
+    for (family, qualifier) in columns {
+        let name = format!("{}:{}", family, String::from_utf8_lossy(qualifier));
+        fields.push(Field::new(name, DataType::Binary, true));
+    }
+    Schema::new(fields)
+}
+
+// The newest cell of each projected column, plus a labelled empty cell so rows
+// with none of them are still read.
+fn projection_filter(sources: &[Source]) -> RowFilter {
+    let keys = filters::chain(vec![
+        filters::cells_per_row_limit(1),
+        filters::strip_value(),
+        filters::label(KEY_LABEL),
+    ]);
+    let columns: Vec<(&str, &[u8])> = sources
+        .iter()
+        .filter_map(|s| match s {
+            Source::Column((family, qualifier)) => Some((family.as_str(), qualifier.as_slice())),
+            Source::Key => None,
+        })
+        .collect();
+    if columns.is_empty() {
+        return keys;
+    }
+    filters::interleave(vec![
+        filters::chain(vec![filters::columns(&columns), filters::latest()]),
+        keys,
+    ])
+}
+
+fn record_batch(
+    rows: &[Row],
+    schema: &SchemaRef,
+    sources: &[Source],
+) -> Result<RecordBatch, BTErr> {
+    let arrays: Vec<ArrayRef> = sources
+        .iter()
+        .map(|source| -> ArrayRef {
+            match source {
+                Source::Key => Arc::new(BinaryArray::from_iter_values(rows.iter().map(|r| &r.key))),
+                Source::Column((family, qualifier)) => Arc::new(
+                    rows.iter()
+                        .map(|row| newest_value(row, family, qualifier))
+                        .collect::<BinaryArray>(),
+                ),
+            }
+        })
+        .collect();
+    RecordBatch::try_new_with_options(
+        schema.clone(),
+        arrays,
+        &::datafusion::arrow::array::RecordBatchOptions::new().with_row_count(Some(rows.len())),
+    )
+    .map_err(|e| BTErr::DecodeErr(format!("cannot build Arrow batch: {}", e)))
+}
+
+fn newest_value<'a>(row: &'a Row, family: &str, qualifier: &[u8]) -> Option<&'a [u8]> {
+    row.families
+        .iter()
+        .filter(|f| f.name == family)
+        .flat_map(|f| f.columns.iter())
+        .filter(|c| c.qualifier == qualifier)
+        .flat_map(|c| c.cells.iter())
+        .filter(|c| c.labels.is_empty())
+        .max_by_key(|c| c.timestamp_micros)
+        .map(|c| c.value.as_slice())
+}
+
+/// The row-key condition `expr` expresses, if it is one `key_row_set` can push
+/// down: `=`, `IN`, comparisons and `BETWEEN` with literals, `LIKE 'prefix%'`, and
+/// `OR`s of equalities (which is how DataFusion rewrites short `IN` lists).
+pub fn key_predicate(expr: &Expr) -> Option<KeyPredicate> {
+    match expr {
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        }) => {
+            let mut keys = Vec::new();
+            for side in [left, right] {
+                match key_predicate(side)? {
+                    KeyPredicate::Equals(key) => keys.push(key),
+                    KeyPredicate::In(more) => keys.extend(more),
+                    _ => return None,
+                }
+            }
+            Some(KeyPredicate::In(keys))
+        }
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            let (op, key) = if is_row_key(left) {
+                (*op, literal(right)?)
+            } else if is_row_key(right) {
+                (op.swap()?, literal(left)?)
+            } else {
+                return None;
+            };
+            Some(match op {
+                Operator::Eq => KeyPredicate::Equals(key),
+                Operator::Lt => KeyPredicate::Range(Bound::Unbounded, Bound::Excluded(key)),
+                Operator::LtEq => KeyPredicate::Range(Bound::Unbounded, Bound::Included(key)),
+                Operator::Gt => KeyPredicate::Range(Bound::Excluded(key), Bound::Unbounded),
+                Operator::GtEq => KeyPredicate::Range(Bound::Included(key), Bound::Unbounded),
+                _ => return None,
+            })
+        }
+        Expr::InList(list) if !list.negated && is_row_key(&list.expr) => Some(KeyPredicate::In(
+            list.list.iter().map(literal).collect::<Option<_>>()?,
+        )),
+        Expr::Between(Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        }) if is_row_key(expr) => Some(KeyPredicate::Range(
+            Bound::Included(literal(low)?),
+            Bound::Included(literal(high)?),
+        )),
+        Expr::Like(Like {
+            negated: false,
+            expr,
+            pattern,
+            escape_char: None,
+            case_insensitive: false,
+        }) if is_row_key(expr) => {
+            let pattern = literal(pattern)?;
+            let prefix = pattern.strip_suffix(b"%")?;
+            if prefix.iter().any(|b| matches!(b, b'%' | b'_' | b'\\')) {
+                return None;
+            }
+            Some(KeyPredicate::Prefix(prefix.to_vec()))
+        }
+        _ => None,
+    }
+}
+
+// `row_key`, possibly cast to another string or binary type.
+fn is_row_key(expr: &Expr) -> bool {
+    match expr {
+        Expr::Column(column) => column.name == ROW_KEY,
+        Expr::Cast(Cast { expr, data_type }) | Expr::TryCast(TryCast { expr, data_type }) => {
+            is_bytes(data_type) && is_row_key(expr)
+        }
+        _ => false,
+    }
+}
+
+// The bytes of a non-null string or binary literal.
+fn literal(expr: &Expr) -> Option<Vec<u8>> {
+    match expr {
+        Expr::Literal(value, _) => match value {
+            ScalarValue::Binary(Some(v))
+            | ScalarValue::LargeBinary(Some(v))
+            | ScalarValue::BinaryView(Some(v))
+            | ScalarValue::FixedSizeBinary(_, Some(v)) => Some(v.clone()),
+            ScalarValue::Utf8(Some(s))
+            | ScalarValue::LargeUtf8(Some(s))
+            | ScalarValue::Utf8View(Some(s)) => Some(s.clone().into_bytes()),
+            _ => None,
+        },
+        Expr::Cast(Cast { expr, data_type }) | Expr::TryCast(TryCast { expr, data_type }) => {
+            if is_bytes(data_type) {
+                literal(expr)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn is_bytes(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Binary
+            | DataType::LargeBinary
+            | DataType::BinaryView
+            | DataType::Utf8
+            | DataType::LargeUtf8
+            | DataType::Utf8View
+    )
+}
+
+fn bt_err(e: BTErr) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
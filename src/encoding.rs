@@ -0,0 +1,291 @@
+// AIDEV-NOTE: Cell encodings from types.proto, i.e. how a typed value is stored
+// in a cell so that SQL and materialized views read it back. The encoding is
+// part of the `Type` (e.g. `Int64 { encoding: OrderedCodeBytes }`); types
+// without an encoding use the defaults SQL assumes: raw bytes, UTF-8 strings
+// and big-endian INT64. AGGREGATE cells use their state type's encoding.
+use crate::convert::{check_value, type_name};
+use crate::error::BTErr;
+use crate::keys::{decode_ordered_i64, encode_ordered_i64, ordered_struct, split_fields};
+use crate::protos::data::{value, ArrayValue, Value};
+use crate::protos::types::type_::{bytes, int64, string, struct_, timestamp};
+use crate::protos::types::{type_, Type};
+use crate::utils::{decode_i64, encode_i64};
+use protobuf::well_known_types::timestamp::Timestamp;
+
+/// Encodes `value` as cell bytes in the encoding of `ty`.
+///
+/// ```ignore
+/// let bytes = encoding::encode(&42i64.to_value(), &ordered_int64_type)?;
+/// wraps::write_rows(&table, &token, &mut vec![set_cell(family, qualifier, bytes)])?;
+/// ```
+pub fn encode(value: &Value, ty: &Type) -> Result<Vec<u8>, BTErr> {
+    use type_::Kind as T;
+    use value::Kind as V;
+    if let Some(T::AggregateType(agg)) = &ty.kind {
+        return encode(value, &agg.state_type);
+    }
+    check_value(value, ty)?;
+
+    match (&ty.kind, &value.kind) {
+        (Some(T::BytesType(b)), kind) => {
+            let escape_nulls = match &b.encoding.encoding {
+                Some(bytes::encoding::Encoding::Raw(raw)) => raw.escape_nulls,
+                None => false,
+            };
+            match kind {
+                Some(V::BytesValue(v)) => Ok(escape_value(v, &[0], escape_nulls)),
+                _ => null_escaped(escape_nulls, ty),
+            }
+        }
+        (Some(T::StringType(s)), kind) => {
+            let escape = null_escape_char(&s.encoding)?;
+            match kind {
+                Some(V::StringValue(v)) => Ok(escape_value(
+                    v.as_bytes(),
+                    escape.as_bytes(),
+                    !escape.is_empty(),
+                )),
+                _ => null_escaped(!escape.is_empty(), ty),
+            }
+        }
+        (Some(T::Int64Type(i)), Some(V::IntValue(v))) => Ok(encode_int64(*v, &i.encoding)),
+        (Some(T::TimestampType(t)), Some(V::TimestampValue(ts))) => {
+            if ts.nanos % 1000 != 0 {
+                return Err(BTErr::TypeErr(String::from(
+                    "TIMESTAMP cells hold whole microseconds",
+                )));
+            }
+            let micros = ts.seconds * 1_000_000 + i64::from(ts.nanos / 1000);
+            Ok(encode_int64(micros, &micros_encoding(&t.encoding)))
+        }
+        (Some(T::StructType(st)), Some(V::ArrayValue(array))) => {
+            let fields = st
+                .fields
+                .iter()
+                .zip(&array.values)
+                .map(|(f, v)| encode(v, &f.type_))
+                .collect::<Result<Vec<_>, BTErr>>()?;
+            encode_struct(st, fields)
+        }
+        (Some(T::Int64Type(_) | T::TimestampType(_) | T::StructType(_)), None) => {
+            null_escaped(false, ty)
+        }
+        _ => Err(BTErr::TypeErr(format!(
+            "{} has no cell encoding",
+            type_name(ty)
+        ))),
+    }
+}
+
+/// Decodes cell bytes written in the encoding of `ty`.
+pub fn decode(bytes: &[u8], ty: &Type) -> Result<Value, BTErr> {
+    use type_::Kind as T;
+    use value::Kind as V;
+    let kind = match &ty.kind {
+        Some(T::AggregateType(agg)) => return decode(bytes, &agg.state_type),
+        Some(T::BytesType(b)) => {
+            let escape_nulls = match &b.encoding.encoding {
+                Some(bytes::encoding::Encoding::Raw(raw)) => raw.escape_nulls,
+                None => false,
+            };
+            unescape_value(bytes, &[0], escape_nulls).map(V::BytesValue)
+        }
+        Some(T::StringType(s)) => {
+            let escape = null_escape_char(&s.encoding)?;
+            match unescape_value(bytes, escape.as_bytes(), !escape.is_empty()) {
+                Some(v) => Some(V::StringValue(String::from_utf8(v).map_err(|e| {
+                    BTErr::DecodeErr(format!("STRING cell is not UTF-8: {}", e))
+                })?)),
+                None => None,
+            }
+        }
+        Some(T::Int64Type(i)) => Some(V::IntValue(decode_int64(bytes, &i.encoding)?)),
+        Some(T::TimestampType(t)) => {
+            let micros = decode_int64(bytes, &micros_encoding(&t.encoding))?;
+            let mut ts = Timestamp::new();
+            ts.seconds = micros.div_euclid(1_000_000);
+            ts.nanos = (micros.rem_euclid(1_000_000) * 1000) as i32;
+            Some(V::TimestampValue(ts))
+        }
+        Some(T::StructType(st)) => {
+            let fields = decode_struct(st, bytes)?;
+            let mut array = ArrayValue::new();
+            for (field, bytes) in st.fields.iter().zip(&fields) {
+                array.values.push(decode(bytes, &field.type_)?);
+            }
+            Some(V::ArrayValue(array))
+        }
+        _ => {
+            return Err(BTErr::TypeErr(format!(
+                "{} has no cell encoding",
+                type_name(ty)
+            )))
+        }
+    };
+    let mut value = Value::new();
+    value.kind = kind;
+    Ok(value)
+}
+
+fn null_escaped(escape_nulls: bool, ty: &Type) -> Result<Vec<u8>, BTErr> {
+    if escape_nulls {
+        Ok(Vec::new())
+    } else {
+        Err(BTErr::TypeErr(format!(
+            "{} encoding cannot store NULL",
+            type_name(ty)
+        )))
+    }
+}
+
+// With null escaping, "" is NULL, so values made only of `escape` (the empty one
+// included) get one more `escape`.
+fn escape_value(v: &[u8], escape: &[u8], escape_nulls: bool) -> Vec<u8> {
+    let mut out = v.to_vec();
+    if escape_nulls && only_escapes(v, escape) {
+        out.extend_from_slice(escape);
+    }
+    out
+}
+
+fn unescape_value(v: &[u8], escape: &[u8], escape_nulls: bool) -> Option<Vec<u8>> {
+    if !escape_nulls {
+        return Some(v.to_vec());
+    }
+    if v.is_empty() {
+        return None;
+    }
+    if only_escapes(v, escape) {
+        return Some(v[..v.len() - escape.len()].to_vec());
+    }
+    Some(v.to_vec())
+}
+
+fn only_escapes(v: &[u8], escape: &[u8]) -> bool {
+    v.len().is_multiple_of(escape.len()) && v.chunks(escape.len()).all(|c| c == escape)
+}
+
+fn null_escape_char(encoding: &string::Encoding) -> Result<String, BTErr> {
+    match &encoding.encoding {
+        Some(string::encoding::Encoding::Utf8Bytes(utf8)) => {
+            if utf8.null_escape_char.chars().count() > 1 {
+                return Err(BTErr::TypeErr(format!(
+                    "null_escape_char must be a single character, got {:?}",
+                    utf8.null_escape_char
+                )));
+            }
+            Ok(utf8.null_escape_char.clone())
+        }
+        #[allow(deprecated)]
+        Some(string::encoding::Encoding::Utf8Raw(_)) | None => Ok(String::new()),
+    }
+}
+
+fn micros_encoding(encoding: &timestamp::Encoding) -> int64::Encoding {
+    match &encoding.encoding {
+        Some(timestamp::encoding::Encoding::UnixMicrosInt64(e)) => e.clone(),
+        None => int64::Encoding::new(),
+    }
+}
+
+fn encode_int64(v: i64, encoding: &int64::Encoding) -> Vec<u8> {
+    match &encoding.encoding {
+        Some(int64::encoding::Encoding::OrderedCodeBytes(_)) => encode_ordered_i64(v),
+        Some(int64::encoding::Encoding::BigEndianBytes(_)) | None => encode_i64(v),
+    }
+}
+
+fn decode_int64(bytes: &[u8], encoding: &int64::Encoding) -> Result<i64, BTErr> {
+    match &encoding.encoding {
+        Some(int64::encoding::Encoding::OrderedCodeBytes(_)) => match decode_ordered_i64(bytes)? {
+            (v, len) if len == bytes.len() => Ok(v),
+            _ => Err(BTErr::DecodeErr(String::from(
+                "trailing bytes after ordered-code Int64",
+            ))),
+        },
+        Some(int64::encoding::Encoding::BigEndianBytes(_)) | None => decode_i64(bytes),
+    }
+}
+
+fn encode_struct(st: &type_::Struct, fields: Vec<Vec<u8>>) -> Result<Vec<u8>, BTErr> {
+    use struct_::encoding::Encoding as E;
+    match &st.encoding.encoding {
+        Some(E::Singleton(_)) if fields.len() == 1 => Ok(fields.into_iter().next().unwrap()),
+        Some(E::Singleton(_)) => Err(BTErr::TypeErr(format!(
+            "Singleton encoding needs one STRUCT field, got {}",
+            fields.len()
+        ))),
+        Some(E::DelimitedBytes(d)) => {
+            let delimiter = delimiter(d)?;
+            if fields.iter().any(|f| f.contains(&delimiter[0])) {
+                return Err(BTErr::TypeErr(String::from(
+                    "STRUCT field contains its delimiter",
+                )));
+            }
+            if fields.is_empty() {
+                return Ok(delimiter.to_vec());
+            }
+            Ok(fields.join(delimiter))
+        }
+        Some(E::OrderedCodeBytes(_)) => Ok(ordered_struct(&fields)),
+        None => Err(BTErr::TypeErr(String::from("STRUCT has no encoding"))),
+    }
+}
+
+// Encoded bytes of each field of `st`.
+fn decode_struct(st: &type_::Struct, bytes: &[u8]) -> Result<Vec<Vec<u8>>, BTErr> {
+    use struct_::encoding::Encoding as E;
+    let mut fields = match &st.encoding.encoding {
+        Some(E::Singleton(_)) if st.fields.len() == 1 => vec![bytes.to_vec()],
+        Some(E::Singleton(_)) => {
+            return Err(BTErr::TypeErr(format!(
+                "Singleton encoding needs one STRUCT field, got {}",
+                st.fields.len()
+            )))
+        }
+        Some(E::DelimitedBytes(d)) => {
+            let delimiter = delimiter(d)?;
+            if st.fields.is_empty() && bytes == delimiter {
+                Vec::new()
+            } else {
+                split_on(bytes, delimiter)
+            }
+        }
+        Some(E::OrderedCodeBytes(_)) => split_fields(bytes)?,
+        None => return Err(BTErr::TypeErr(String::from("STRUCT has no encoding"))),
+    };
+    // OrderedCodeBytes leaves out trailing empty fields
+    if let Some(E::OrderedCodeBytes(_)) = &st.encoding.encoding {
+        if fields.len() < st.fields.len() {
+            fields.resize(st.fields.len(), Vec::new());
+        }
+    }
+    if fields.len() != st.fields.len() {
+        return Err(BTErr::DecodeErr(format!(
+            "STRUCT has {} fields, cell holds {}",
+            st.fields.len(),
+            fields.len()
+        )));
+    }
+    Ok(fields)
+}
+
+fn delimiter(d: &struct_::encoding::DelimitedBytes) -> Result<&[u8], BTErr> {
+    if d.delimiter.is_empty() {
+        return Err(BTErr::TypeErr(String::from(
+            "DelimitedBytes needs a delimiter",
+        )));
+    }
+    Ok(&d.delimiter)
+}
+
+fn split_on(bytes: &[u8], delimiter: &[u8]) -> Vec<Vec<u8>> {
+    let mut fields = Vec::new();
+    let mut rest = bytes;
+    while let Some(i) = rest.windows(delimiter.len()).position(|w| w == delimiter) {
+        fields.push(rest[..i].to_vec());
+        rest = &rest[i + delimiter.len()..];
+    }
+    fields.push(rest.to_vec());
+    fields
+}
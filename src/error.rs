@@ -1,10 +1,13 @@
 use curl::Error as curl_err;
 use goauth::GoErr as go_err;
 use protobuf::Error as pb_err;
+use protobuf_json_mapping::ParseError as pb_json_parse_err;
 use protobuf_json_mapping::PrintError as pb_json_err;
+use crate::protos::status::Status;
 use serde_json::Error as serde_err;
 use smpl_jwt::JwtErr as jwt_err;
 use std;
+use std::io::Error as io_err;
 use std::str::Utf8Error as utf8_err;
 
 macro_rules! impl_from {
@@ -17,6 +20,67 @@ macro_rules! impl_from {
     };
 }
 
+/// Error returned by the Bigtable REST endpoint, e.g.
+/// `{"error": {"code": 404, "message": "...", "status": "NOT_FOUND"}}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiError {
+    pub code: i64,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub status: String,
+    /// Typed details of the error, such as a `google.rpc.PreconditionFailure`,
+    /// each tagged with its `@type`.
+    #[serde(default)]
+    pub details: Vec<serde_json::Value>,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.status, self.code, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+// gRPC status names and their HTTP codes, indexed by gRPC code.
+const STATUSES: [(&str, i64); 17] = [
+    ("OK", 200),
+    ("CANCELLED", 499),
+    ("UNKNOWN", 500),
+    ("INVALID_ARGUMENT", 400),
+    ("DEADLINE_EXCEEDED", 504),
+    ("NOT_FOUND", 404),
+    ("ALREADY_EXISTS", 409),
+    ("PERMISSION_DENIED", 403),
+    ("RESOURCE_EXHAUSTED", 429),
+    ("FAILED_PRECONDITION", 400),
+    ("ABORTED", 409),
+    ("OUT_OF_RANGE", 400),
+    ("UNIMPLEMENTED", 501),
+    ("INTERNAL", 500),
+    ("UNAVAILABLE", 503),
+    ("DATA_LOSS", 500),
+    ("UNAUTHENTICATED", 401),
+];
+
+impl ApiError {
+    /// Error for a `google.rpc.Status` embedded in a response, such as the
+    /// status of a `MutateRows` entry.
+    pub(crate) fn from_status(status: &Status) -> Self {
+        let (name, code) = STATUSES
+            .get(status.code as usize)
+            .copied()
+            .unwrap_or(("UNKNOWN", 500));
+        ApiError {
+            code,
+            message: status.message.clone(),
+            status: String::from(name),
+            details: Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum BTErr {
     GOErr(go_err),
@@ -24,8 +88,14 @@ pub enum BTErr {
     SerdeErr(serde_err),
     PBErr(pb_err),
     PBJsonErr(pb_json_err),
+    PBJsonParseErr(pb_json_parse_err),
     JWTErr(jwt_err),
     UTF8Err(utf8_err),
+    IOErr(io_err),
+    ApiErr(ApiError),
+    DecodeErr(String),
+    TypeErr(String),
+    ResourceErr(String),
     Unknown,
 }
 
@@ -34,8 +104,26 @@ impl_from!(curl_err, CurlErr);
 impl_from!(serde_err, SerdeErr);
 impl_from!(pb_err, PBErr);
 impl_from!(pb_json_err, PBJsonErr);
+impl_from!(pb_json_parse_err, PBJsonParseErr);
 impl_from!(jwt_err, JWTErr);
 impl_from!(utf8_err, UTF8Err);
+impl_from!(io_err, IOErr);
+impl_from!(ApiError, ApiErr);
+
+impl BTErr {
+    /// Whether the request may succeed if sent again: the connection failed, or
+    /// the service reported a transient condition.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BTErr::CurlErr(_) => true,
+            BTErr::ApiErr(e) => matches!(
+                e.status.as_str(),
+                "UNAVAILABLE" | "ABORTED" | "DEADLINE_EXCEEDED"
+            ),
+            _ => false,
+        }
+    }
+}
 
 impl std::fmt::Display for BTErr {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -45,8 +133,14 @@ impl std::fmt::Display for BTErr {
             BTErr::SerdeErr(e) => e.fmt(f),
             BTErr::PBErr(e) => e.fmt(f),
             BTErr::PBJsonErr(e) => e.fmt(f),
+            BTErr::PBJsonParseErr(e) => e.fmt(f),
             BTErr::JWTErr(e) => e.fmt(f),
             BTErr::UTF8Err(e) => e.fmt(f),
+            BTErr::IOErr(e) => e.fmt(f),
+            BTErr::ApiErr(e) => e.fmt(f),
+            BTErr::DecodeErr(e) => write!(f, "Decode error: {}", e),
+            BTErr::TypeErr(e) => write!(f, "Type error: {}", e),
+            BTErr::ResourceErr(e) => write!(f, "Invalid resource: {}", e),
             BTErr::Unknown => write!(f, "An unknown error has occurred"),
         }
     }
@@ -60,8 +154,14 @@ impl std::error::Error for BTErr {
             BTErr::SerdeErr(e) => Some(e),
             BTErr::PBErr(e) => Some(e),
             BTErr::PBJsonErr(e) => Some(e),
+            BTErr::PBJsonParseErr(e) => Some(e),
             BTErr::JWTErr(e) => Some(e),
             BTErr::UTF8Err(e) => Some(e),
+            BTErr::IOErr(e) => Some(e),
+            BTErr::ApiErr(e) => Some(e),
+            BTErr::DecodeErr(_) => None,
+            BTErr::TypeErr(_) => None,
+            BTErr::ResourceErr(_) => None,
             BTErr::Unknown => None,
         }
     }
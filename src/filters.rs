@@ -0,0 +1,134 @@
+// AIDEV-NOTE: Constructors for common RowFilters. Bigtable regex filters are RE2
+// patterns that must match the whole value, so literal bytes need escaping.
+use crate::protos::data::{
+    column_range, row_filter, value_range, ColumnRange, RowFilter, TimestampRange, ValueRange,
+};
+use std::ops::Bound;
+
+/// Escapes `bytes` so a regex filter matches them literally.
+///
+/// ASCII punctuation is backslash-escaped, NUL becomes `\x00`, and non-ASCII
+/// bytes pass through untouched (same rules as the official clients).
+pub fn escape_regex(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    for &b in bytes {
+        if b == 0 {
+            out.extend_from_slice(b"\\x00");
+        } else if b.is_ascii() && !b.is_ascii_alphanumeric() && b != b'_' {
+            out.push(b'\\');
+            out.push(b);
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+pub fn chain(filters: Vec<RowFilter>) -> RowFilter {
+    let mut chain = row_filter::Chain::new();
+    chain.filters = filters;
+    filter(row_filter::Filter::Chain(chain))
+}
+
+pub fn interleave(filters: Vec<RowFilter>) -> RowFilter {
+    let mut interleave = row_filter::Interleave::new();
+    interleave.filters = filters;
+    filter(row_filter::Filter::Interleave(interleave))
+}
+
+/// Applies `true_filter` to rows where `predicate` yields any cell, `false_filter` otherwise.
+pub fn condition(
+    predicate: RowFilter,
+    true_filter: RowFilter,
+    false_filter: RowFilter,
+) -> RowFilter {
+    let mut condition = row_filter::Condition::new();
+    condition.predicate_filter = Some(predicate).into();
+    condition.true_filter = Some(true_filter).into();
+    condition.false_filter = Some(false_filter).into();
+    filter(row_filter::Filter::Condition(condition))
+}
+
+pub fn pass_all() -> RowFilter {
+    filter(row_filter::Filter::PassAllFilter(true))
+}
+
+pub fn block_all() -> RowFilter {
+    filter(row_filter::Filter::BlockAllFilter(true))
+}
+
+/// Cells of a single column, matched exactly.
+pub fn column(family: &str, qualifier: &[u8]) -> RowFilter {
+    let mut range = ColumnRange::new();
+    range.family_name = String::from(family);
+    range.start_qualifier = Some(column_range::Start_qualifier::StartQualifierClosed(
+        qualifier.to_vec(),
+    ));
+    range.end_qualifier = Some(column_range::End_qualifier::EndQualifierClosed(
+        qualifier.to_vec(),
+    ));
+    filter(row_filter::Filter::ColumnRangeFilter(range))
+}
+
+/// Cells of any of `columns`, e.g. the columns a query projects.
+pub fn columns(columns: &[(&str, &[u8])]) -> RowFilter {
+    match columns {
+        [(family, qualifier)] => column(family, qualifier),
+        _ => interleave(columns.iter().map(|(f, q)| column(f, q)).collect()),
+    }
+}
+
+/// Only the newest cell of each column.
+pub fn latest() -> RowFilter {
+    filter(row_filter::Filter::CellsPerColumnLimitFilter(1))
+}
+
+/// Only the first `n` cells of each row.
+pub fn cells_per_row_limit(n: i32) -> RowFilter {
+    filter(row_filter::Filter::CellsPerRowLimitFilter(n))
+}
+
+/// Replaces every cell's value with an empty one, e.g. to read only row keys.
+pub fn strip_value() -> RowFilter {
+    filter(row_filter::Filter::StripValueTransformer(true))
+}
+
+/// Labels every cell with `label`, telling apart the branches of an `interleave`.
+pub fn label(label: &str) -> RowFilter {
+    filter(row_filter::Filter::ApplyLabelTransformer(String::from(label)))
+}
+
+/// Cells whose value is exactly `value`.
+pub fn value_equals(value: &[u8]) -> RowFilter {
+    filter(row_filter::Filter::ValueRegexFilter(escape_regex(value)))
+}
+
+/// Cells whose value falls in the given byte range.
+pub fn value_range(start: Bound<&[u8]>, end: Bound<&[u8]>) -> RowFilter {
+    let mut range = ValueRange::new();
+    range.start_value = match start {
+        Bound::Included(v) => Some(value_range::Start_value::StartValueClosed(v.to_vec())),
+        Bound::Excluded(v) => Some(value_range::Start_value::StartValueOpen(v.to_vec())),
+        Bound::Unbounded => None,
+    };
+    range.end_value = match end {
+        Bound::Included(v) => Some(value_range::End_value::EndValueClosed(v.to_vec())),
+        Bound::Excluded(v) => Some(value_range::End_value::EndValueOpen(v.to_vec())),
+        Bound::Unbounded => None,
+    };
+    filter(row_filter::Filter::ValueRangeFilter(range))
+}
+
+/// Cells with `start_micros <= timestamp < end_micros`; an `end_micros` of 0 is unbounded.
+pub fn timestamp_range(start_micros: i64, end_micros: i64) -> RowFilter {
+    let mut range = TimestampRange::new();
+    range.start_timestamp_micros = start_micros;
+    range.end_timestamp_micros = end_micros;
+    filter(row_filter::Filter::TimestampRangeFilter(range))
+}
+
+fn filter(f: row_filter::Filter) -> RowFilter {
+    let mut filter = RowFilter::new();
+    filter.filter = Some(f);
+    filter
+}
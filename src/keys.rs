@@ -0,0 +1,382 @@
+// AIDEV-NOTE: Composite keys use the STRUCT `OrderedCodeBytes` encoding from
+// types.proto: fields are joined with 0x00 0x01, 0x00 bytes inside a field become
+// 0x00 0xFF, empty fields become 0x00 0x00 and trailing empty fields are dropped.
+// Strings and bytes are stored as-is, INT64 (and TIMESTAMP micros) use the
+// variable length Int64 `OrderedCodeBytes` format. Both keep byte order equal to
+// value order, so keys built here line up with SQL `STRUCT` keys Bigtable decodes.
+use crate::error::BTErr;
+use crate::protos::data::{Row, RowSet};
+use crate::scan::{prefix_end, to_range, ParallelScan};
+use crate::support::Table;
+use goauth::auth::Token;
+use std::ops::Bound;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ESCAPE: u8 = 0x00;
+const SEPARATOR: u8 = 0x01;
+const EMPTY: u8 = 0x00;
+const NULL_BYTE: u8 = 0xff;
+
+// Header bits of an ordered-code Int64 of the given length, for its first two bytes.
+const HEADERS: [[u8; 2]; 11] = [
+    [0, 0],
+    [0x80, 0],
+    [0xc0, 0],
+    [0xe0, 0],
+    [0xf0, 0],
+    [0xf8, 0],
+    [0xfc, 0],
+    [0xfe, 0],
+    [0xff, 0],
+    [0xff, 0x80],
+    [0xff, 0xc0],
+];
+
+/// Encodes `v` in the Int64 `OrderedCodeBytes` format: 1 to 10 bytes, shorter
+/// near zero, sorting like the numbers they encode.
+pub fn encode_ordered_i64(v: i64) -> Vec<u8> {
+    let magnitude = if v < 0 { !v } else { v } as u64;
+    let bits = 64 - magnitude.leading_zeros() as usize;
+    let len = bits / 7 + 1;
+
+    let sign = if v < 0 { 0xff } else { 0 };
+    let mut buf = [sign; 10];
+    buf[2..].copy_from_slice(&v.to_be_bytes());
+    let begin = buf.len() - len;
+    buf[begin] ^= HEADERS[len][0];
+    if len > 1 {
+        buf[begin + 1] ^= HEADERS[len][1];
+    }
+    buf[begin..].to_vec()
+}
+
+/// Decodes an Int64 `OrderedCodeBytes` value from the start of `bytes`,
+/// returning it and the number of bytes it took.
+pub fn decode_ordered_i64(bytes: &[u8]) -> Result<(i64, usize), BTErr> {
+    let invalid = || BTErr::DecodeErr(String::from("invalid ordered-code Int64"));
+    let first = *bytes.first().ok_or_else(invalid)?;
+    let sign = if first & 0x80 == 0 { 0xff } else { 0 };
+    let len = match first ^ sign {
+        0xff => match bytes.get(1).map(|b| b ^ sign) {
+            Some(second) if second < 0x80 => 8,
+            Some(second) if second < 0xc0 => 9,
+            Some(0xc0) if bytes.get(2).is_some_and(|b| b ^ sign < 0x80) => 10,
+            _ => return Err(invalid()),
+        },
+        header => header.leading_ones() as usize,
+    };
+    if bytes.len() < len {
+        return Err(invalid());
+    }
+
+    let mut buf = [sign; 10];
+    let begin = buf.len() - len;
+    buf[begin..].copy_from_slice(&bytes[..len]);
+    buf[begin] ^= HEADERS[len][0];
+    if len > 1 {
+        buf[begin + 1] ^= HEADERS[len][1];
+    }
+    let mut be = [0u8; 8];
+    be.copy_from_slice(&buf[2..]);
+    let v = i64::from_be_bytes(be);
+    if (v < 0) != (sign == 0xff) || buf[..2] != [sign, sign] {
+        return Err(invalid());
+    }
+    Ok((v, len))
+}
+
+/// Microseconds since the epoch, negative before it. Sub-microsecond parts
+/// round down, towards the earlier time.
+pub fn timestamp_micros(t: SystemTime) -> i64 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_micros() as i64,
+        Err(e) => {
+            let d = e.duration();
+            let partial = d.subsec_nanos() % 1000 != 0;
+            -(d.as_micros() as i64) - i64::from(partial)
+        }
+    }
+}
+
+/// Inverse of `timestamp_micros`.
+pub fn from_timestamp_micros(micros: i64) -> SystemTime {
+    let d = Duration::from_micros(micros.unsigned_abs());
+    if micros < 0 {
+        UNIX_EPOCH - d
+    } else {
+        UNIX_EPOCH + d
+    }
+}
+
+/// `i64::MAX - micros`, so that newer times sort first. Times before the
+/// epoch all map to `i64::MAX`.
+pub fn reverse_timestamp(t: SystemTime) -> i64 {
+    i64::MAX - timestamp_micros(t).max(0)
+}
+
+/// Inverse of `reverse_timestamp`.
+pub fn from_reverse_timestamp(reversed: i64) -> SystemTime {
+    from_timestamp_micros(i64::MAX - reversed.max(0))
+}
+
+/// Builds an order-preserving composite row key.
+///
+/// ```ignore
+/// let key = KeyBuilder::new()
+///     .string("user")
+///     .int64(42)
+///     .reverse_timestamp(SystemTime::now())
+///     .build();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct KeyBuilder {
+    fields: Vec<Vec<u8>>,
+}
+
+impl KeyBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn bytes(mut self, v: &[u8]) -> Self {
+        self.fields.push(v.to_vec());
+        self
+    }
+
+    pub fn string(self, v: &str) -> Self {
+        self.bytes(v.as_bytes())
+    }
+
+    pub fn int64(mut self, v: i64) -> Self {
+        self.fields.push(encode_ordered_i64(v));
+        self
+    }
+
+    /// Microseconds since the epoch, as an INT64.
+    pub fn timestamp(self, t: SystemTime) -> Self {
+        self.int64(timestamp_micros(t))
+    }
+
+    /// A timestamp that sorts newest first.
+    pub fn reverse_timestamp(self, t: SystemTime) -> Self {
+        self.int64(reverse_timestamp(t))
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        ordered_struct(&self.fields)
+    }
+
+    /// The keys of every row whose leading fields are the ones built so far,
+    /// e.g. all events of a user.
+    pub fn prefix_row_set(&self) -> RowSet {
+        let mut prefix = join_fields(&self.fields);
+        if !self.fields.is_empty() {
+            prefix.extend_from_slice(&[ESCAPE, SEPARATOR]);
+        }
+        let mut rows = RowSet::new();
+        rows.row_ranges.push(to_range(
+            Bound::Included(prefix.clone()),
+            prefix_end(&prefix),
+        ));
+        rows
+    }
+}
+
+// STRUCT `OrderedCodeBytes` of already encoded fields.
+pub(crate) fn ordered_struct(fields: &[Vec<u8>]) -> Vec<u8> {
+    match fields.iter().rposition(|f| !f.is_empty()) {
+        Some(last) => join_fields(&fields[..=last]),
+        None => vec![ESCAPE, EMPTY],
+    }
+}
+
+fn join_fields(fields: &[Vec<u8>]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            key.extend_from_slice(&[ESCAPE, SEPARATOR]);
+        }
+        if field.is_empty() {
+            key.extend_from_slice(&[ESCAPE, EMPTY]);
+        }
+        for &b in field {
+            key.push(b);
+            if b == ESCAPE {
+                key.push(NULL_BYTE);
+            }
+        }
+    }
+    key
+}
+
+/// Reads the fields of a composite key back in the order they were written.
+/// Fields past the end of the key read as empty, as trailing empty fields are
+/// not stored.
+///
+/// ```ignore
+/// let mut reader = KeyReader::new(&row.key)?;
+/// let (kind, id) = (reader.string()?, reader.int64()?);
+/// ```
+pub struct KeyReader {
+    fields: std::vec::IntoIter<Vec<u8>>,
+}
+
+impl KeyReader {
+    pub fn new(key: &[u8]) -> Result<Self, BTErr> {
+        Ok(KeyReader {
+            fields: split_fields(key)?.into_iter(),
+        })
+    }
+
+    pub fn bytes(&mut self) -> Vec<u8> {
+        self.fields.next().unwrap_or_default()
+    }
+
+    pub fn string(&mut self) -> Result<String, BTErr> {
+        String::from_utf8(self.bytes())
+            .map_err(|e| BTErr::DecodeErr(format!("key field is not UTF-8: {}", e)))
+    }
+
+    pub fn int64(&mut self) -> Result<i64, BTErr> {
+        let field = self.bytes();
+        match decode_ordered_i64(&field)? {
+            (v, len) if len == field.len() => Ok(v),
+            _ => Err(BTErr::DecodeErr(String::from(
+                "key field has bytes after its Int64",
+            ))),
+        }
+    }
+
+    pub fn timestamp(&mut self) -> Result<SystemTime, BTErr> {
+        self.int64().map(from_timestamp_micros)
+    }
+
+    pub fn reverse_timestamp(&mut self) -> Result<SystemTime, BTErr> {
+        self.int64().map(from_reverse_timestamp)
+    }
+
+    /// Whether every stored field has been read.
+    pub fn is_empty(&self) -> bool {
+        self.fields.len() == 0
+    }
+}
+
+pub(crate) fn split_fields(key: &[u8]) -> Result<Vec<Vec<u8>>, BTErr> {
+    if key == [ESCAPE, EMPTY] {
+        return Ok(Vec::new());
+    }
+    let mut fields = vec![Vec::new()];
+    let mut bytes = key.iter();
+    while let Some(&b) = bytes.next() {
+        if b != ESCAPE {
+            fields.last_mut().unwrap().push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(&NULL_BYTE) => fields.last_mut().unwrap().push(ESCAPE),
+            Some(&SEPARATOR) => fields.push(Vec::new()),
+            Some(&EMPTY) if fields.last().is_some_and(|f| f.is_empty()) => {}
+            _ => {
+                return Err(BTErr::DecodeErr(String::from(
+                    "invalid escape in composite key",
+                )))
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// Spreads sequential keys over `buckets` key ranges by prefixing them with a
+/// hash bucket, written as a zero-padded number and `#` (e.g. `07#`).
+///
+/// ```ignore
+/// let salt = Salt::new(16);
+/// let key = salt.salt(b"2024-06-01#sensor-1");
+/// let rows = salt.scan(&table, &token, Bound::Included(b"2024-06-01"), Bound::Unbounded).collect()?;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Salt {
+    buckets: u32,
+    width: usize,
+}
+
+impl Salt {
+    pub fn new(buckets: u32) -> Self {
+        let buckets = buckets.max(1);
+        Salt {
+            buckets,
+            width: (buckets - 1).to_string().len(),
+        }
+    }
+
+    pub fn buckets(&self) -> u32 {
+        self.buckets
+    }
+
+    /// Bucket of `key`, from its CRC32C.
+    pub fn bucket(&self, key: &[u8]) -> u32 {
+        crc32c::crc32c(key) % self.buckets
+    }
+
+    pub fn prefix(&self, bucket: u32) -> Vec<u8> {
+        format!("{:0width$}#", bucket, width = self.width).into_bytes()
+    }
+
+    pub fn salt(&self, key: &[u8]) -> Vec<u8> {
+        let mut salted = self.prefix(self.bucket(key));
+        salted.extend_from_slice(key);
+        salted
+    }
+
+    /// Strips the salt from a salted key.
+    pub fn unsalt<'k>(&self, salted: &'k [u8]) -> Result<&'k [u8], BTErr> {
+        let key = salted
+            .get(self.width + 1..)
+            .ok_or_else(|| BTErr::DecodeErr(String::from("key is shorter than its salt")))?;
+        if salted.get(self.width) != Some(&b'#') || self.salt(key) != salted {
+            return Err(BTErr::DecodeErr(String::from("key is not salted")));
+        }
+        Ok(key)
+    }
+
+    /// Unsalted keys between `start` and `end`, as one range per bucket.
+    pub fn row_set(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> RowSet {
+        let mut rows = RowSet::new();
+        for bucket in 0..self.buckets {
+            let prefix = self.prefix(bucket);
+            let salted = |key: &[u8]| [prefix.as_slice(), key].concat();
+            let lo = match start {
+                Bound::Included(k) => Bound::Included(salted(k)),
+                Bound::Excluded(k) => Bound::Excluded(salted(k)),
+                Bound::Unbounded => Bound::Included(prefix.clone()),
+            };
+            let hi = match end {
+                Bound::Included(k) => Bound::Included(salted(k)),
+                Bound::Excluded(k) => Bound::Excluded(salted(k)),
+                Bound::Unbounded => prefix_end(&prefix),
+            };
+            rows.row_ranges.push(to_range(lo, hi));
+        }
+        rows
+    }
+
+    /// A parallel scan over `start..end` in every bucket. Rows come back in
+    /// salted order; `unsalted_order` restores the order of the original keys.
+    pub fn scan<'a>(
+        &self,
+        table: &Table,
+        token: &'a Token,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> ParallelScan<'a> {
+        ParallelScan::new(table, token)
+            .rows(self.row_set(start, end))
+            .concurrency(self.buckets.min(16) as usize)
+    }
+
+    /// Sorts rows read from several buckets by their unsalted keys.
+    pub fn unsalted_order(&self, rows: &mut [Row]) {
+        let width = self.width + 1;
+        rows.sort_by(|a, b| a.key.get(width..).cmp(&b.key.get(width..)));
+    }
+}
@@ -6,10 +6,26 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+pub mod aggregate;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod changestream;
+pub mod client;
+pub mod convert;
+#[cfg(feature = "datafusion")]
+pub mod datafusion;
+pub mod encoding;
 pub mod error;
+pub mod filters;
+pub mod keys;
+pub mod lock;
+pub mod mapping;
 pub mod method;
 pub mod protos;
+pub mod query;
 pub mod request;
+pub mod rows;
+pub mod scan;
 pub mod support;
 pub mod utils;
 pub mod wraps;
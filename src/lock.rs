@@ -0,0 +1,215 @@
+// AIDEV-NOTE: Leases live in one row per lease name. The `owner` cell is written
+// with its timestamp set to the expiry time, so "held and unexpired" is a single
+// CheckAndMutateRow predicate on that cell. `fence` is a ReadModifyWriteRow
+// counter in the same row that hands out monotonically increasing fencing tokens.
+//
+// Expiry is judged against the caller's clock; clients must keep clocks in sync
+// to well within the lease TTL.
+use crate::error::BTErr;
+use crate::filters;
+use crate::protos::data::RowFilter;
+use crate::request::{CurlTransport, Transport};
+use crate::support::Table;
+use crate::utils::encode_i64;
+use crate::wraps::{check_and_mutate_via, ReadModifyWrite, RowMutation};
+use goauth::auth::Token;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const OWNER: &[u8] = b"owner";
+const EXPIRES: &[u8] = b"expires";
+const FENCE: &[u8] = b"fence";
+
+/// Table (and column family) holding leases.
+///
+/// ```ignore
+/// use bigtable::lock::{Lease, LeaseTable};
+/// use std::time::Duration;
+///
+/// let leases = LeaseTable::new(&table, &token).family("lease");
+/// if let Some(mut lease) = Lease::acquire(&leases, b"jobs/compaction", b"worker-1", Duration::from_secs(30))? {
+///     do_work(lease.fencing_token);
+///     lease.renew(&leases, Duration::from_secs(30))?;
+///     lease.release(&leases)?;
+/// }
+/// ```
+pub struct LeaseTable<'a> {
+    table: Table,
+    token: &'a Token,
+    family: String,
+    transport: &'a dyn Transport,
+}
+
+impl<'a> LeaseTable<'a> {
+    pub fn new(table: &Table, token: &'a Token) -> Self {
+        LeaseTable {
+            table: table.clone(),
+            token,
+            family: String::from("lease"),
+            transport: &CurlTransport,
+        }
+    }
+
+    /// Column family used for lease cells (default `lease`). It should keep
+    /// at least one version and have no garbage collection by age.
+    pub fn family(mut self, family: &str) -> Self {
+        self.family = String::from(family);
+        self
+    }
+
+    pub fn transport(mut self, transport: &'a dyn Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    fn check_and_mutate(
+        &self,
+        name: &[u8],
+        predicate: RowFilter,
+        on_match: RowMutation,
+        on_no_match: RowMutation,
+    ) -> Result<bool, BTErr> {
+        check_and_mutate_via(
+            self.transport,
+            &self.table,
+            self.token,
+            name,
+            predicate,
+            on_match,
+            on_no_match,
+        )
+    }
+}
+
+/// A held lease. Pass `fencing_token` along with any write guarded by the lease
+/// so stale holders can be rejected downstream.
+#[derive(Clone, Debug)]
+pub struct Lease {
+    pub name: Vec<u8>,
+    pub owner: Vec<u8>,
+    pub fencing_token: i64,
+    pub expires_at_micros: i64,
+}
+
+impl Lease {
+    /// Takes the lease if it is free, expired, or already held by `owner`.
+    /// Returns `None` while another owner holds an unexpired lease.
+    pub fn acquire(
+        table: &LeaseTable,
+        name: &[u8],
+        owner: &[u8],
+        ttl: Duration,
+    ) -> Result<Option<Lease>, BTErr> {
+        let now = now_micros();
+        let expires_at = expiry(now, ttl);
+
+        // Yields a cell only if someone else holds an unexpired lease.
+        let held_by_other = filters::condition(
+            owned_by(&table.family, owner),
+            filters::block_all(),
+            filters::chain(vec![
+                filters::column(&table.family, OWNER),
+                filters::latest(),
+                filters::timestamp_range(now, 0),
+            ]),
+        );
+
+        let held = table.check_and_mutate(
+            name,
+            held_by_other,
+            RowMutation::new(),
+            write_lease(&table.family, owner, expires_at),
+        )?;
+        if held {
+            return Ok(None);
+        }
+
+        let fencing_token = ReadModifyWrite::new(name)
+            .increment(&table.family, FENCE, 1)
+            .execute_via(table.transport, &table.table, table.token)?
+            .int(&table.family, FENCE)?
+            .ok_or_else(|| BTErr::DecodeErr(String::from("missing fencing token")))?;
+
+        let mut lease = Lease {
+            name: name.to_vec(),
+            owner: owner.to_vec(),
+            fencing_token,
+            expires_at_micros: expires_at,
+        };
+
+        // Another owner may have taken over between the write and the increment;
+        // confirming ownership afterwards keeps fencing tokens ordered by holder.
+        if !lease.renew(table, ttl)? {
+            return Ok(None);
+        }
+        Ok(Some(lease))
+    }
+
+    /// Extends the lease by `ttl` from now. Returns `false` if it was lost or expired.
+    pub fn renew(&mut self, table: &LeaseTable, ttl: Duration) -> Result<bool, BTErr> {
+        let now = now_micros();
+        let expires_at = expiry(now, ttl);
+
+        let still_held = filters::chain(vec![
+            filters::column(&table.family, OWNER),
+            filters::latest(),
+            filters::timestamp_range(now, 0),
+            filters::value_equals(&self.owner),
+        ]);
+
+        let renewed = table.check_and_mutate(
+            &self.name,
+            still_held,
+            write_lease(&table.family, &self.owner, expires_at),
+            RowMutation::new(),
+        )?;
+        if renewed {
+            self.expires_at_micros = expires_at;
+        }
+        Ok(renewed)
+    }
+
+    /// Gives the lease up. Returns `false` if another owner had already taken it.
+    pub fn release(self, table: &LeaseTable) -> Result<bool, BTErr> {
+        table.check_and_mutate(
+            &self.name,
+            owned_by(&table.family, &self.owner),
+            RowMutation::new()
+                .delete_cells(&table.family, OWNER)
+                .delete_cells(&table.family, EXPIRES),
+            RowMutation::new(),
+        )
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now_micros() >= self.expires_at_micros
+    }
+}
+
+fn owned_by(family: &str, owner: &[u8]) -> RowFilter {
+    filters::chain(vec![
+        filters::column(family, OWNER),
+        filters::latest(),
+        filters::value_equals(owner),
+    ])
+}
+
+fn write_lease(family: &str, owner: &[u8], expires_at: i64) -> RowMutation {
+    RowMutation::new()
+        .delete_cells(family, OWNER)
+        .delete_cells(family, EXPIRES)
+        .set_cell_at(family, OWNER, expires_at, owner)
+        .set_cell_at(family, EXPIRES, expires_at, &encode_i64(expires_at))
+}
+
+fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+// Bigtable tables default to millisecond timestamp granularity.
+fn expiry(now: i64, ttl: Duration) -> i64 {
+    let expires_at = now + ttl.as_micros() as i64;
+    expires_at - expires_at % 1000
+}
@@ -0,0 +1,199 @@
+// AIDEV-NOTE: Mapping between Rust types and rows. `BigtableRow` is usually
+// derived (`bigtable_derive`, re-exported here with the `derive` feature); the
+// generated code only uses the items below plus `rows::latest_cell`, so they are
+// part of the macro's contract. A codec is any module with
+// `encode(&T) -> Result<Vec<u8>, BTErr>` and `decode(&[u8]) -> Result<T, BTErr>`.
+use crate::error::BTErr;
+use crate::protos::bigtable::mutate_rows_request;
+use crate::protos::data::{Cell, Row};
+use crate::wraps::RowMutation;
+use std::convert::TryFrom;
+use std::time::SystemTime;
+
+#[cfg(feature = "derive")]
+pub use bigtable_derive::BigtableRow;
+
+/// A type stored as one row.
+///
+/// ```ignore
+/// wraps::mutate_row(&table, &token, &user.row_key()?, user.to_mutations()?)?;
+/// let user = wraps::read_row(&table, &token, b"user#1", None)?
+///     .map(|row| User::from_row(&row))
+///     .transpose()?;
+/// ```
+pub trait BigtableRow: Sized {
+    fn row_key(&self) -> Result<Vec<u8>, BTErr>;
+
+    /// Mutations writing every mapped column; `None` fields delete theirs, and
+    /// versioned fields replace every cell of theirs, so versions removed from
+    /// the field are deleted.
+    fn to_mutations(&self) -> Result<RowMutation, BTErr>;
+
+    fn from_row(row: &Row) -> Result<Self, BTErr>;
+
+    /// The row as a `MutateRows` entry.
+    fn to_entry(&self) -> Result<mutate_rows_request::Entry, BTErr> {
+        let mut entry = mutate_rows_request::Entry::new();
+        entry.row_key = self.row_key()?;
+        entry.mutations = self.to_mutations()?.mutations;
+        Ok(entry)
+    }
+}
+
+/// Values with a default cell encoding: bytes and strings as-is, integers and
+/// floats big-endian (as Bigtable counters), bools as one byte, and times as
+/// big-endian microseconds since the epoch.
+pub trait CellValue: Sized {
+    fn to_cell(&self) -> Vec<u8>;
+    fn from_cell(bytes: &[u8]) -> Result<Self, BTErr>;
+}
+
+/// Cell timestamps of versioned fields (`Vec<(Ts, T)>`). `SystemTime` is
+/// truncated to milliseconds; `i64` microseconds are written as given.
+pub trait CellTimestamp {
+    fn to_micros(&self) -> i64;
+    fn from_micros(micros: i64) -> Self;
+}
+
+/// The default codec, using `CellValue`.
+pub mod cell {
+    use super::CellValue;
+    use crate::error::BTErr;
+
+    pub fn encode<T: CellValue>(value: &T) -> Result<Vec<u8>, BTErr> {
+        Ok(value.to_cell())
+    }
+
+    pub fn decode<T: CellValue>(bytes: &[u8]) -> Result<T, BTErr> {
+        T::from_cell(bytes)
+    }
+}
+
+/// Stores values as JSON, for `#[bigtable(with = "bigtable::mapping::json")]`.
+pub mod json {
+    use crate::error::BTErr;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    /// Fails for values JSON cannot represent, such as maps with non-string keys.
+    pub fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BTErr> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BTErr> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Every cell of `family:qualifier`, newest first.
+pub fn cells<'a>(row: &'a Row, family: &str, qualifier: &[u8]) -> Vec<&'a Cell> {
+    let mut cells: Vec<&Cell> = row
+        .families
+        .iter()
+        .filter(|f| f.name == family)
+        .flat_map(|f| f.columns.iter())
+        .filter(|c| c.qualifier == qualifier)
+        .flat_map(|c| c.cells.iter())
+        .collect();
+    cells.sort_by_key(|c| std::cmp::Reverse(c.timestamp_micros));
+    cells
+}
+
+#[doc(hidden)]
+pub fn missing_cell(column: &str) -> BTErr {
+    BTErr::DecodeErr(format!("row has no cell in {}", column))
+}
+
+fn fixed<const N: usize>(bytes: &[u8], what: &str) -> Result<[u8; N], BTErr> {
+    <[u8; N]>::try_from(bytes).map_err(|_| {
+        BTErr::DecodeErr(format!(
+            "expected {} byte {}, got {} bytes",
+            N,
+            what,
+            bytes.len()
+        ))
+    })
+}
+
+impl CellValue for Vec<u8> {
+    fn to_cell(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_cell(bytes: &[u8]) -> Result<Self, BTErr> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl CellValue for String {
+    fn to_cell(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_cell(bytes: &[u8]) -> Result<Self, BTErr> {
+        Ok(std::str::from_utf8(bytes)?.to_string())
+    }
+}
+
+macro_rules! big_endian {
+    ($($t: ty),*) => {$(
+        impl CellValue for $t {
+            fn to_cell(&self) -> Vec<u8> {
+                self.to_be_bytes().to_vec()
+            }
+
+            fn from_cell(bytes: &[u8]) -> Result<Self, BTErr> {
+                Ok(<$t>::from_be_bytes(fixed(bytes, stringify!($t))?))
+            }
+        }
+    )*};
+}
+
+big_endian!(i64, u64, i32, u32, f64, f32);
+
+impl CellValue for bool {
+    fn to_cell(&self) -> Vec<u8> {
+        vec![u8::from(*self)]
+    }
+
+    fn from_cell(bytes: &[u8]) -> Result<Self, BTErr> {
+        match bytes {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(BTErr::DecodeErr(String::from("expected 1 byte bool"))),
+        }
+    }
+}
+
+impl CellValue for SystemTime {
+    fn to_cell(&self) -> Vec<u8> {
+        crate::keys::timestamp_micros(*self).to_cell()
+    }
+
+    fn from_cell(bytes: &[u8]) -> Result<Self, BTErr> {
+        i64::from_cell(bytes).map(SystemTime::from_micros)
+    }
+}
+
+// Bigtable tables default to millisecond timestamp granularity and reject
+// cell timestamps in between, so times are truncated to the millisecond.
+impl CellTimestamp for SystemTime {
+    fn to_micros(&self) -> i64 {
+        let micros = crate::keys::timestamp_micros(*self);
+        micros - micros.rem_euclid(1000)
+    }
+
+    fn from_micros(micros: i64) -> Self {
+        crate::keys::from_timestamp_micros(micros)
+    }
+}
+
+impl CellTimestamp for i64 {
+    fn to_micros(&self) -> i64 {
+        *self
+    }
+
+    fn from_micros(micros: i64) -> Self {
+        micros
+    }
+}
@@ -116,7 +116,7 @@ macro_rules! method {
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: ReadRows::new()
 ///     };
 ///     let response = req.execute(&get_auth_token("credentials.json", true)?)?;
@@ -139,7 +139,7 @@ method!(ReadRows, ReadRowsRequest, true);
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: SampleRowKeys::new()
 ///     };
 ///     let response = req.execute(&get_auth_token("credentials.json", true)?)?;
@@ -163,7 +163,7 @@ method!(SampleRowKeys, SampleRowKeysRequest, false);
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let mut req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: MutateRow::new()
 ///     };
 ///
@@ -197,7 +197,7 @@ method!(MutateRow, MutateRowRequest, true);
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let mut req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: MutateRows::new()
 ///     };
 ///
@@ -233,7 +233,7 @@ method!(MutateRows, MutateRowsRequest, true);
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let mut req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: CheckAndMutateRow::new()
 ///     };
 ///
@@ -270,7 +270,7 @@ method!(CheckAndMutateRow, CheckAndMutateRowRequest, true);
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let mut req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: ReadModifyWriteRow::new()
 ///     };
 ///
@@ -309,7 +309,7 @@ method!(ReadModifyWriteRow, ReadModifyWriteRowRequest, true);
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: PingAndWarm::new()
 ///     };
 ///     let response = req.execute(&get_auth_token("credentials.json", true)?)?;
@@ -334,7 +334,7 @@ method!(PingAndWarm, PingAndWarmRequest, true, UrlScope::Instance, ":ping");
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: GenerateInitialChangeStreamPartitions::new()
 ///     };
 ///     let response = req.execute(&get_auth_token("credentials.json", true)?)?;
@@ -358,7 +358,7 @@ method!(GenerateInitialChangeStreamPartitions, GenerateInitialChangeStreamPartit
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: ReadChangeStream::new()
 ///     };
 ///     let response = req.execute(&get_auth_token("credentials.json", true)?)?;
@@ -383,7 +383,7 @@ method!(ReadChangeStream, ReadChangeStreamRequest, true);
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let mut req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: PrepareQuery::new()
 ///     };
 ///     req.method.payload_mut().query = String::from("SELECT * FROM my_table");
@@ -409,7 +409,7 @@ method!(PrepareQuery, PrepareQueryRequest, true, UrlScope::Instance);
 /// fn wrapper() -> Result<(), BTErr> {
 ///     let mut req = BTRequest {
 ///         base: None,
-///         table: Default::default(),
+///         table: "projects/my-project/instances/my-instance/tables/my-table".parse()?,
 ///         method: ExecuteQuery::new()
 ///     };
 ///     req.method.payload_mut().query = String::from("SELECT * FROM my_table");
@@ -0,0 +1,420 @@
+// AIDEV-NOTE: ExecuteQuery streams rows as serialized `ProtoRows` batches spread
+// over `PartialResultSet` messages. Fragments are buffered until a message
+// carries `batch_checksum` (CRC32C of the whole batch), which marks the batch
+// complete; complete batches are held back until a message carries a
+// `resume_token`, which commits everything received so far. `reset` drops
+// whatever was received since the last commit. Values are flat: every
+// `columns.len()` values form one row.
+//
+// Only committed rows reach the caller, so a stream that breaks on a retryable
+// error is re-sent with the last `resume_token` and the uncommitted remainder
+// is dropped; the server continues right after the commit.
+use crate::convert::{decode, FromValue, SqlType};
+use crate::error::BTErr;
+use crate::method::{BigTable, ExecuteQuery, PrepareQuery};
+use crate::protos::bigtable::{
+    execute_query_response, prepare_query_request, ExecuteQueryRequest, ExecuteQueryResponse,
+    PrepareQueryResponse,
+};
+use crate::protos::data::{
+    partial_result_set, result_set_metadata, ColumnMetadata, PartialResultSet, ProtoFormat,
+    ProtoRows, ResultSetMetadata, Value,
+};
+use crate::protos::types::Type;
+use crate::request::{parse_message, BTRequest, CurlTransport, Transport};
+use crate::support::{Instance, Table};
+use goauth::auth::Token;
+use protobuf::Message;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+mod de;
+mod prepared;
+
+pub use self::de::from_row;
+pub use self::prepared::PreparedStatement;
+
+/// One result row; values are in column order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryRow {
+    pub values: Vec<Value>,
+}
+
+/// A decoded query result.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<ColumnMetadata>,
+    pub rows: Vec<QueryRow>,
+}
+
+impl ResultSet {
+    /// Position of the column called `name`.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    /// Value of column `name` in row `row`.
+    pub fn value(&self, row: usize, name: &str) -> Option<&Value> {
+        let column = self.column(name)?;
+        self.rows.get(row).and_then(|r| r.values.get(column))
+    }
+
+    /// Reads column `name` of row `row` as `T`, checked against the column's
+    /// type.
+    pub fn get<T: FromValue + SqlType>(&self, row: usize, name: &str) -> Result<T, BTErr> {
+        let column = self
+            .column(name)
+            .ok_or_else(|| BTErr::TypeErr(format!("no column named {}", name)))?;
+        let value = self
+            .rows
+            .get(row)
+            .ok_or_else(|| {
+                BTErr::TypeErr(format!(
+                    "row {} is out of range for {} rows",
+                    row,
+                    self.rows.len()
+                ))
+            })?
+            .values
+            .get(column)
+            .ok_or_else(|| BTErr::TypeErr(format!("row {} has no column {}", row, name)))?;
+        decode(value, &self.columns[column].type_)
+    }
+}
+
+/// Turns a stream of `ExecuteQueryResponse`s into rows.
+#[derive(Default)]
+pub struct ResultDecoder {
+    columns: Option<Vec<ColumnMetadata>>,
+    // fragments of the batch being received
+    batch: Vec<u8>,
+    // values of complete batches that are not committed yet
+    pending: Vec<Value>,
+    resume_token: Vec<u8>,
+}
+
+impl ResultDecoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Decoder for a prepared query, whose metadata came with `PrepareQuery`.
+    pub fn with_metadata(metadata: &ResultSetMetadata) -> Result<Self, BTErr> {
+        let mut decoder = ResultDecoder::new();
+        decoder.columns = Some(columns(metadata)?);
+        Ok(decoder)
+    }
+
+    /// Columns of the result, once known.
+    pub fn columns(&self) -> Option<&[ColumnMetadata]> {
+        self.columns.as_deref()
+    }
+
+    /// Token of the last commit; empty before the first one.
+    pub fn resume_token(&self) -> &[u8] {
+        &self.resume_token
+    }
+
+    /// Feeds one message, returning the rows it commits.
+    pub fn push(&mut self, response: ExecuteQueryResponse) -> Result<Vec<QueryRow>, BTErr> {
+        match response.response {
+            Some(execute_query_response::Response::Metadata(metadata)) => {
+                self.columns = Some(columns(&metadata)?);
+                Ok(Vec::new())
+            }
+            Some(execute_query_response::Response::Results(results)) => self.push_results(results),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Drops everything received since the last commit, e.g. before the
+    /// stream is resumed from `resume_token`.
+    pub fn reset(&mut self) {
+        self.batch.clear();
+        self.pending.clear();
+    }
+
+    /// Checks that the stream did not end with uncommitted data.
+    pub fn finish(&self) -> Result<(), BTErr> {
+        if !self.batch.is_empty() || !self.pending.is_empty() {
+            return Err(invalid("stream ended with uncommitted rows"));
+        }
+        Ok(())
+    }
+
+    fn push_results(&mut self, results: PartialResultSet) -> Result<Vec<QueryRow>, BTErr> {
+        if results.reset {
+            self.reset();
+        }
+        match results.partial_rows {
+            Some(partial_result_set::Partial_rows::ProtoRowsBatch(batch)) => {
+                self.batch.extend_from_slice(&batch.batch_data)
+            }
+            None => {}
+        }
+
+        if let Some(checksum) = results.batch_checksum {
+            if crc32c::crc32c(&self.batch) != checksum {
+                return Err(invalid("batch checksum mismatch"));
+            }
+            let rows = ProtoRows::parse_from_bytes(&self.batch)?;
+            self.pending.extend(rows.values);
+            self.batch.clear();
+        }
+
+        if results.resume_token.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !self.batch.is_empty() {
+            return Err(invalid("resume token in the middle of a batch"));
+        }
+        let width = match &self.columns {
+            Some(columns) => columns.len(),
+            None => return Err(invalid("results before metadata")),
+        };
+        if self.pending.is_empty() {
+            self.resume_token = results.resume_token;
+            return Ok(Vec::new());
+        }
+        if width == 0 || !self.pending.len().is_multiple_of(width) {
+            return Err(invalid("committed values do not fill whole rows"));
+        }
+        self.resume_token = results.resume_token;
+        let mut values = std::mem::take(&mut self.pending).into_iter();
+        let mut rows = Vec::new();
+        while values.len() > 0 {
+            rows.push(QueryRow {
+                values: values.by_ref().take(width).collect(),
+            });
+        }
+        Ok(rows)
+    }
+}
+
+fn columns(metadata: &ResultSetMetadata) -> Result<Vec<ColumnMetadata>, BTErr> {
+    match &metadata.schema {
+        Some(result_set_metadata::Schema::ProtoSchema(schema)) => Ok(schema.columns.clone()),
+        _ => Err(invalid("result set metadata has no proto schema")),
+    }
+}
+
+fn invalid(msg: &str) -> BTErr {
+    BTErr::DecodeErr(format!("invalid ExecuteQuery response: {}", msg))
+}
+
+/// Runs a GoogleSQL query without parameters and collects its result.
+///
+/// ```ignore
+/// let result = query::execute_query(&instance, &token, "SELECT _key, cf1 FROM orders LIMIT 10")?;
+/// for row in &result.rows {
+///     println!("{:?}", row.values[0]);
+/// }
+/// ```
+pub fn execute_query(instance: &Instance, token: &Token, query: &str) -> Result<ResultSet, BTErr> {
+    Query::new(instance, token, query).execute()
+}
+
+/// Runs a GoogleSQL query without parameters and deserializes its rows into `T`.
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Order {
+///     _key: Vec<u8>,
+///     total: Option<i64>,
+/// }
+///
+/// let orders: Vec<Order> = query::query_as(&instance, &token, "SELECT _key, total FROM orders")?;
+/// ```
+pub fn query_as<T: DeserializeOwned>(
+    instance: &Instance,
+    token: &Token,
+    query: &str,
+) -> Result<Vec<T>, BTErr> {
+    let result = execute_query(instance, token, query)?;
+    result.deserialize()
+}
+
+/// A GoogleSQL query, prepared and executed in one go.
+pub struct Query<'a> {
+    instance: Table,
+    token: &'a Token,
+    query: String,
+    retries: Retries,
+    transport: &'a dyn Transport,
+}
+
+impl<'a> Query<'a> {
+    pub fn new(instance: &Instance, token: &'a Token, query: &str) -> Self {
+        Query {
+            instance: instance_table(instance),
+            token,
+            query: String::from(query),
+            retries: Default::default(),
+            transport: &CurlTransport,
+        }
+    }
+
+    /// How many times a broken stream is resumed before giving up (default 3).
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.retries.max_retries = max_retries;
+        self
+    }
+
+    /// App profile to run the query with.
+    pub fn app_profile(mut self, app_profile_id: &str) -> Self {
+        self.instance.app_profile_id = String::from(app_profile_id);
+        self
+    }
+
+    /// Wait before the first resume, doubled for each further one (default 100ms).
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retries.delay = delay;
+        self
+    }
+
+    pub fn transport(mut self, transport: &'a dyn Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn execute(self) -> Result<ResultSet, BTErr> {
+        let mut rows = Vec::new();
+        let columns = self.run(|row| rows.push(row))?;
+        Ok(ResultSet { columns, rows })
+    }
+
+    /// Calls `f` with every row once it is committed, so rows are never repeated
+    /// when the stream is resumed.
+    pub fn for_each<F: FnMut(QueryRow)>(self, f: F) -> Result<(), BTErr> {
+        self.run(f).map(|_| ())
+    }
+
+    fn run<F: FnMut(QueryRow)>(self, mut f: F) -> Result<Vec<ColumnMetadata>, BTErr> {
+        let prepared = prepare_query_via(
+            self.transport,
+            &self.instance,
+            self.token,
+            &self.query,
+            &HashMap::new(),
+        )?;
+        let mut request = ExecuteQueryRequest::new();
+        request.prepared_query = prepared.prepared_query;
+        let decoder = ResultDecoder::with_metadata(&prepared.metadata)?;
+        run_query_via(
+            self.transport,
+            &self.instance,
+            self.token,
+            request,
+            decoder,
+            &self.retries,
+            &mut f,
+        )
+    }
+}
+
+/// Resumption limits for a query stream.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Retries {
+    pub(crate) max_retries: u32,
+    pub(crate) delay: Duration,
+}
+
+impl Default for Retries {
+    fn default() -> Self {
+        Retries {
+            max_retries: 3,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
+
+pub(crate) fn prepare_query_via(
+    transport: &dyn Transport,
+    instance: &Table,
+    token: &Token,
+    query: &str,
+    param_types: &HashMap<String, Type>,
+) -> Result<PrepareQueryResponse, BTErr> {
+    let mut req = BTRequest {
+        base: None,
+        table: instance.clone(),
+        method: PrepareQuery::new(),
+    };
+    let payload = req.method.payload_mut();
+    payload.query = String::from(query);
+    payload.param_types = param_types.clone();
+    payload.data_format = Some(prepare_query_request::Data_format::ProtoFormat(
+        ProtoFormat::new(),
+    ));
+    parse_message(&req.execute_with(token, transport)?)
+}
+
+/// Sends `request` and hands committed rows to `f`, resuming the stream after
+/// retryable failures. Returns the result columns.
+pub(crate) fn run_query_via(
+    transport: &dyn Transport,
+    instance: &Table,
+    token: &Token,
+    mut request: ExecuteQueryRequest,
+    mut decoder: ResultDecoder,
+    retries: &Retries,
+    f: &mut dyn FnMut(QueryRow),
+) -> Result<Vec<ColumnMetadata>, BTErr> {
+    let mut attempt = 0;
+    loop {
+        request.resume_token = decoder.resume_token().to_vec();
+        match stream_query_via(transport, instance, token, &request, &mut decoder, f) {
+            Ok(()) => break,
+            Err(e) if e.is_retryable() && attempt < retries.max_retries => {
+                debug!("Resuming query after: {}", e);
+                decoder.reset();
+                thread::sleep(retries.delay * 2u32.saturating_pow(attempt));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(decoder.columns.unwrap_or_default())
+}
+
+// Messages before a mid-stream error are still decoded, so their committed rows
+// are kept and the next attempt resumes after them.
+fn stream_query_via(
+    transport: &dyn Transport,
+    instance: &Table,
+    token: &Token,
+    request: &ExecuteQueryRequest,
+    decoder: &mut ResultDecoder,
+    f: &mut dyn FnMut(QueryRow),
+) -> Result<(), BTErr> {
+    let mut req = BTRequest {
+        base: None,
+        table: instance.clone(),
+        method: ExecuteQuery::new(),
+    };
+    req.method.set_payload(request.clone());
+    let messages = match req.execute_with(token, transport)? {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Null => Vec::new(),
+        other => vec![other],
+    };
+    for message in &messages {
+        for row in decoder.push(parse_message(message)?)? {
+            f(row);
+        }
+    }
+    decoder.finish()
+}
+
+// Query methods are instance-level; the table is named in the SQL. The `Table`
+// only carries the instance and app profile.
+pub(crate) fn instance_table(instance: &Instance) -> Table {
+    Table {
+        instance: instance.clone(),
+        name: String::new(),
+        app_profile_id: String::new(),
+        view: None,
+    }
+}
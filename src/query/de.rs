@@ -0,0 +1,343 @@
+// AIDEV-NOTE: Rows deserialize as maps from column name to value, so structs
+// pick their fields by name (tuples take the columns in order). The column type
+// decides how an `ArrayValue` is read: STRUCT as a map of field names, MAP as a
+// map of its `[key, value]` pairs, ARRAY as a sequence. TIMESTAMP and DATE read
+// as maps shaped like `SystemTime` and `convert::Date`. NULL reads as `None`.
+use super::{QueryRow, ResultSet};
+use crate::error::BTErr;
+use crate::protos::data::{value, ColumnMetadata, Value};
+use crate::protos::types::{type_, Type};
+use crate::utils::from_timestamp;
+use protobuf::Message;
+use serde::de::value::{BorrowedStrDeserializer, MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, Deserialize, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess,
+    Visitor,
+};
+use std::time::UNIX_EPOCH;
+
+impl de::Error for BTErr {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        BTErr::DecodeErr(format!("cannot deserialize row: {}", msg))
+    }
+}
+
+/// Deserializes `row`, whose values are described by `columns`.
+pub fn from_row<'de, T: Deserialize<'de>>(
+    columns: &'de [ColumnMetadata],
+    row: &'de QueryRow,
+) -> Result<T, BTErr> {
+    if columns.len() != row.values.len() {
+        return Err(de::Error::custom(format!(
+            "{} columns but {} values",
+            columns.len(),
+            row.values.len()
+        )));
+    }
+    T::deserialize(RowDeserializer { columns, row })
+}
+
+impl ResultSet {
+    /// Deserializes every row into `T`, e.g. a `#[derive(Deserialize)]` struct
+    /// with a field per column.
+    pub fn deserialize<'de, T: Deserialize<'de>>(&'de self) -> Result<Vec<T>, BTErr> {
+        self.iter_as().collect()
+    }
+
+    /// Deserializes the rows one at a time.
+    pub fn iter_as<'de, T: Deserialize<'de>>(
+        &'de self,
+    ) -> impl Iterator<Item = Result<T, BTErr>> + 'de {
+        self.rows
+            .iter()
+            .map(move |row| from_row(&self.columns, row))
+    }
+}
+
+struct RowDeserializer<'de> {
+    columns: &'de [ColumnMetadata],
+    row: &'de QueryRow,
+}
+
+impl<'de> Deserializer<'de> for RowDeserializer<'de> {
+    type Error = BTErr;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BTErr> {
+        visitor.visit_map(NamedValues {
+            fields: self
+                .columns
+                .iter()
+                .zip(&self.row.values)
+                .map(|(c, v)| (c.name.as_str(), ValueDeserializer::new(v, &c.type_)))
+                .collect::<Vec<_>>()
+                .into_iter(),
+            pending: None,
+        })
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BTErr> {
+        visitor.visit_seq(Elements {
+            elements: self
+                .columns
+                .iter()
+                .zip(&self.row.values)
+                .map(|(c, v)| ValueDeserializer::new(v, &c.type_))
+                .collect::<Vec<_>>()
+                .into_iter(),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, BTErr> {
+        self.deserialize_seq(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ValueDeserializer<'de> {
+    value: &'de Value,
+    ty: &'de Type,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn new(value: &'de Value, ty: &'de Type) -> Self {
+        ValueDeserializer { value, ty }
+    }
+
+    fn elements(self, values: &'de [Value]) -> Elements<'de> {
+        let element_type: &'de Type = match &self.ty.kind {
+            Some(type_::Kind::ArrayType(array)) => &array.element_type,
+            _ => Type::default_instance(),
+        };
+        let elements: Vec<_> = match &self.ty.kind {
+            Some(type_::Kind::StructType(st)) => values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    let ty: &'de Type = st
+                        .fields
+                        .get(i)
+                        .map_or(Type::default_instance(), |f| &f.type_);
+                    ValueDeserializer::new(v, ty)
+                })
+                .collect(),
+            _ => values
+                .iter()
+                .map(|v| ValueDeserializer::new(v, element_type))
+                .collect(),
+        };
+        Elements {
+            elements: elements.into_iter(),
+        }
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = BTErr;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BTErr> {
+        use value::Kind as K;
+        let kind = match &self.value.kind {
+            Some(kind) => kind,
+            None => return visitor.visit_none(),
+        };
+        match kind {
+            K::StringValue(s) => visitor.visit_borrowed_str(s),
+            K::BytesValue(b) | K::RawValue(b) => visitor.visit_borrowed_bytes(b),
+            K::IntValue(i) | K::RawTimestampMicros(i) => visitor.visit_i64(*i),
+            K::FloatValue(f) => match self.ty.kind {
+                Some(type_::Kind::Float32Type(_)) => visitor.visit_f32(*f as f32),
+                _ => visitor.visit_f64(*f),
+            },
+            K::BoolValue(b) => visitor.visit_bool(*b),
+            K::TimestampValue(ts) => {
+                // serde's SystemTime cannot hold times before the epoch
+                let d = from_timestamp(ts).duration_since(UNIX_EPOCH).map_err(|_| {
+                    <BTErr as de::Error>::custom(
+                        "TIMESTAMP before the epoch cannot be deserialized as SystemTime",
+                    )
+                })?;
+                let fields = vec![
+                    ("secs_since_epoch", d.as_secs()),
+                    ("nanos_since_epoch", u64::from(d.subsec_nanos())),
+                ];
+                visitor.visit_map(MapDeserializer::new(fields.into_iter()))
+            }
+            K::DateValue(date) => {
+                let fields = vec![
+                    ("year", date.year),
+                    ("month", date.month),
+                    ("day", date.day),
+                ];
+                visitor.visit_map(MapDeserializer::new(fields.into_iter()))
+            }
+            K::ArrayValue(array) => match &self.ty.kind {
+                Some(type_::Kind::StructType(st)) => visitor.visit_map(NamedValues {
+                    fields: st
+                        .fields
+                        .iter()
+                        .zip(&array.values)
+                        .map(|(f, v)| (f.field_name.as_str(), ValueDeserializer::new(v, &f.type_)))
+                        .collect::<Vec<_>>()
+                        .into_iter(),
+                    pending: None,
+                }),
+                Some(type_::Kind::MapType(map)) => {
+                    let mut entries = Vec::new();
+                    for entry in &array.values {
+                        match &entry.kind {
+                            Some(K::ArrayValue(pair)) if pair.values.len() == 2 => entries.push((
+                                ValueDeserializer::new(&pair.values[0], &map.key_type),
+                                ValueDeserializer::new(&pair.values[1], &map.value_type),
+                            )),
+                            _ => {
+                                return Err(de::Error::custom(
+                                    "map entry is not a [key, value] pair",
+                                ))
+                            }
+                        }
+                    }
+                    visitor.visit_map(MapDeserializer::new(entries.into_iter()))
+                }
+                _ => visitor.visit_seq(self.elements(&array.values)),
+            },
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BTErr> {
+        match self.value.kind {
+            None => visitor.visit_none(),
+            Some(_) => visitor.visit_some(self),
+        }
+    }
+
+    // `Vec<u8>` fields ask for a sequence; BYTES values hand out their bytes.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BTErr> {
+        match &self.value.kind {
+            Some(value::Kind::BytesValue(b)) | Some(value::Kind::RawValue(b)) => {
+                visitor.visit_seq(SeqDeserializer::new(b.iter().copied()))
+            }
+            Some(value::Kind::ArrayValue(array)) => visitor.visit_seq(self.elements(&array.values)),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, BTErr> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, BTErr> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, BTErr> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // Unit variants are read from their name.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, BTErr> {
+        match &self.value.kind {
+            Some(value::Kind::StringValue(s)) => {
+                visitor.visit_enum(IntoDeserializer::<BTErr>::into_deserializer(s.as_str()))
+            }
+            _ => Err(de::Error::custom("enums are read from STRING values")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, BTErr> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+struct Elements<'de> {
+    elements: std::vec::IntoIter<ValueDeserializer<'de>>,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'de> {
+    type Error = BTErr;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, BTErr> {
+        match self.elements.next() {
+            Some(element) => seed.deserialize(element).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.len())
+    }
+}
+
+// Columns of a row or fields of a STRUCT, keyed by name.
+struct NamedValues<'de> {
+    fields: std::vec::IntoIter<(&'de str, ValueDeserializer<'de>)>,
+    pending: Option<ValueDeserializer<'de>>,
+}
+
+impl<'de> MapAccess<'de> for NamedValues<'de> {
+    type Error = BTErr;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, BTErr> {
+        match self.fields.next() {
+            Some((name, value)) => {
+                self.pending = Some(value);
+                seed.deserialize(BorrowedStrDeserializer::new(name))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, BTErr> {
+        match self.pending.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("value requested before its key")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
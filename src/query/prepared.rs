@@ -0,0 +1,204 @@
+// AIDEV-NOTE: A prepared query is only valid until `valid_until`, and the
+// service may drop the plan earlier (a FAILED_PRECONDITION whose details carry
+// a PREPARED_QUERY_EXPIRED violation). Either way the statement is prepared
+// again with the same query and parameter types, which keeps the bindings
+// valid. An expired plan is retried once, and only while no row has been
+// handed out, so a re-prepared run never repeats rows.
+use super::{
+    instance_table, prepare_query_via, run_query_via, QueryRow, ResultDecoder, ResultSet, Retries,
+};
+use crate::convert::{check_value, ToValue};
+use crate::error::BTErr;
+use crate::protos::bigtable::ExecuteQueryRequest;
+use crate::protos::data::{ColumnMetadata, ResultSetMetadata, Value};
+use crate::protos::types::Type;
+use crate::request::{CurlTransport, Transport};
+use crate::support::{Instance, Table};
+use crate::utils::from_timestamp;
+use goauth::auth::Token;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+const PRECONDITION_FAILURE: &str = "type.googleapis.com/google.rpc.PreconditionFailure";
+
+struct Plan {
+    prepared_query: Vec<u8>,
+    metadata: ResultSetMetadata,
+    valid_until: Option<SystemTime>,
+}
+
+/// A GoogleSQL query with typed `@name` parameters, prepared once and executed
+/// with different bindings.
+///
+/// ```ignore
+/// let mut stmt = PreparedStatement::new(&instance, &token, "SELECT * FROM orders WHERE _key = @key")
+///     .param("key", bytes_type)
+///     .prepare()?;
+/// stmt.bind("key", key_value)?;
+/// let result = stmt.execute()?;
+/// ```
+pub struct PreparedStatement<'a> {
+    instance: Table,
+    token: &'a Token,
+    query: String,
+    param_types: HashMap<String, Type>,
+    params: HashMap<String, Value>,
+    plan: Option<Plan>,
+    retries: Retries,
+    transport: &'a dyn Transport,
+}
+
+impl<'a> PreparedStatement<'a> {
+    pub fn new(instance: &Instance, token: &'a Token, query: &str) -> Self {
+        PreparedStatement {
+            instance: instance_table(instance),
+            token,
+            query: String::from(query),
+            param_types: HashMap::new(),
+            params: HashMap::new(),
+            plan: None,
+            retries: Default::default(),
+            transport: &CurlTransport,
+        }
+    }
+
+    /// Declares the type of parameter `@name`.
+    pub fn param(mut self, name: &str, ty: Type) -> Self {
+        self.param_types.insert(String::from(name), ty);
+        self
+    }
+
+    /// App profile to prepare and run the query with.
+    pub fn app_profile(mut self, app_profile_id: &str) -> Self {
+        self.instance.app_profile_id = String::from(app_profile_id);
+        self
+    }
+
+    pub fn transport(mut self, transport: &'a dyn Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Prepares the query on the service.
+    pub fn prepare(mut self) -> Result<Self, BTErr> {
+        self.refresh()?;
+        Ok(self)
+    }
+
+    /// Binds `value` to parameter `@name`, checking it against the declared
+    /// type. `None` (or a `Value` without a kind) binds NULL.
+    pub fn bind<T: ToValue>(&mut self, name: &str, value: T) -> Result<&mut Self, BTErr> {
+        let ty = self
+            .param_types
+            .get(name)
+            .ok_or_else(|| BTErr::TypeErr(format!("parameter @{} is not declared", name)))?;
+        let mut value = value.to_value();
+        check_value(&value, ty).map_err(|e| match e {
+            BTErr::TypeErr(e) => BTErr::TypeErr(format!("parameter @{}: {}", name, e)),
+            e => e,
+        })?;
+        value.type_ = Some(ty.clone()).into();
+        self.params.insert(String::from(name), value);
+        Ok(self)
+    }
+
+    pub fn clear_bindings(&mut self) {
+        self.params.clear();
+    }
+
+    /// Runs the query with the current bindings and collects its result.
+    pub fn execute(&mut self) -> Result<ResultSet, BTErr> {
+        let mut rows = Vec::new();
+        let columns = self.run(&mut |row| rows.push(row))?;
+        Ok(ResultSet { columns, rows })
+    }
+
+    /// Calls `f` with every row once it is committed.
+    pub fn for_each<F: FnMut(QueryRow)>(&mut self, mut f: F) -> Result<(), BTErr> {
+        self.run(&mut f).map(|_| ())
+    }
+
+    fn run(&mut self, f: &mut dyn FnMut(QueryRow)) -> Result<Vec<ColumnMetadata>, BTErr> {
+        if let Some(name) = self
+            .param_types
+            .keys()
+            .find(|n| !self.params.contains_key(*n))
+        {
+            return Err(BTErr::TypeErr(format!("parameter @{} is not bound", name)));
+        }
+        if self.expired() {
+            self.refresh()?;
+        }
+
+        let mut delivered = false;
+        let mut reprepared = false;
+        loop {
+            let plan = self.plan.as_ref().unwrap();
+            let mut request = ExecuteQueryRequest::new();
+            request.prepared_query = plan.prepared_query.clone();
+            request.params = self.params.clone();
+            let decoder = ResultDecoder::with_metadata(&plan.metadata)?;
+            let result = run_query_via(
+                self.transport,
+                &self.instance,
+                self.token,
+                request,
+                decoder,
+                &self.retries,
+                &mut |row| {
+                    delivered = true;
+                    f(row)
+                },
+            );
+            match result {
+                Err(e) if self.plan_expired(&e) && !delivered && !reprepared => {
+                    debug!("Preparing query again after: {}", e);
+                    self.refresh()?;
+                    reprepared = true;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn expired(&self) -> bool {
+        match &self.plan {
+            Some(plan) => plan.valid_until.is_some_and(|t| t <= SystemTime::now()),
+            None => true,
+        }
+    }
+
+    // The service names an expired plan with a PREPARED_QUERY_EXPIRED
+    // violation; without one, a FAILED_PRECONDITION counts only once
+    // valid_until has passed.
+    fn plan_expired(&self, err: &BTErr) -> bool {
+        match err {
+            BTErr::ApiErr(e) if e.status == "FAILED_PRECONDITION" => {
+                e.details
+                    .iter()
+                    .filter(|d| d["@type"] == PRECONDITION_FAILURE)
+                    .filter_map(|d| d["violations"].as_array())
+                    .flatten()
+                    .any(|v| v["type"] == "PREPARED_QUERY_EXPIRED")
+                    || self.expired()
+            }
+            _ => false,
+        }
+    }
+
+    fn refresh(&mut self) -> Result<(), BTErr> {
+        let response = prepare_query_via(
+            self.transport,
+            &self.instance,
+            self.token,
+            &self.query,
+            &self.param_types,
+        )?;
+        self.plan = Some(Plan {
+            prepared_query: response.prepared_query,
+            metadata: response.metadata.unwrap_or_default(),
+            valid_until: response.valid_until.as_ref().map(from_timestamp),
+        });
+        Ok(())
+    }
+}
@@ -1,13 +1,16 @@
 use curl::easy::{Easy, List};
-use crate::error::BTErr;
+use crate::error::{ApiError, BTErr};
 use goauth::auth::Token;
-use crate::method::{BigTable, ReadRows, UrlScope};
+use crate::method::{BigTable, UrlScope};
+use protobuf::reflect::{FieldDescriptor, ReflectValueBox, ReflectValueRef};
+use protobuf::MessageFull;
 use protobuf_json_mapping;
+use protobuf_json_mapping::ParseOptions;
 use serde_json;
 use serde_json::Value;
 use std;
 use std::io::Read;
-use crate::support::Table;
+use crate::support::{Table, View};
 
 pub struct BTRequest<'a, T: BigTable> {
     pub base: Option<&'a str>,
@@ -15,58 +18,194 @@ pub struct BTRequest<'a, T: BigTable> {
     pub method: T,
 }
 
-impl<'a> Default for BTRequest<'a, ReadRows> {
-    fn default() -> Self {
-        BTRequest {
-            base: None,
-            table: Default::default(),
-            method: ReadRows::new(),
+impl<'a, T: BigTable> BTRequest<'a, T> {
+    /// Resource the method acts on: `projects/{p}/instances/{i}/tables/{t}` or
+    /// the table's view, or the instance for instance-level methods.
+    pub fn resource_name(&self) -> String {
+        match self.method.url_scope() {
+            UrlScope::Table => self.table.to_string(),
+            UrlScope::Instance => self.table.instance.to_string(),
         }
     }
-}
 
-impl<'a, T: BigTable> BTRequest<'a, T> {
-    // AIDEV-NOTE: form_url handles both table-level and instance-level API methods
+    // AIDEV-NOTE: form_url handles both table-level and instance-level API methods.
+    // Views are only addressed in the URL; REST binds the request's view name field
+    // from the path. A method whose request has no such field cannot use the view.
     pub fn form_url(&self) -> Result<String, BTErr> {
-        let base = match self.base {
-            Some(x) => x,
-            None => "https://bigtable.googleapis.com/v2",
+        if let (UrlScope::Table, Some(view)) = (self.method.url_scope(), &self.table.view) {
+            if T::M::descriptor().field_by_name(view.field_name()).is_none() {
+                return Err(BTErr::TypeErr(format!(
+                    "{} cannot be sent to {:?}",
+                    self.method.url_method().trim_start_matches(':'),
+                    view
+                )));
+            }
+        }
+        let base = self.base.unwrap_or("https://bigtable.googleapis.com/v2");
+        Ok(format!(
+            "{}/{}{}",
+            base,
+            self.resource_name(),
+            self.method.url_method()
+        ))
+    }
+
+    /// App profile the request is sent with: the payload's own
+    /// `app_profile_id` if set, otherwise the table's.
+    pub fn app_profile_id(&self) -> String {
+        match app_profile_field::<T::M>() {
+            Some(field) => match field.get_singular_field_or_default(self.method.payload()) {
+                ReflectValueRef::String(id) if !id.is_empty() => String::from(id),
+                _ => self.table.app_profile_id.clone(),
+            },
+            None => String::new(),
+        }
+    }
+
+    /// Value of the `x-goog-request-params` header, naming the resource and
+    /// app profile so the service can route the request. Views route by the
+    /// part of their name the routing annotations extract: the table of an
+    /// authorized view, the instance of a materialized view.
+    pub fn routing_params(&self) -> String {
+        let (key, resource) = match (self.method.url_scope(), &self.table.view) {
+            (UrlScope::Table, None) => ("table_name", self.resource_name()),
+            (UrlScope::Table, Some(View::AuthorizedView(_))) => (
+                "table_name",
+                format!("{}/tables/{}", self.table.instance, self.table.name),
+            ),
+            (UrlScope::Table, Some(View::MaterializedView(_))) => {
+                ("name", self.table.instance.to_string())
+            }
+            (UrlScope::Instance, _) => ("name", self.resource_name()),
         };
-        match self.method.url_scope() {
-            UrlScope::Table => Ok(format!(
-                "{}/projects/{}/instances/{}/tables/{}{}",
-                base,
-                self.table.instance.project.name,
-                self.table.instance.name,
-                self.table.name,
-                self.method.url_method()
-            )),
-            UrlScope::Instance => Ok(format!(
-                "{}/projects/{}/instances/{}{}",
-                base,
-                self.table.instance.project.name,
-                self.table.instance.name,
-                self.method.url_method()
-            )),
+        let mut params = format!("{}={}", key, url_encode(&resource));
+        let app_profile_id = self.app_profile_id();
+        if !app_profile_id.is_empty() {
+            params.push_str(&format!("&app_profile_id={}", url_encode(&app_profile_id)));
         }
+        params
     }
 
     pub fn execute(&self, token: &Token) -> Result<Value, BTErr> {
-        let mut response_data: Vec<u8> = Vec::new();
-        let mut easy = Easy::new();
+        self.execute_with(token, &CurlTransport)
+    }
 
+    /// Like `execute`, but sends the request through `transport`.
+    pub fn execute_with(&self, token: &Token, transport: &dyn Transport) -> Result<Value, BTErr> {
         // AIDEV-NOTE: protobuf 3.x uses print_to_string for JSON serialization
-        let s_payload = protobuf_json_mapping::print_to_string(self.method.payload())?;
-        let mut b_payload = s_payload.as_bytes();
+        let payload = self.method.payload();
+        let app_profile_id = self.app_profile_id();
+        let s_payload = match app_profile_field::<T::M>() {
+            Some(field)
+                if field.get_singular_field_or_default(payload)
+                    != ReflectValueRef::String(&app_profile_id) =>
+            {
+                let mut payload = payload.clone();
+                field.set_singular_field(&mut payload, ReflectValueBox::String(app_profile_id));
+                protobuf_json_mapping::print_to_string(&payload)?
+            }
+            _ => protobuf_json_mapping::print_to_string(payload)?,
+        };
+
+        let response_data = transport.send(
+            &self.form_url()?,
+            &gen_headers(token, &self.routing_params()),
+            s_payload.as_bytes(),
+            self.method.is_post(),
+        )?;
+
+        let response_str = std::str::from_utf8(&response_data)?;
+
+        debug!("Bytes transfered: {}", response_data.len());
+        debug!("Response: {}", response_str);
+
+        Ok(serde_json::from_str(response_str)?)
+    }
+
+    /// Executes a unary method and decodes the response into `R`.
+    pub fn execute_message<R: MessageFull>(&self, token: &Token) -> Result<R, BTErr> {
+        parse_message(&self.execute(token)?)
+    }
 
-        easy.url(&self.form_url()?)?;
+    /// Executes a server-streaming method and decodes every streamed message into `R`.
+    pub fn execute_stream<R: MessageFull>(&self, token: &Token) -> Result<Vec<R>, BTErr> {
+        parse_stream(&self.execute(token)?)
+    }
+}
+
+/// Turns an `{"error": {..}}` response body into `BTErr::ApiErr`.
+///
+/// Streaming methods report failures as the last element of the response array.
+pub fn check_response(response: &Value) -> Result<(), BTErr> {
+    let error = match response {
+        Value::Array(items) => items.iter().find_map(|x| x.get("error")),
+        _ => response.get("error"),
+    };
+    match error {
+        Some(e) => Err(BTErr::ApiErr(serde_json::from_value::<ApiError>(e.clone())?)),
+        None => Ok(()),
+    }
+}
+
+/// Decodes a unary JSON response into its protobuf message.
+pub fn parse_message<R: MessageFull>(response: &Value) -> Result<R, BTErr> {
+    check_response(response)?;
+    // AIDEV-NOTE: the service may return fields newer than our vendored protos
+    let options = ParseOptions {
+        ignore_unknown_fields: true,
+        ..Default::default()
+    };
+    Ok(protobuf_json_mapping::parse_from_str_with_options(
+        &response.to_string(),
+        &options,
+    )?)
+}
+
+/// Decodes a streaming JSON response (an array of messages) into protobuf messages.
+pub fn parse_stream<R: MessageFull>(response: &Value) -> Result<Vec<R>, BTErr> {
+    check_response(response)?;
+    match response {
+        Value::Array(items) => items.iter().map(parse_message).collect(),
+        Value::Null => Ok(Vec::new()),
+        _ => Ok(vec![parse_message(response)?]),
+    }
+}
+
+// AIDEV-NOTE: Transport lets higher-level APIs run against something other than
+// the live endpoint, e.g. an in-memory stand-in in tests.
+/// Sends a serialized request and returns the raw response body.
+pub trait Transport: Send + Sync {
+    fn send(&self, url: &str, headers: &[String], body: &[u8], post: bool)
+        -> Result<Vec<u8>, BTErr>;
+}
+
+/// Default `Transport`, talking HTTPS to the Bigtable REST endpoint via curl.
+pub struct CurlTransport;
 
-        if self.method.is_post() {
+impl Transport for CurlTransport {
+    fn send(
+        &self,
+        url: &str,
+        headers: &[String],
+        body: &[u8],
+        post: bool,
+    ) -> Result<Vec<u8>, BTErr> {
+        let mut response_data: Vec<u8> = Vec::new();
+        let mut b_payload = body;
+        let mut easy = Easy::new();
+
+        easy.url(url)?;
+
+        if post {
             easy.post(true)?;
             easy.post_field_size(b_payload.len() as u64)?;
         }
 
-        easy.http_headers(gen_headers(token)?)?;
+        let mut list = List::new();
+        for header in headers {
+            list.append(header)?;
+        }
+        easy.http_headers(list)?;
 
         {
             let mut transfer = easy.transfer();
@@ -82,23 +221,34 @@ impl<'a, T: BigTable> BTRequest<'a, T> {
             transfer.perform()?;
         }
 
-        let response_str = std::str::from_utf8(&response_data)?;
+        Ok(response_data)
+    }
+}
 
-        debug!("Bytes transfered: {}", response_data.len());
-        debug!("Response: {}", response_str);
+// Every Data API request message has an `app_profile_id` field.
+fn app_profile_field<M: MessageFull>() -> Option<FieldDescriptor> {
+    M::descriptor().field_by_name("app_profile_id")
+}
 
-        Ok(serde_json::from_str(response_str)?)
-    }
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
 }
 
-fn gen_headers(token: &Token) -> Result<List, BTErr> {
-    let mut list = List::new();
-    let auth = format!(
-        "Authorization: {} {}",
-        token.token_type(),
-        token.access_token()
-    );
-    list.append(&auth)?;
-    list.append("Content-Type: application/json")?;
-    Ok(list)
+fn gen_headers(token: &Token, routing_params: &str) -> Vec<String> {
+    vec![
+        format!(
+            "Authorization: {} {}",
+            token.token_type(),
+            token.access_token()
+        ),
+        String::from("Content-Type: application/json"),
+        format!("x-goog-request-params: {}", routing_params),
+    ]
 }
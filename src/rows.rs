@@ -0,0 +1,200 @@
+// AIDEV-NOTE: ReadRows streams rows as CellChunks. A row may span several
+// chunks (and several responses), and a single cell value may be split across
+// chunks using value_size. RowMerger rebuilds complete `data::Row`s from them.
+use crate::error::BTErr;
+use crate::protos::bigtable::read_rows_response::CellChunk;
+use crate::protos::bigtable::ReadRowsResponse;
+use crate::protos::data::{Cell, Column, Family, Row};
+
+#[derive(Default)]
+pub struct RowMerger {
+    row: Option<Row>,
+    family: String,
+    qualifier: Vec<u8>,
+    cell: Option<Cell>,
+    last_key: Option<Vec<u8>>,
+    reversed: bool,
+}
+
+impl RowMerger {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Merger for a `ReadRowsRequest` with `reversed` set, where keys arrive in descending order.
+    pub fn reversed() -> Self {
+        RowMerger {
+            reversed: true,
+            ..Default::default()
+        }
+    }
+
+    /// Feeds one chunk, returning the row it completes, if any.
+    pub fn push(&mut self, chunk: CellChunk) -> Result<Option<Row>, BTErr> {
+        if chunk.reset_row() {
+            return self.reset(&chunk).map(|_| None);
+        }
+
+        if self.cell.is_some() {
+            if !chunk.row_key.is_empty() || chunk.family_name.is_some() || chunk.qualifier.is_some()
+            {
+                return Err(invalid(
+                    "new cell started before previous value was complete",
+                ));
+            }
+        } else {
+            self.start_cell(&chunk)?;
+        }
+
+        let cell = self.cell.get_or_insert_with(Cell::new);
+        cell.value.extend_from_slice(&chunk.value);
+        if chunk.value_size > 0 {
+            if chunk.commit_row() {
+                return Err(invalid("row committed in the middle of a split value"));
+            }
+            return Ok(None);
+        }
+        self.finish_cell();
+
+        if chunk.commit_row() {
+            let row = self
+                .row
+                .take()
+                .ok_or_else(|| invalid("commit without a row"))?;
+            self.last_key = Some(row.key.clone());
+            return Ok(Some(row));
+        }
+        Ok(None)
+    }
+
+    /// Checks that the stream did not end in the middle of a row.
+    pub fn finish(&self) -> Result<(), BTErr> {
+        match self.row {
+            Some(_) => Err(invalid("stream ended with an uncommitted row")),
+            None => Ok(()),
+        }
+    }
+
+    /// Key of the last committed row, used to resume an interrupted scan.
+    pub fn last_key(&self) -> Option<&[u8]> {
+        self.last_key.as_deref()
+    }
+
+    fn reset(&mut self, chunk: &CellChunk) -> Result<(), BTErr> {
+        if self.row.is_none() {
+            return Err(invalid("reset_row without a row in progress"));
+        }
+        if !chunk.row_key.is_empty()
+            || chunk.family_name.is_some()
+            || chunk.qualifier.is_some()
+            || !chunk.value.is_empty()
+            || chunk.timestamp_micros != 0
+        {
+            return Err(invalid("reset_row chunk carries data"));
+        }
+        self.row = None;
+        self.cell = None;
+        Ok(())
+    }
+
+    fn start_cell(&mut self, chunk: &CellChunk) -> Result<(), BTErr> {
+        match self.row {
+            None => {
+                if chunk.row_key.is_empty() {
+                    return Err(invalid("new row is missing a row key"));
+                }
+                if chunk.family_name.is_none() || chunk.qualifier.is_none() {
+                    return Err(invalid("new row is missing a family or qualifier"));
+                }
+                if let Some(last) = &self.last_key {
+                    let in_order = if self.reversed {
+                        chunk.row_key < *last
+                    } else {
+                        chunk.row_key > *last
+                    };
+                    if !in_order {
+                        return Err(invalid("row keys are out of order"));
+                    }
+                }
+                let mut row = Row::new();
+                row.key = chunk.row_key.clone();
+                self.row = Some(row);
+            }
+            Some(ref row) => {
+                if !chunk.row_key.is_empty() && chunk.row_key != row.key {
+                    return Err(invalid("row key changed without a commit"));
+                }
+            }
+        }
+
+        if let Some(family) = chunk.family_name.as_ref() {
+            if chunk.qualifier.is_none() {
+                return Err(invalid("new family is missing a qualifier"));
+            }
+            self.family = family.value.clone();
+        }
+        if let Some(qualifier) = chunk.qualifier.as_ref() {
+            self.qualifier = qualifier.value.clone();
+        }
+
+        let mut cell = Cell::new();
+        cell.timestamp_micros = chunk.timestamp_micros;
+        cell.labels = chunk.labels.clone();
+        if chunk.value_size > 0 {
+            cell.value.reserve(chunk.value_size as usize);
+        }
+        self.cell = Some(cell);
+        Ok(())
+    }
+
+    fn finish_cell(&mut self) {
+        let (row, cell) = match (self.row.as_mut(), self.cell.take()) {
+            (Some(row), Some(cell)) => (row, cell),
+            _ => return,
+        };
+
+        if row.families.last().map(|f| &f.name) != Some(&self.family) {
+            let mut family = Family::new();
+            family.name = self.family.clone();
+            row.families.push(family);
+        }
+        let family = row.families.last_mut().unwrap();
+
+        if family.columns.last().map(|c| &c.qualifier) != Some(&self.qualifier) {
+            let mut column = Column::new();
+            column.qualifier = self.qualifier.clone();
+            family.columns.push(column);
+        }
+        family.columns.last_mut().unwrap().cells.push(cell);
+    }
+}
+
+/// Merges a complete `ReadRows` response stream into rows.
+pub fn merge_rows(responses: Vec<ReadRowsResponse>) -> Result<Vec<Row>, BTErr> {
+    let mut merger = RowMerger::new();
+    let mut rows = Vec::new();
+    for response in responses {
+        for chunk in response.chunks {
+            if let Some(row) = merger.push(chunk)? {
+                rows.push(row);
+            }
+        }
+    }
+    merger.finish()?;
+    Ok(rows)
+}
+
+/// Returns the newest cell of `family:qualifier`, if present.
+pub fn latest_cell<'a>(row: &'a Row, family: &str, qualifier: &[u8]) -> Option<&'a Cell> {
+    row.families
+        .iter()
+        .filter(|f| f.name == family)
+        .flat_map(|f| f.columns.iter())
+        .filter(|c| c.qualifier == qualifier)
+        .flat_map(|c| c.cells.iter())
+        .max_by_key(|c| c.timestamp_micros)
+}
+
+fn invalid(msg: &str) -> BTErr {
+    BTErr::DecodeErr(format!("invalid ReadRows chunk: {}", msg))
+}
@@ -0,0 +1,556 @@
+// AIDEV-NOTE: Parallel scans split the requested RowSet at keys returned by
+// SampleRowKeys, grouping adjacent samples by offset_bytes so shards hold roughly
+// equal amounts of data. Shards are contiguous and in key order, so key-ordered
+// delivery only has to emit shard i after shards before it have finished.
+// Each shard is read in pages of `page_size` rows, resuming after the last key.
+use crate::error::BTErr;
+use crate::protos::bigtable::SampleRowKeysResponse;
+use crate::protos::data::{row_range, Row, RowFilter, RowRange, RowSet};
+use crate::request::{CurlTransport, Transport};
+use crate::support::Table;
+use crate::wraps::{read_rows_via, sample_row_keys_via};
+use goauth::auth::Token;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+type Span = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// Reads `rows` (the whole table if empty) with `concurrency` parallel readers,
+/// returning rows in key order.
+///
+/// ```ignore
+/// let rows = bt::scan::parallel_scan(&table, &token, RowSet::new(), 8)?;
+/// ```
+pub fn parallel_scan(
+    table: &Table,
+    token: &Token,
+    rows: RowSet,
+    concurrency: usize,
+) -> Result<Vec<Row>, BTErr> {
+    ParallelScan::new(table, token)
+        .rows(rows)
+        .concurrency(concurrency)
+        .collect()
+}
+
+/// Configurable sharded scan.
+///
+/// ```ignore
+/// use bigtable::scan::ParallelScan;
+///
+/// ParallelScan::new(&table, &token)
+///     .rows(row_set)
+///     .filter(bt::filters::latest())
+///     .concurrency(16)
+///     .for_each_unordered(|row| export(row))?;
+/// ```
+pub struct ParallelScan<'a> {
+    table: Table,
+    token: &'a Token,
+    rows: RowSet,
+    filter: Option<RowFilter>,
+    concurrency: usize,
+    max_shards: usize,
+    page_size: i64,
+    limit: i64,
+    transport: &'a dyn Transport,
+}
+
+enum Msg {
+    Page(usize, Vec<Row>),
+    Done(usize),
+    Failed(BTErr),
+}
+
+impl<'a> ParallelScan<'a> {
+    pub fn new(table: &Table, token: &'a Token) -> Self {
+        ParallelScan {
+            table: table.clone(),
+            token,
+            rows: RowSet::new(),
+            filter: None,
+            concurrency: 4,
+            max_shards: 0,
+            page_size: 10_000,
+            limit: 0,
+            transport: &CurlTransport,
+        }
+    }
+
+    /// Rows to scan; an empty set scans the whole table.
+    pub fn rows(mut self, rows: RowSet) -> Self {
+        self.rows = rows;
+        self
+    }
+
+    pub fn filter(mut self, filter: RowFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Number of shards read at the same time (default 4).
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Upper bound on the number of shards (default four per reader, which
+    /// evens out shards that turn out slower than sampled).
+    pub fn max_shards(mut self, max_shards: usize) -> Self {
+        self.max_shards = max_shards;
+        self
+    }
+
+    /// Rows fetched per `ReadRows` request within a shard (default 10000, 0 for no limit).
+    pub fn page_size(mut self, page_size: i64) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Stops after `limit` rows (0 for no limit). Each shard reads at most
+    /// `limit` rows, so an ordered scan yields the first `limit` rows by key.
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = limit.max(0);
+        self
+    }
+
+    pub fn transport(mut self, transport: &'a dyn Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Samples the table and returns the shards the scan would read, in key order.
+    pub fn shards(&self) -> Result<Vec<RowSet>, BTErr> {
+        let samples = sample_row_keys_via(self.transport, &self.table, self.token)?;
+        let max_shards = match self.max_shards {
+            0 => self.concurrency * 4,
+            n => n,
+        };
+        Ok(split_row_set(&self.rows, &samples, max_shards))
+    }
+
+    /// Reads all rows, in key order.
+    pub fn collect(&self) -> Result<Vec<Row>, BTErr> {
+        let mut rows = Vec::new();
+        self.for_each(|row| rows.push(row))?;
+        Ok(rows)
+    }
+
+    /// Calls `f` with every row, in key order. Rows from later shards are
+    /// buffered until the shards before them finish.
+    pub fn for_each<F: FnMut(Row)>(&self, f: F) -> Result<(), BTErr> {
+        self.run(true, f)
+    }
+
+    /// Calls `f` with every row as soon as its page arrives. Rows within a shard
+    /// stay in key order, but shards are interleaved.
+    pub fn for_each_unordered<F: FnMut(Row)>(&self, f: F) -> Result<(), BTErr> {
+        self.run(false, f)
+    }
+
+    fn run<F: FnMut(Row)>(&self, ordered: bool, mut f: F) -> Result<(), BTErr> {
+        let shards = self.shards()?;
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let (tx, rx) = mpsc::channel();
+
+        thread::scope(|s| {
+            for _ in 0..self.concurrency.min(shards.len()) {
+                let tx = tx.clone();
+                let (shards, next, stop) = (&shards, &next, &stop);
+                s.spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= shards.len() {
+                            break;
+                        }
+                        let read = self.read_shard(&shards[i], stop, |page| {
+                            tx.send(Msg::Page(i, page)).is_ok()
+                        });
+                        let sent = match read {
+                            Ok(()) => tx.send(Msg::Done(i)),
+                            Err(e) => tx.send(Msg::Failed(e)),
+                        };
+                        if sent.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            let mut delivered = 0;
+            let mut limited = |row| {
+                if self.limit == 0 || delivered < self.limit {
+                    delivered += 1;
+                    f(row);
+                }
+            };
+            let result = deliver(rx, shards.len(), ordered, &mut limited);
+            stop.store(true, Ordering::Relaxed);
+            result
+        })
+    }
+
+    fn read_shard<S: FnMut(Vec<Row>) -> bool>(
+        &self,
+        shard: &RowSet,
+        stop: &AtomicBool,
+        mut send: S,
+    ) -> Result<(), BTErr> {
+        let mut rows = shard.clone();
+        let mut remaining = self.limit;
+        while !stop.load(Ordering::Relaxed) {
+            let page_size = match (self.page_size, remaining) {
+                (page_size, 0) => page_size,
+                (0, remaining) => remaining,
+                (page_size, remaining) => page_size.min(remaining),
+            };
+            let page = read_rows_via(
+                self.transport,
+                &self.table,
+                self.token,
+                rows.clone(),
+                self.filter.clone(),
+                page_size,
+            )?;
+            if remaining > 0 {
+                remaining -= page.len() as i64;
+                if remaining <= 0 {
+                    send(page);
+                    return Ok(());
+                }
+            }
+            let last = match page.last() {
+                Some(row) if page_size > 0 && page.len() as i64 >= page_size => {
+                    row.key.clone()
+                }
+                _ => {
+                    send(page);
+                    return Ok(());
+                }
+            };
+            if !send(page) {
+                return Ok(());
+            }
+            rows = match restrict(&rows, &Bound::Excluded(last), &Bound::Unbounded) {
+                Some(rest) => rest,
+                None => return Ok(()),
+            };
+        }
+        Ok(())
+    }
+}
+
+fn deliver<F: FnMut(Row)>(
+    rx: mpsc::Receiver<Msg>,
+    shards: usize,
+    ordered: bool,
+    f: &mut F,
+) -> Result<(), BTErr> {
+    let mut pending: Vec<Vec<Vec<Row>>> = (0..shards).map(|_| Vec::new()).collect();
+    let mut done = vec![false; shards];
+    let mut current = 0;
+
+    for msg in rx {
+        match msg {
+            Msg::Page(i, page) => {
+                if !ordered || i == current {
+                    page.into_iter().for_each(&mut *f);
+                } else {
+                    pending[i].push(page);
+                }
+            }
+            Msg::Done(i) => {
+                done[i] = true;
+                while current < shards && done[current] {
+                    current += 1;
+                    if current < shards {
+                        pending[current].drain(..).flatten().for_each(&mut *f);
+                    }
+                }
+            }
+            Msg::Failed(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// A condition on the row key, such as a query engine pushes down to a scan.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyPredicate {
+    Equals(Vec<u8>),
+    In(Vec<Vec<u8>>),
+    Range(Bound<Vec<u8>>, Bound<Vec<u8>>),
+    Prefix(Vec<u8>),
+}
+
+/// The RowSet of keys matching all of `predicates` (the whole table, as an empty
+/// set, if there are none), or `None` if no key can match.
+///
+/// ```ignore
+/// let rows = scan::key_row_set(&[
+///     KeyPredicate::Prefix(b"user#".to_vec()),
+///     KeyPredicate::Range(Bound::Included(b"user#m".to_vec()), Bound::Unbounded),
+/// ]);
+/// ```
+pub fn key_row_set(predicates: &[KeyPredicate]) -> Option<RowSet> {
+    let mut rows = RowSet::new();
+    for predicate in predicates {
+        rows = match predicate {
+            KeyPredicate::Equals(key) => keep_keys(&rows, std::slice::from_ref(key))?,
+            KeyPredicate::In(keys) => keep_keys(&rows, keys)?,
+            KeyPredicate::Range(start, end) => restrict(&rows, start, end)?,
+            KeyPredicate::Prefix(prefix) => restrict(
+                &rows,
+                &Bound::Included(prefix.clone()),
+                &prefix_end(prefix),
+            )?,
+        };
+    }
+    Some(rows)
+}
+
+// The keys of `keys` that `rows` contains, in order.
+fn keep_keys(rows: &RowSet, keys: &[Vec<u8>]) -> Option<RowSet> {
+    let whole_table = rows.row_keys.is_empty() && rows.row_ranges.is_empty();
+    let mut out = RowSet::new();
+    out.row_keys = keys
+        .iter()
+        .filter(|k| {
+            whole_table
+                || rows.row_keys.contains(k)
+                || rows.row_ranges.iter().any(|r| contains(&span(r), k))
+        })
+        .cloned()
+        .collect();
+    out.row_keys.sort();
+    out.row_keys.dedup();
+    if out.row_keys.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+// First key after every key starting with `prefix`.
+pub(crate) fn prefix_end(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Bound::Excluded(end);
+        }
+    }
+    Bound::Unbounded
+}
+
+/// Splits `rows` (the whole table if empty) into at most `max_shards` contiguous
+/// RowSets at sampled keys, so that each covers a similar number of sampled bytes.
+/// Shards are returned in key order and never empty.
+pub fn split_row_set(
+    rows: &RowSet,
+    samples: &[SampleRowKeysResponse],
+    max_shards: usize,
+) -> Vec<RowSet> {
+    // Bytes between consecutive samples, counted only where the scan reads.
+    let mut intervals = Vec::new();
+    let (mut prev_key, mut prev_offset) = (Vec::new(), 0);
+    for sample in samples {
+        let start = if prev_key.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Included(prev_key.clone())
+        };
+        let end = if sample.row_key.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded(sample.row_key.clone())
+        };
+        let bytes = match restrict(rows, &start, &end) {
+            Some(_) => (sample.offset_bytes - prev_offset).max(0),
+            None => 0,
+        };
+        intervals.push((prev_key, sample.row_key.clone(), bytes));
+        prev_key = sample.row_key.clone();
+        prev_offset = sample.offset_bytes;
+    }
+
+    // Close a shard before an interval that would overflow it, and after one
+    // that fills it.
+    let total: i64 = intervals.iter().map(|(_, _, bytes)| bytes).sum();
+    let target = total / max_shards.max(1) as i64;
+    let mut splits = Vec::new();
+    let mut acc = 0;
+    for (start, end, bytes) in intervals {
+        if acc > 0 && acc + bytes > target && !start.is_empty() && splits.len() + 1 < max_shards {
+            splits.push(start);
+            acc = 0;
+        }
+        acc += bytes;
+        if acc > 0 && acc >= target && !end.is_empty() && splits.len() + 1 < max_shards {
+            splits.push(end);
+            acc = 0;
+        }
+    }
+
+    let mut shards = Vec::new();
+    let mut lo = Bound::Unbounded;
+    for split in splits.into_iter().map(Some).chain(std::iter::once(None)) {
+        let hi = match split {
+            Some(ref key) => Bound::Excluded(key.clone()),
+            None => Bound::Unbounded,
+        };
+        if let Some(shard) = restrict(rows, &lo, &hi) {
+            shards.push(shard);
+        }
+        if let Some(key) = split {
+            lo = Bound::Included(key);
+        }
+    }
+    shards
+}
+
+/// The part of `rows` within `lo..hi`, or `None` if nothing is left. An empty
+/// `rows` means the whole table.
+pub(crate) fn restrict(rows: &RowSet, lo: &Bound<Vec<u8>>, hi: &Bound<Vec<u8>>) -> Option<RowSet> {
+    let mut out = RowSet::new();
+    out.row_keys = rows
+        .row_keys
+        .iter()
+        .filter(|k| contains(&(lo.clone(), hi.clone()), k))
+        .cloned()
+        .collect();
+
+    let spans: Vec<Span> = if rows.row_keys.is_empty() && rows.row_ranges.is_empty() {
+        vec![(Bound::Unbounded, Bound::Unbounded)]
+    } else {
+        rows.row_ranges.iter().map(span).collect()
+    };
+    for (start, end) in spans {
+        let start = max_start(start, lo.clone());
+        let end = min_end(end, hi.clone());
+        if !is_empty(&start, &end) {
+            out.row_ranges.push(to_range(start, end));
+        }
+    }
+
+    if out.row_keys.is_empty() && out.row_ranges.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+fn span(range: &RowRange) -> Span {
+    let start = match &range.start_key {
+        Some(row_range::Start_key::StartKeyClosed(k)) if !k.is_empty() => {
+            Bound::Included(k.clone())
+        }
+        Some(row_range::Start_key::StartKeyOpen(k)) => Bound::Excluded(k.clone()),
+        _ => Bound::Unbounded,
+    };
+    let end = match &range.end_key {
+        Some(row_range::End_key::EndKeyClosed(k)) if !k.is_empty() => Bound::Included(k.clone()),
+        Some(row_range::End_key::EndKeyOpen(k)) if !k.is_empty() => Bound::Excluded(k.clone()),
+        _ => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+pub(crate) fn to_range(start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> RowRange {
+    let mut range = RowRange::new();
+    range.start_key = match start {
+        Bound::Included(k) => Some(row_range::Start_key::StartKeyClosed(k)),
+        Bound::Excluded(k) => Some(row_range::Start_key::StartKeyOpen(k)),
+        Bound::Unbounded => None,
+    };
+    range.end_key = match end {
+        Bound::Included(k) => Some(row_range::End_key::EndKeyClosed(k)),
+        Bound::Excluded(k) => Some(row_range::End_key::EndKeyOpen(k)),
+        Bound::Unbounded => None,
+    };
+    range
+}
+
+fn contains((start, end): &Span, key: &[u8]) -> bool {
+    let after_start = match start {
+        Bound::Included(s) => key >= s.as_slice(),
+        Bound::Excluded(s) => key > s.as_slice(),
+        Bound::Unbounded => true,
+    };
+    let before_end = match end {
+        Bound::Included(e) => key <= e.as_slice(),
+        Bound::Excluded(e) => key < e.as_slice(),
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+fn max_start(a: Bound<Vec<u8>>, b: Bound<Vec<u8>>) -> Bound<Vec<u8>> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x), Bound::Included(y)) | (Bound::Excluded(x), Bound::Excluded(y)) => {
+            if x >= y {
+                a
+            } else {
+                b
+            }
+        }
+        (Bound::Included(x), Bound::Excluded(y)) => {
+            if x > y {
+                a
+            } else {
+                b
+            }
+        }
+        (Bound::Excluded(x), Bound::Included(y)) => {
+            if x >= y {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn min_end(a: Bound<Vec<u8>>, b: Bound<Vec<u8>>) -> Bound<Vec<u8>> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x), Bound::Included(y)) | (Bound::Excluded(x), Bound::Excluded(y)) => {
+            if x <= y {
+                a
+            } else {
+                b
+            }
+        }
+        (Bound::Included(x), Bound::Excluded(y)) => {
+            if x < y {
+                a
+            } else {
+                b
+            }
+        }
+        (Bound::Excluded(x), Bound::Included(y)) => {
+            if x <= y {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn is_empty(start: &Bound<Vec<u8>>, end: &Bound<Vec<u8>>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s), Bound::Excluded(e))
+        | (Bound::Excluded(s), Bound::Included(e))
+        | (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+        _ => false,
+    }
+}
@@ -1,42 +1,273 @@
-#[derive(Clone)]
+// AIDEV-NOTE: Resource names. Build them with `Project::new(..)?.instance(..)?.table(..)?`
+// or parse a full name (`"projects/p/instances/i/tables/t".parse::<Table>()?`); both
+// check IDs against Bigtable's rules so a misconfigured name fails before any request.
+// `Display` prints the full name, which request URLs and routing headers use.
+use crate::error::BTErr;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Project {
     pub name: String,
 }
 
-impl Default for Project {
-    fn default() -> Self {
-        Project {
-            name: String::from("rustbigtable"),
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instance {
+    pub project: Project,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Table {
+    pub instance: Instance,
+    pub name: String,
+    /// App profile routing every request on this table; empty for the
+    /// instance's default profile.
+    pub app_profile_id: String,
+    /// View requests are sent to instead of the table itself.
+    pub view: Option<View>,
+}
+
+/// A view serving requests made with a `Table`, for callers granted access to
+/// the view rather than the table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum View {
+    /// `tables/{table}/authorizedViews/{name}`: a subset of the table's rows and
+    /// columns. Supports reads, `SampleRowKeys` and mutations.
+    AuthorizedView(String),
+    /// `instances/{instance}/materializedViews/{name}`: the stored result of a
+    /// query, addressed without a table. Supports reads and `SampleRowKeys`.
+    MaterializedView(String),
+}
+
+impl View {
+    // Name of the request field addressing the view.
+    pub(crate) fn field_name(&self) -> &'static str {
+        match self {
+            View::AuthorizedView(_) => "authorized_view_name",
+            View::MaterializedView(_) => "materialized_view_name",
         }
     }
 }
 
-#[derive(Clone)]
-pub struct Instance {
-    pub project: Project,
-    pub name: String,
+impl Project {
+    /// The project `id`, e.g. `my-project` or a domain-scoped `example.com:my-project`.
+    pub fn new(id: &str) -> Result<Self, BTErr> {
+        check_project_id(id)?;
+        Ok(Project {
+            name: String::from(id),
+        })
+    }
+
+    pub fn instance(&self, id: &str) -> Result<Instance, BTErr> {
+        check_instance_id(id)?;
+        Ok(Instance {
+            project: self.clone(),
+            name: String::from(id),
+        })
+    }
+}
+
+impl Instance {
+    pub fn table(&self, id: &str) -> Result<Table, BTErr> {
+        check_table_id("table", id)?;
+        Ok(Table {
+            instance: self.clone(),
+            name: String::from(id),
+            app_profile_id: String::new(),
+            view: None,
+        })
+    }
+
+    /// The materialized view `name`, read with the `Table` APIs.
+    pub fn materialized_view(&self, name: &str) -> Result<Table, BTErr> {
+        check_table_id("materialized view", name)?;
+        Ok(Table {
+            instance: self.clone(),
+            name: String::new(),
+            app_profile_id: String::new(),
+            view: Some(View::MaterializedView(String::from(name))),
+        })
+    }
+}
+
+impl Table {
+    /// The authorized view `name` of this table.
+    pub fn authorized_view(mut self, name: &str) -> Result<Self, BTErr> {
+        check_table_id("authorized view", name)?;
+        self.view = Some(View::AuthorizedView(String::from(name)));
+        Ok(self)
+    }
+
+    /// Routes requests through `app_profile_id`, e.g. to keep batch jobs off
+    /// the clusters serving live traffic.
+    pub fn app_profile(mut self, app_profile_id: &str) -> Self {
+        self.app_profile_id = String::from(app_profile_id);
+        self
+    }
 }
 
-impl Default for Instance {
-    fn default() -> Self {
-        Instance {
-            name: String::from("test-inst"),
-            project: Default::default(),
+impl fmt::Display for Project {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "projects/{}", self.name)
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/instances/{}", self.project, self.name)
+    }
+}
+
+/// The table's name, or its view's when it has one.
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.view {
+            None => write!(f, "{}/tables/{}", self.instance, self.name),
+            Some(View::AuthorizedView(view)) => write!(
+                f,
+                "{}/tables/{}/authorizedViews/{}",
+                self.instance, self.name, view
+            ),
+            Some(View::MaterializedView(view)) => {
+                write!(f, "{}/materializedViews/{}", self.instance, view)
+            }
         }
     }
 }
 
-#[derive(Clone)]
-pub struct Table {
-    pub instance: Instance,
-    pub name: String,
+impl FromStr for Project {
+    type Err = BTErr;
+
+    /// Parses `projects/{project}`.
+    fn from_str(s: &str) -> Result<Self, BTErr> {
+        match segments(s).as_slice() {
+            ["projects", project] => Project::new(project),
+            _ => Err(malformed(s, "projects/{project}")),
+        }
+    }
+}
+
+impl FromStr for Instance {
+    type Err = BTErr;
+
+    /// Parses `projects/{project}/instances/{instance}`.
+    fn from_str(s: &str) -> Result<Self, BTErr> {
+        match segments(s).as_slice() {
+            ["projects", project, "instances", instance] => {
+                Project::new(project)?.instance(instance)
+            }
+            _ => Err(malformed(s, "projects/{project}/instances/{instance}")),
+        }
+    }
+}
+
+impl FromStr for Table {
+    type Err = BTErr;
+
+    /// Parses a table, `.../tables/{table}/authorizedViews/{view}` or
+    /// `.../instances/{instance}/materializedViews/{view}` name.
+    fn from_str(s: &str) -> Result<Self, BTErr> {
+        let parts = segments(s);
+        let instance = match parts.as_slice() {
+            ["projects", project, "instances", instance, ..] => {
+                Project::new(project)?.instance(instance)?
+            }
+            _ => {
+                return Err(malformed(
+                    s,
+                    "projects/{project}/instances/{instance}/tables/{table}",
+                ))
+            }
+        };
+        match &parts[4..] {
+            ["tables", table] => instance.table(table),
+            ["tables", table, "authorizedViews", view] => {
+                instance.table(table)?.authorized_view(view)
+            }
+            ["materializedViews", view] => instance.materialized_view(view),
+            _ => Err(malformed(
+                s,
+                "projects/{project}/instances/{instance}/tables/{table}",
+            )),
+        }
+    }
+}
+
+fn segments(s: &str) -> Vec<&str> {
+    s.split('/').collect()
+}
+
+fn malformed(s: &str, expected: &str) -> BTErr {
+    BTErr::ResourceErr(format!("expected {}, got {:?}", expected, s))
+}
+
+fn invalid(kind: &str, id: &str, rule: &str) -> BTErr {
+    BTErr::ResourceErr(format!("{} ID {:?} {}", kind, id, rule))
 }
 
-impl Default for Table {
-    fn default() -> Self {
-        Table {
-            name: String::from("my-table"),
-            instance: Default::default(),
+fn is_lower_id(id: &str) -> bool {
+    id.starts_with(|c: char| c.is_ascii_lowercase())
+        && !id.ends_with('-')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+// 6-30 characters: a lowercase letter, then lowercase letters, digits or
+// hyphens, not ending in a hyphen. Legacy projects are prefixed `domain:`.
+fn check_project_id(id: &str) -> Result<(), BTErr> {
+    let local = match id.rsplit_once(':') {
+        Some((domain, local)) => {
+            if domain.is_empty()
+                || !domain
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-')
+            {
+                return Err(invalid("project", id, "has an invalid domain prefix"));
+            }
+            local
         }
+        None => id,
+    };
+    if !(6..=30).contains(&local.len()) || !is_lower_id(local) {
+        return Err(invalid(
+            "project",
+            id,
+            "must be 6-30 lowercase letters, digits or hyphens, start with a letter \
+             and not end with a hyphen",
+        ));
+    }
+    Ok(())
+}
+
+// 6-33 characters, otherwise as a project ID.
+fn check_instance_id(id: &str) -> Result<(), BTErr> {
+    if !(6..=33).contains(&id.len()) || !is_lower_id(id) {
+        return Err(invalid(
+            "instance",
+            id,
+            "must be 6-33 lowercase letters, digits or hyphens, start with a letter \
+             and not end with a hyphen",
+        ));
+    }
+    Ok(())
+}
+
+// Tables and views: 1-50 letters, digits, `_`, `-` or `.`, not starting with
+// `-` or `.`.
+fn check_table_id(kind: &str, id: &str) -> Result<(), BTErr> {
+    let valid = (1..=50).contains(&id.len())
+        && !id.starts_with(['-', '.'])
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if !valid {
+        return Err(invalid(
+            kind,
+            id,
+            "must be 1-50 letters, digits, '_', '-' or '.', not starting with '-' or '.'",
+        ));
     }
+    Ok(())
 }
@@ -3,8 +3,10 @@ use goauth::auth::{JwtClaims, Token};
 use goauth::credentials::Credentials;
 use goauth::get_token;
 use goauth::scopes::Scope;
+use protobuf::well_known_types::timestamp::Timestamp;
 use smpl_jwt::Jwt;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::error::BTErr;
 
@@ -36,3 +38,53 @@ pub fn row_key_from_str(str: &str) -> Vec<u8> {
     row_key.extend_from_slice(str.as_bytes());
     row_key
 }
+
+/// Encodes `v` as the 8-byte big-endian two's complement value Bigtable uses
+/// for `Int64` cells (counters, aggregates, `ReadModifyWriteRow` increments).
+pub fn encode_i64(v: i64) -> Vec<u8> {
+    v.to_be_bytes().to_vec()
+}
+
+pub fn decode_i64(bytes: &[u8]) -> Result<i64, BTErr> {
+    let mut buf = [0u8; 8];
+    if bytes.len() != buf.len() {
+        return Err(BTErr::DecodeErr(format!(
+            "expected 8 byte big-endian Int64, got {} bytes",
+            bytes.len()
+        )));
+    }
+    buf.copy_from_slice(bytes);
+    Ok(i64::from_be_bytes(buf))
+}
+
+/// Converts to a protobuf `Timestamp`: `seconds` is negative before the epoch
+/// and `nanos` always counts forward from it.
+pub fn to_timestamp(t: SystemTime) -> Timestamp {
+    let mut ts = Timestamp::new();
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => {
+            ts.seconds = d.as_secs() as i64;
+            ts.nanos = d.subsec_nanos() as i32;
+        }
+        Err(e) => {
+            let d = e.duration();
+            ts.seconds = -(d.as_secs() as i64);
+            if d.subsec_nanos() > 0 {
+                ts.seconds -= 1;
+                ts.nanos = (1_000_000_000 - d.subsec_nanos()) as i32;
+            }
+        }
+    }
+    ts
+}
+
+/// Converts a protobuf `Timestamp`; negative `nanos` are treated as zero.
+pub fn from_timestamp(ts: &Timestamp) -> SystemTime {
+    let secs = Duration::from_secs(ts.seconds.unsigned_abs());
+    let start = if ts.seconds < 0 {
+        UNIX_EPOCH - secs
+    } else {
+        UNIX_EPOCH + secs
+    };
+    start + Duration::from_nanos(ts.nanos.max(0) as u64)
+}
@@ -1,11 +1,18 @@
 // AIDEV-NOTE: Updated for protobuf 3.x - RepeatedField replaced with Vec,
 // nested types now use module-based naming (e.g., mutate_rows_request::Entry)
-use crate::protos::bigtable::mutate_rows_request;
-use crate::protos::data::{mutation, Mutation, ReadModifyWriteRule, read_modify_write_rule};
+use crate::aggregate::{self, AggregateValue, Aggregator};
+use crate::protos::bigtable::{
+    mutate_rows_request, CheckAndMutateRowResponse, MutateRowResponse,
+    ReadModifyWriteRowRequest, ReadModifyWriteRowResponse, ReadRowsResponse,
+    SampleRowKeysResponse,
+};
+use crate::protos::data::{self, mutation, Mutation, ReadModifyWriteRule, RowFilter, RowSet, read_modify_write_rule};
 use crate::error::BTErr;
+use crate::filters;
 use goauth::auth::Token;
-use crate::method::{BigTable, MutateRows, ReadModifyWriteRow, ReadRows, SampleRowKeys};
-use crate::request::BTRequest;
+use crate::method::{BigTable, CheckAndMutateRow, MutateRow, MutateRows, ReadModifyWriteRow, ReadRows, SampleRowKeys};
+use crate::request::{parse_message, parse_stream, BTRequest, CurlTransport, Transport};
+use crate::rows;
 use serde_json;
 use crate::support::Table;
 use crate::utils::*;
@@ -25,27 +32,22 @@ pub struct Row {
     pub value: String,
 }
 
-impl Default for Row {
-    fn default() -> Self {
-        Row {
-            row_key: String::from("dummy_row_key"),
-            family: String::from("dummy_column_family"),
-            qualifier: String::from("dummy_column_qualifier"),
-            value: String::from("dummy_value"),
-        }
-    }
-}
-
 /// ```ignore
 /// use bigtable as bt;
 /// use bt::utils::*;
 /// use bt::error::BTErr;
+/// use bt::support::Table;
 /// use bt::wraps;
 ///
 /// fn write_rows() -> Result<(), BTErr> {
-///     let mut rows: Vec<wraps::Row> = vec!(wraps::Row::default());
+///     let mut rows: Vec<wraps::Row> = vec!(wraps::Row {
+///         row_key: String::from("user#1"),
+///         family: String::from("cf1"),
+///         qualifier: String::from("name"),
+///         value: String::from("Ann"),
+///     });
 ///     let token = get_auth_token("credentials.json", true)?;
-///     let table = Default::default();
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
 ///     let _ = wraps::bulk_write_rows(&mut rows, &token, table);
 ///     Ok(())
 /// }
@@ -77,12 +79,18 @@ pub fn bulk_write_rows(rows: &mut Vec<Row>, token: &Token, table: Table) -> Resu
 /// use bigtable as bt;
 /// use bt::utils::*;
 /// use bt::error::BTErr;
+/// use bt::support::Table;
 /// use bt::wraps;
 ///
 /// fn write_rows() -> Result<(), BTErr> {
-///     let mut rows: Vec<wraps::Row> = vec!(wraps::Row::default());
+///     let mut rows: Vec<wraps::Row> = vec!(wraps::Row {
+///         row_key: String::from("user#1"),
+///         family: String::from("cf1"),
+///         qualifier: String::from("name"),
+///         value: String::from("Ann"),
+///     });
 ///     let token = get_auth_token("credentials.json", true)?;
-///     let table = Default::default();
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
 ///     let _ = wraps::write_rows(&mut rows, &token, &table);
 ///     Ok(())
 /// }
@@ -94,29 +102,203 @@ pub fn write_rows(rows: &mut Vec<Row>, token: &Token, table: &Table) -> Result<u
         let mut req = BTRequest {
             base: None,
             table: table.clone(),
-            method: ReadModifyWriteRow::new(),
+            method: MutateRow::new(),
         };
 
-        let rule = make_readmodifywrite_rule(&row.qualifier, &row.family, encode_str(&row.value));
+        let mut mutation = Mutation::new();
+        let set_cell = make_setcell_mutation(&row.qualifier, &row.family, encode_str(&row.value));
+        mutation.mutation = Some(mutation::Mutation::SetCell(set_cell));
 
         req.method.payload_mut().row_key = encode_str(&row.row_key);
-        req.method.payload_mut().rules.push(rule);
+        req.method.payload_mut().mutations.push(mutation);
 
-        let _ = req.execute(token)?;
+        req.execute_message::<MutateRowResponse>(token)?;
         total += 1;
     }
     Ok(total)
 }
 
+/// Atomically adds `delta` to a big-endian Int64 cell and returns the new value.
+/// A missing cell is treated as zero.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps;
+///
+/// fn next_id() -> Result<i64, BTErr> {
+///     let token = get_auth_token("credentials.json", true)?;
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///     wraps::increment(&table, &token, b"sequences", "cf1", b"order_id", 1)
+/// }
+/// ```
+pub fn increment(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    family: &str,
+    qualifier: &[u8],
+    delta: i64,
+) -> Result<i64, BTErr> {
+    ReadModifyWrite::new(row_key)
+        .increment(family, qualifier, delta)
+        .execute(table, token)?
+        .int(family, qualifier)?
+        .ok_or_else(|| missing_cell(family, qualifier))
+}
+
+/// Atomically appends `value` to a cell and returns the full new value.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps;
+///
+/// fn log_event() -> Result<Vec<u8>, BTErr> {
+///     let token = get_auth_token("credentials.json", true)?;
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///     wraps::append(&table, &token, b"user#1", "cf1", b"events", b",login")
+/// }
+/// ```
+pub fn append(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    family: &str,
+    qualifier: &[u8],
+    value: &[u8],
+) -> Result<Vec<u8>, BTErr> {
+    ReadModifyWrite::new(row_key)
+        .append(family, qualifier, value)
+        .execute(table, token)?
+        .value(family, qualifier)
+        .map(|v| v.to_vec())
+        .ok_or_else(|| missing_cell(family, qualifier))
+}
+
+/// Builder for a `ReadModifyWriteRow` call applying several rules to one row atomically.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps::ReadModifyWrite;
+///
+/// fn record_hit() -> Result<(), BTErr> {
+///     let token = get_auth_token("credentials.json", true)?;
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///     let row = ReadModifyWrite::new(b"page#home")
+///         .increment("cf1", b"hits", 1)
+///         .append("cf1", b"referrers", b",example.com")
+///         .execute(&table, &token)?;
+///     let hits = row.int("cf1", b"hits")?;
+///     Ok(())
+/// }
+/// ```
+pub struct ReadModifyWrite {
+    row_key: Vec<u8>,
+    rules: Vec<ReadModifyWriteRule>,
+}
+
+impl ReadModifyWrite {
+    pub fn new(row_key: &[u8]) -> Self {
+        ReadModifyWrite {
+            row_key: row_key.to_vec(),
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn increment(mut self, family: &str, qualifier: &[u8], delta: i64) -> Self {
+        self.rules.push(make_readmodifywrite_rule(
+            qualifier,
+            family,
+            read_modify_write_rule::Rule::IncrementAmount(delta),
+        ));
+        self
+    }
+
+    pub fn append(mut self, family: &str, qualifier: &[u8], value: &[u8]) -> Self {
+        self.rules.push(make_readmodifywrite_rule(
+            qualifier,
+            family,
+            read_modify_write_rule::Rule::AppendValue(value.to_vec()),
+        ));
+        self
+    }
+
+    /// Applies the rules and returns the post-modification cells.
+    pub fn execute(self, table: &Table, token: &Token) -> Result<ModifiedRow, BTErr> {
+        self.execute_via(&CurlTransport, table, token)
+    }
+
+    pub(crate) fn execute_via(
+        self,
+        transport: &dyn Transport,
+        table: &Table,
+        token: &Token,
+    ) -> Result<ModifiedRow, BTErr> {
+        let mut req = BTRequest {
+            base: None,
+            table: table.clone(),
+            method: ReadModifyWriteRow::new(),
+        };
+        req.method.set_payload(self.into_request());
+
+        let mut response =
+            parse_message::<ReadModifyWriteRowResponse>(&req.execute_with(token, transport)?)?;
+        Ok(ModifiedRow {
+            row: response.row.take().unwrap_or_default(),
+        })
+    }
+
+    pub(crate) fn into_request(self) -> ReadModifyWriteRowRequest {
+        let mut request = ReadModifyWriteRowRequest::new();
+        request.row_key = self.row_key;
+        request.rules = self.rules;
+        request
+    }
+}
+
+/// Cells modified by a `ReadModifyWriteRow` call, holding their new values.
+#[derive(Debug)]
+pub struct ModifiedRow {
+    pub row: data::Row,
+}
+
+impl ModifiedRow {
+    pub fn value(&self, family: &str, qualifier: &[u8]) -> Option<&[u8]> {
+        rows::latest_cell(&self.row, family, qualifier).map(|c| c.value.as_slice())
+    }
+
+    /// New value of an incremented cell, decoded as a big-endian i64.
+    pub fn int(&self, family: &str, qualifier: &[u8]) -> Result<Option<i64>, BTErr> {
+        self.value(family, qualifier).map(decode_i64).transpose()
+    }
+}
+
+fn missing_cell(family: &str, qualifier: &[u8]) -> BTErr {
+    BTErr::DecodeErr(format!(
+        "ReadModifyWriteRow response is missing {}:{}",
+        family,
+        String::from_utf8_lossy(qualifier)
+    ))
+}
+
 /// ```ignore
 /// use bigtable as bt;
 /// use bt::utils::*;
 /// use bt::error::BTErr;
+/// use bt::support::Table;
 /// use bt::wraps;
 ///
 /// fn read_rows(limit: i64) -> Result<(), BTErr> {
 ///    let token = get_auth_token("credentials.json", true)?;
-///    let table = Default::default();
+///    let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
 ///    let _ = wraps::read_rows(&table, &token, Some(limit));
 ///    Ok(())
 /// }
@@ -140,6 +322,393 @@ pub fn read_rows(
     Ok(response)
 }
 
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps;
+///
+/// fn read_row() -> Result<(), BTErr> {
+///    let token = get_auth_token("credentials.json", true)?;
+///    let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///    let row = wraps::read_row(&table, &token, b"r1", None)?;
+///    Ok(())
+/// }
+/// ```
+pub fn read_row(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    filter: Option<RowFilter>,
+) -> Result<Option<data::Row>, BTErr> {
+    read_row_via(&CurlTransport, table, token, row_key, filter)
+}
+
+pub(crate) fn read_row_via(
+    transport: &dyn Transport,
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    filter: Option<RowFilter>,
+) -> Result<Option<data::Row>, BTErr> {
+    let mut row_set = RowSet::new();
+    row_set.row_keys.push(row_key.to_vec());
+    Ok(read_rows_via(transport, table, token, row_set, filter, 1)?.pop())
+}
+
+/// Reads the rows in `row_set`, up to `limit` rows (0 for no limit).
+pub(crate) fn read_rows_via(
+    transport: &dyn Transport,
+    table: &Table,
+    token: &Token,
+    row_set: RowSet,
+    filter: Option<RowFilter>,
+    limit: i64,
+) -> Result<Vec<data::Row>, BTErr> {
+    let mut req = BTRequest {
+        base: None,
+        table: table.clone(),
+        method: ReadRows::new(),
+    };
+    req.method.payload_mut().rows = Some(row_set).into();
+    req.method.payload_mut().filter = filter.into();
+    req.method.payload_mut().rows_limit = limit;
+
+    let responses = parse_stream::<ReadRowsResponse>(&req.execute_with(token, transport)?)?;
+    rows::merge_rows(responses)
+}
+
+/// Adds `delta` to an aggregate cell (Sum, Min, Max or HLL++ family) with `AddToCell`.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps;
+///
+/// fn count_visit() -> Result<(), BTErr> {
+///    let token = get_auth_token("credentials.json", true)?;
+///    let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///    wraps::add_to_cell(&table, &token, b"page#home", "counters", b"visits", 0, 1)?;
+///    Ok(())
+/// }
+/// ```
+pub fn add_to_cell(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    family: &str,
+    qualifier: &[u8],
+    timestamp_micros: i64,
+    delta: i64,
+) -> Result<(), BTErr> {
+    let mut req = BTRequest {
+        base: None,
+        table: table.clone(),
+        method: MutateRow::new(),
+    };
+
+    req.method.payload_mut().row_key = row_key.to_vec();
+    req.method
+        .payload_mut()
+        .mutations
+        .push(aggregate::add_to_cell(family, qualifier, timestamp_micros, delta));
+
+    req.execute_message::<MutateRowResponse>(token)?;
+    Ok(())
+}
+
+/// Reads the newest aggregate cell of `family:qualifier` and decodes its state.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::aggregate::Aggregator;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps;
+///
+/// fn visits() -> Result<(), BTErr> {
+///    let token = get_auth_token("credentials.json", true)?;
+///    let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///    let total = wraps::read_aggregate(&table, &token, b"page#home", "counters", b"visits", Aggregator::Sum)?;
+///    Ok(())
+/// }
+/// ```
+pub fn read_aggregate(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    family: &str,
+    qualifier: &[u8],
+    aggregator: Aggregator,
+) -> Result<Option<AggregateValue>, BTErr> {
+    let filter = filters::chain(vec![filters::column(family, qualifier), filters::latest()]);
+    let row = match read_row(table, token, row_key, Some(filter))? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    match rows::latest_cell(&row, family, qualifier) {
+        Some(cell) => Ok(Some(AggregateValue::decode(aggregator, &cell.value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Mutations applied atomically to a single row.
+///
+/// ```ignore
+/// use bigtable::wraps::RowMutation;
+///
+/// let m = RowMutation::new()
+///     .set_cell("cf1", b"status", b"done")
+///     .delete_cells("cf1", b"lease");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RowMutation {
+    pub mutations: Vec<Mutation>,
+}
+
+impl RowMutation {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets a cell using the server's timestamp.
+    pub fn set_cell(self, family: &str, qualifier: &[u8], value: &[u8]) -> Self {
+        self.set_cell_at(family, qualifier, -1, value)
+    }
+
+    pub fn set_cell_at(
+        self,
+        family: &str,
+        qualifier: &[u8],
+        timestamp_micros: i64,
+        value: &[u8],
+    ) -> Self {
+        let mut set_cell = mutation::SetCell::new();
+        set_cell.family_name = String::from(family);
+        set_cell.column_qualifier = qualifier.to_vec();
+        set_cell.timestamp_micros = timestamp_micros;
+        set_cell.value = value.to_vec();
+        self.push(mutation::Mutation::SetCell(set_cell))
+    }
+
+    /// Deletes every version of a column.
+    pub fn delete_cells(self, family: &str, qualifier: &[u8]) -> Self {
+        let mut delete = mutation::DeleteFromColumn::new();
+        delete.family_name = String::from(family);
+        delete.column_qualifier = qualifier.to_vec();
+        self.push(mutation::Mutation::DeleteFromColumn(delete))
+    }
+
+    pub fn delete_family(self, family: &str) -> Self {
+        let mut delete = mutation::DeleteFromFamily::new();
+        delete.family_name = String::from(family);
+        self.push(mutation::Mutation::DeleteFromFamily(delete))
+    }
+
+    pub fn delete_row(self) -> Self {
+        self.push(mutation::Mutation::DeleteFromRow(Default::default()))
+    }
+
+    pub fn push(mut self, m: mutation::Mutation) -> Self {
+        let mut mutation = Mutation::new();
+        mutation.mutation = Some(m);
+        self.mutations.push(mutation);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mutations.is_empty()
+    }
+}
+
+/// Applies `mutations` to a single row atomically.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps::{self, RowMutation};
+///
+/// fn rename() -> Result<(), BTErr> {
+///     let token = get_auth_token("credentials.json", true)?;
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///     wraps::mutate_row(
+///         &table,
+///         &token,
+///         b"user#1",
+///         RowMutation::new()
+///             .delete_cells("cf1", b"name")
+///             .set_cell("cf1", b"name", b"durch"),
+///     )
+/// }
+/// ```
+pub fn mutate_row(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    mutations: RowMutation,
+) -> Result<(), BTErr> {
+    mutate_row_via(&CurlTransport, table, token, row_key, mutations)
+}
+
+pub(crate) fn mutate_row_via(
+    transport: &dyn Transport,
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    mutations: RowMutation,
+) -> Result<(), BTErr> {
+    let mut req = BTRequest {
+        base: None,
+        table: table.clone(),
+        method: MutateRow::new(),
+    };
+
+    req.method.payload_mut().row_key = row_key.to_vec();
+    req.method.payload_mut().mutations = mutations.mutations;
+
+    parse_message::<MutateRowResponse>(&req.execute_with(token, transport)?)?;
+    Ok(())
+}
+
+/// Applies `on_match` if `predicate` yields any cell for the row, `on_no_match`
+/// otherwise, and returns whether the predicate matched.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::filters;
+/// use bt::wraps::{self, RowMutation};
+///
+/// fn close_order() -> Result<bool, BTErr> {
+///     let token = get_auth_token("credentials.json", true)?;
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///     wraps::check_and_mutate(
+///         &table,
+///         &token,
+///         b"order#1",
+///         filters::column("cf1", b"paid"),
+///         RowMutation::new().set_cell("cf1", b"status", b"closed"),
+///         RowMutation::new(),
+///     )
+/// }
+/// ```
+pub fn check_and_mutate(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    predicate: RowFilter,
+    on_match: RowMutation,
+    on_no_match: RowMutation,
+) -> Result<bool, BTErr> {
+    check_and_mutate_via(&CurlTransport, table, token, row_key, predicate, on_match, on_no_match)
+}
+
+pub(crate) fn check_and_mutate_via(
+    transport: &dyn Transport,
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    predicate: RowFilter,
+    on_match: RowMutation,
+    on_no_match: RowMutation,
+) -> Result<bool, BTErr> {
+    let mut req = BTRequest {
+        base: None,
+        table: table.clone(),
+        method: CheckAndMutateRow::new(),
+    };
+
+    req.method.payload_mut().row_key = row_key.to_vec();
+    req.method.payload_mut().predicate_filter = Some(predicate).into();
+    req.method.payload_mut().true_mutations = on_match.mutations;
+    req.method.payload_mut().false_mutations = on_no_match.mutations;
+
+    let response =
+        parse_message::<CheckAndMutateRowResponse>(&req.execute_with(token, transport)?)?;
+    Ok(response.predicate_matched)
+}
+
+/// Writes `value` only if the column has no cells; returns whether it was written.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps;
+///
+/// fn claim_username() -> Result<bool, BTErr> {
+///     let token = get_auth_token("credentials.json", true)?;
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///     wraps::put_if_absent(&table, &token, b"username#durch", "cf1", b"owner", b"user#1")
+/// }
+/// ```
+pub fn put_if_absent(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    family: &str,
+    qualifier: &[u8],
+    value: &[u8],
+) -> Result<bool, BTErr> {
+    let matched = check_and_mutate(
+        table,
+        token,
+        row_key,
+        filters::chain(vec![filters::column(family, qualifier), filters::latest()]),
+        RowMutation::new(),
+        RowMutation::new().set_cell(family, qualifier, value),
+    )?;
+    Ok(!matched)
+}
+
+/// Replaces the newest value of a column with `new` only if it currently equals
+/// `expected`; returns whether the swap happened.
+///
+/// ```ignore
+/// use bigtable as bt;
+/// use bt::utils::*;
+/// use bt::error::BTErr;
+/// use bt::support::Table;
+/// use bt::wraps;
+///
+/// fn ship() -> Result<bool, BTErr> {
+///     let token = get_auth_token("credentials.json", true)?;
+///     let table: Table = "projects/my-project/instances/my-instance/tables/my-table".parse()?;
+///     wraps::compare_and_set(&table, &token, b"order#1", "cf1", b"status", b"paid", b"shipped")
+/// }
+/// ```
+pub fn compare_and_set(
+    table: &Table,
+    token: &Token,
+    row_key: &[u8],
+    family: &str,
+    qualifier: &[u8],
+    expected: &[u8],
+    new: &[u8],
+) -> Result<bool, BTErr> {
+    check_and_mutate(
+        table,
+        token,
+        row_key,
+        filters::chain(vec![
+            filters::column(family, qualifier),
+            filters::latest(),
+            filters::value_equals(expected),
+        ]),
+        RowMutation::new().set_cell(family, qualifier, new),
+        RowMutation::new(),
+    )
+}
+
 fn make_setcell_mutation(
     column_qualifier: &str,
     column_family: &str,
@@ -154,23 +723,37 @@ fn make_setcell_mutation(
 }
 
 fn make_readmodifywrite_rule(
-    column_qualifier: &str,
+    column_qualifier: &[u8],
     column_family: &str,
-    blob: Vec<u8>,
+    op: read_modify_write_rule::Rule,
 ) -> ReadModifyWriteRule {
     let mut rule = ReadModifyWriteRule::new();
     rule.family_name = String::from(column_family);
-    rule.column_qualifier = encode_str(column_qualifier);
-    rule.rule = Some(read_modify_write_rule::Rule::AppendValue(blob));
+    rule.column_qualifier = column_qualifier.to_vec();
+    rule.rule = Some(op);
     rule
 }
 
-fn sample_row_keys(token: &Token) -> Result<String, BTErr> {
+/// Returns sampled row keys splitting the table into roughly equal-sized chunks,
+/// in key order. `offset_bytes` is the approximate amount of data up to each key;
+/// the last sample has an empty key and covers the end of the table.
+///
+/// ```ignore
+/// let samples = bt::wraps::sample_row_keys(&table, &token)?;
+/// ```
+pub fn sample_row_keys(table: &Table, token: &Token) -> Result<Vec<SampleRowKeysResponse>, BTErr> {
+    sample_row_keys_via(&CurlTransport, table, token)
+}
+
+pub(crate) fn sample_row_keys_via(
+    transport: &dyn Transport,
+    table: &Table,
+    token: &Token,
+) -> Result<Vec<SampleRowKeysResponse>, BTErr> {
     let req = BTRequest {
         base: None,
-        table: Default::default(),
+        table: table.clone(),
         method: SampleRowKeys::new(),
     };
-    let response = req.execute(token)?;
-    Ok(serde_json::to_string(&response)?)
+    parse_stream::<SampleRowKeysResponse>(&req.execute_with(token, transport)?)
 }
@@ -0,0 +1,93 @@
+// AIDEV-NOTE: Offline tests for aggregate cell encoding and ReadRows chunk merging.
+// These run without credentials.
+
+use bigtable::aggregate::{self, AggregateValue, Aggregator};
+use bigtable::protos::bigtable::ReadRowsResponse;
+use bigtable::protos::types::{type_, Type};
+use bigtable::request::parse_stream;
+use bigtable::rows;
+use serde_json::json;
+
+#[test]
+fn test_decode_sum_state() {
+    let state = (-42i64).to_be_bytes();
+    let value = AggregateValue::decode(Aggregator::Sum, &state).unwrap();
+    assert_eq!(value, AggregateValue::Sum(-42));
+    assert_eq!(value.encode(), state.to_vec());
+}
+
+#[test]
+fn test_decode_rejects_short_state() {
+    assert!(AggregateValue::decode(Aggregator::Max, &[0, 1, 2]).is_err());
+}
+
+#[test]
+fn test_aggregator_from_type() {
+    let mut agg = type_::Aggregate::new();
+    agg.aggregator = Some(type_::aggregate::Aggregator::Min(Default::default()));
+    let mut ty = Type::new();
+    ty.kind = Some(type_::Kind::AggregateType(agg));
+
+    assert_eq!(Aggregator::from_type(&ty), Some(Aggregator::Min));
+    assert_eq!(Aggregator::from_type(&Type::new()), None);
+}
+
+#[test]
+fn test_add_to_cell_json() {
+    let m = aggregate::add_to_cell("agg", b"hits", 0, 7);
+    let json: serde_json::Value =
+        serde_json::from_str(&protobuf_json_mapping::print_to_string(&m).unwrap()).unwrap();
+
+    assert_eq!(json["addToCell"]["familyName"], "agg");
+    assert_eq!(json["addToCell"]["columnQualifier"]["rawValue"], "aGl0cw==");
+    assert_eq!(json["addToCell"]["input"]["intValue"], "7");
+}
+
+#[test]
+fn test_merge_split_value_across_responses() {
+    // "AAAAAAAA" + "AAAACg==" is 10i64 big-endian, split over two responses
+    let response = json!([
+        {"chunks": [{
+            "rowKey": "cjE=",
+            "familyName": "agg",
+            "qualifier": "aGl0cw==",
+            "timestampMicros": "0",
+            "value": "AAAAAA==",
+            "valueSize": 8
+        }]},
+        {"chunks": [{"value": "AAAACg==", "commitRow": true}]}
+    ]);
+
+    let responses = parse_stream::<ReadRowsResponse>(&response).unwrap();
+    let rows = rows::merge_rows(responses).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].key, b"r1".to_vec());
+
+    let cell = rows::latest_cell(&rows[0], "agg", b"hits").unwrap();
+    let value = AggregateValue::decode(Aggregator::Sum, &cell.value).unwrap();
+    assert_eq!(value.as_i64(), Some(10));
+}
+
+#[test]
+fn test_merge_reset_row_discards_partial_row() {
+    let response = json!([
+        {"chunks": [
+            {"rowKey": "cjE=", "familyName": "cf1", "qualifier": "cQ==", "value": "YQ=="},
+            {"resetRow": true},
+            {"rowKey": "cjE=", "familyName": "cf1", "qualifier": "cQ==", "value": "Yg==", "commitRow": true}
+        ]}
+    ]);
+
+    let responses = parse_stream::<ReadRowsResponse>(&response).unwrap();
+    let rows = rows::merge_rows(responses).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].families[0].columns[0].cells.len(), 1);
+    assert_eq!(rows[0].families[0].columns[0].cells[0].value, b"b".to_vec());
+}
+
+#[test]
+fn test_error_response_is_surfaced() {
+    let response =
+        json!([{"error": {"code": 404, "message": "table not found", "status": "NOT_FOUND"}}]);
+    assert!(parse_stream::<ReadRowsResponse>(&response).is_err());
+}
@@ -0,0 +1,166 @@
+// AIDEV-NOTE: Arrow conversions only build with `--features arrow`.
+#![cfg(feature = "arrow")]
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Date32Type, Int64Type, TimestampMicrosecondType};
+use arrow_array::Array;
+use arrow_schema::{DataType, TimeUnit};
+use bigtable::arrow::{long_record_batch, wide_record_batch};
+use bigtable::convert::{Date, SqlType, ToValue};
+use bigtable::error::BTErr;
+use bigtable::protos::data::{Cell, Column, ColumnMetadata, Family, Row, Value};
+use bigtable::protos::types::{type_, Type};
+use bigtable::query::{QueryRow, ResultSet};
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn column(name: &str, ty: Type) -> ColumnMetadata {
+    let mut column = ColumnMetadata::new();
+    column.name = String::from(name);
+    column.type_ = Some(ty).into();
+    column
+}
+
+#[test]
+fn test_result_set_to_record_batch() {
+    let result = ResultSet {
+        columns: vec![
+            column("id", i64::sql_type()),
+            column("name", String::sql_type()),
+            column("tags", Vec::<i64>::sql_type()),
+            column("attrs", BTreeMap::<String, i64>::sql_type()),
+            column("at", std::time::SystemTime::sql_type()),
+            column("day", Date::sql_type()),
+        ],
+        rows: vec![
+            QueryRow {
+                values: vec![
+                    1i64.to_value(),
+                    "one".to_value(),
+                    vec![1i64, 2].to_value(),
+                    BTreeMap::from([(String::from("a"), 1i64)]).to_value(),
+                    (UNIX_EPOCH + Duration::from_millis(1500)).to_value(),
+                    Date {
+                        year: 2024,
+                        month: 1,
+                        day: 1,
+                    }
+                    .to_value(),
+                ],
+            },
+            QueryRow {
+                values: vec![
+                    2i64.to_value(),
+                    Value::new(),
+                    Value::new(),
+                    BTreeMap::<String, i64>::new().to_value(),
+                    Value::new(),
+                    Value::new(),
+                ],
+            },
+        ],
+    };
+
+    let batch = result.to_record_batch().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+    assert_eq!(
+        batch.schema().field(4).data_type(),
+        &DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+    );
+
+    let ids = batch.column(0).as_primitive::<Int64Type>();
+    assert_eq!(ids.values().to_vec(), vec![1, 2]);
+    let names = batch.column(1).as_string::<i32>();
+    assert_eq!(names.value(0), "one");
+    assert!(names.is_null(1));
+
+    let tags = batch.column(2).as_list::<i32>();
+    assert_eq!(
+        tags.value(0).as_primitive::<Int64Type>().values().to_vec(),
+        vec![1, 2]
+    );
+    assert!(tags.is_null(1));
+
+    let attrs = batch.column(3).as_map();
+    assert_eq!(attrs.value_length(0), 1);
+    assert_eq!(attrs.keys().as_string::<i32>().value(0), "a");
+    assert_eq!(attrs.value_length(1), 0);
+    assert!(!attrs.is_null(1));
+
+    let at = batch.column(4).as_primitive::<TimestampMicrosecondType>();
+    assert_eq!(at.value(0), 1_500_000);
+    assert!(at.is_null(1));
+    let day = batch.column(5).as_primitive::<Date32Type>();
+    assert_eq!(day.value(0), 19_723);
+}
+
+#[test]
+fn test_record_batch_rejects_mismatched_values() {
+    let result = ResultSet {
+        columns: vec![column("id", i64::sql_type())],
+        rows: vec![QueryRow {
+            values: vec!["one".to_value()],
+        }],
+    };
+    assert!(matches!(result.to_record_batch(), Err(BTErr::TypeErr(_))));
+
+    let mut ty = Type::new();
+    ty.kind = Some(type_::Kind::ProtoType(Default::default()));
+    let result = ResultSet {
+        columns: vec![column("p", ty)],
+        rows: vec![],
+    };
+    assert!(matches!(result.to_record_batch(), Err(BTErr::TypeErr(_))));
+}
+
+fn row(key: &[u8], cells: &[(&str, &[u8], i64, &[u8])]) -> Row {
+    let mut row = Row::new();
+    row.key = key.to_vec();
+    for (family, qualifier, ts, value) in cells {
+        let mut cell = Cell::new();
+        cell.timestamp_micros = *ts;
+        cell.value = value.to_vec();
+        let mut column = Column::new();
+        column.qualifier = qualifier.to_vec();
+        column.cells.push(cell);
+        let mut f = Family::new();
+        f.name = String::from(*family);
+        f.columns.push(column);
+        row.families.push(f);
+    }
+    row
+}
+
+#[test]
+fn test_rows_to_long_and_wide_batches() {
+    let rows = vec![
+        row(b"r1", &[("cf", b"a", 10, b"old"), ("cf", b"a", 20, b"new")]),
+        row(b"r2", &[("cf", b"b", 5, b"b2"), ("meta", b"a", 7, b"m2")]),
+    ];
+
+    let long = long_record_batch(&rows).unwrap();
+    assert_eq!(long.num_rows(), 4);
+    let keys = long.column(0).as_binary::<i32>();
+    assert_eq!(keys.value(0), b"r1");
+    assert_eq!(keys.value(3), b"r2");
+    assert_eq!(long.column(2).as_binary::<i32>().value(2), b"b");
+    assert_eq!(
+        long.column(3)
+            .as_primitive::<TimestampMicrosecondType>()
+            .value(1),
+        20
+    );
+
+    let wide = wide_record_batch(&rows).unwrap();
+    let names: Vec<String> = wide
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| f.name().clone())
+        .collect();
+    assert_eq!(names, vec!["row_key", "cf:a", "cf:b", "meta:a"]);
+    let cf_a = wide.column(1).as_binary::<i32>();
+    assert_eq!(cf_a.value(0), b"new");
+    assert!(cf_a.is_null(1));
+    assert_eq!(wide.column(3).as_binary::<i32>().value(1), b"m2");
+}
@@ -0,0 +1,851 @@
+// AIDEV-NOTE: Change stream tests run against scripted ReadChangeStream responses
+// from the in-memory stand-in in tests/common.
+
+mod common;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bigtable::aggregate::{AggregateValue, Aggregator};
+use bigtable::changestream::{
+    ChangeDecoder, ChangeEvent, ChangeKind, ChangeRecord, ChangeStreamReader, CheckpointStore,
+    FileCheckpointStore, Materializer, MemoryCheckpointStore, TableCheckpointStore,
+    WatermarkTracker,
+};
+use bigtable::error::BTErr;
+use bigtable::protos::bigtable::read_change_stream_request::Start_from;
+use bigtable::protos::bigtable::read_change_stream_response::CloseStream;
+use bigtable::protos::bigtable::read_change_stream_response::DataChange;
+use bigtable::protos::bigtable::ReadChangeStreamRequest;
+use bigtable::protos::data::{mutation, row_range, Row, StreamContinuationToken, StreamPartition};
+use bigtable::rows::latest_cell;
+use common::FakeBigtable;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn partition(start: &[u8], end: &[u8]) -> Value {
+    json!({"rowRange": {"startKeyClosed": STANDARD.encode(start), "endKeyOpen": STANDARD.encode(end)}})
+}
+
+fn token(start: &[u8], end: &[u8], token: &str) -> Value {
+    json!({"partition": partition(start, end), "token": token})
+}
+
+fn data_change(row_key: &[u8], token: &str) -> Value {
+    json!({"dataChange": {
+        "type": "USER",
+        "rowKey": STANDARD.encode(row_key),
+        "commitTimestamp": "2024-01-01T00:00:00Z",
+        "chunks": [{"mutation": {"setCell": {"familyName": "cf1", "columnQualifier": "cQ==", "value": "dg=="}}}],
+        "done": true,
+        "token": token
+    }})
+}
+
+fn range_of(p: &StreamPartition) -> (Vec<u8>, Vec<u8>) {
+    let range = p.row_range.as_ref().unwrap();
+    let start = match &range.start_key {
+        Some(row_range::Start_key::StartKeyClosed(k)) => k.clone(),
+        _ => Vec::new(),
+    };
+    let end = match &range.end_key {
+        Some(row_range::End_key::EndKeyOpen(k)) => k.clone(),
+        _ => Vec::new(),
+    };
+    (start, end)
+}
+
+fn parse_change(json: Value) -> DataChange {
+    protobuf_json_mapping::parse_from_str(&json.to_string()).unwrap()
+}
+
+fn chunk(value: &[u8], size: i32, offset: i32, last: bool) -> Value {
+    json!({
+        "chunkInfo": {"chunkedValueSize": size, "chunkedValueOffset": offset, "lastChunk": last},
+        "mutation": {"setCell": {"familyName": "cf1", "columnQualifier": "cQ==", "value": STANDARD.encode(value)}}
+    })
+}
+
+#[test]
+fn test_decoder_joins_chunked_set_cell() {
+    let mut decoder = ChangeDecoder::new();
+    let first = parse_change(json!({
+        "type": "USER",
+        "sourceClusterId": "c1",
+        "rowKey": "cjE=",
+        "commitTimestamp": "2024-01-01T00:00:00Z",
+        "tiebreaker": 2,
+        "chunks": [
+            {"mutation": {"deleteFromRow": {}}},
+            chunk(b"hel", 5, 0, false)
+        ]
+    }));
+    let last = parse_change(json!({
+        "type": "CONTINUATION",
+        "chunks": [chunk(b"lo", 5, 3, true)],
+        "done": true,
+        "token": "t1",
+        "estimatedLowWatermark": "2024-01-01T00:00:00Z"
+    }));
+
+    assert!(decoder.push(first).unwrap().is_none());
+    let record = decoder.push(last).unwrap().unwrap();
+    assert_eq!(record.row_key, b"r1".to_vec());
+    assert_eq!(record.kind, ChangeKind::User);
+    assert_eq!(record.source_cluster_id, "c1");
+    assert_eq!(record.tiebreaker, 2);
+    assert_eq!(record.token, "t1");
+    assert!(record.low_watermark.is_some());
+    assert_eq!(record.mutations.len(), 2);
+    match &record.mutations[1].mutation {
+        Some(mutation::Mutation::SetCell(set_cell)) => assert_eq!(set_cell.value, b"hello"),
+        other => panic!("unexpected mutation {:?}", other),
+    }
+    assert!(!decoder.in_progress());
+}
+
+#[test]
+fn test_decoder_rejects_protocol_violations() {
+    let start = || {
+        parse_change(json!({
+            "type": "GARBAGE_COLLECTION",
+            "rowKey": "cjE=",
+            "commitTimestamp": "2024-01-01T00:00:00Z",
+            "chunks": [chunk(b"ab", 4, 0, false)]
+        }))
+    };
+
+    // continuation without a change in progress
+    let mut decoder = ChangeDecoder::new();
+    assert!(decoder
+        .push(parse_change(json!({"type": "CONTINUATION", "done": true})))
+        .is_err());
+
+    // gap in the chunked value
+    let mut decoder = ChangeDecoder::new();
+    decoder.push(start()).unwrap();
+    let gap = parse_change(
+        json!({"type": "CONTINUATION", "chunks": [chunk(b"cd", 4, 3, true)], "done": true}),
+    );
+    assert!(decoder.push(gap).is_err());
+
+    // value shorter than announced
+    let mut decoder = ChangeDecoder::new();
+    decoder.push(start()).unwrap();
+    let short = parse_change(
+        json!({"type": "CONTINUATION", "chunks": [chunk(b"c", 4, 2, true)], "done": true}),
+    );
+    assert!(decoder.push(short).is_err());
+
+    // another mutation interleaved with the chunked value
+    let mut decoder = ChangeDecoder::new();
+    decoder.push(start()).unwrap();
+    let interleaved = parse_change(
+        json!({"type": "CONTINUATION", "chunks": [{"mutation": {"deleteFromRow": {}}}]}),
+    );
+    assert!(decoder.push(interleaved).is_err());
+
+    // new change before the previous one was done
+    let mut decoder = ChangeDecoder::new();
+    decoder.push(start()).unwrap();
+    assert!(decoder.push(start()).is_err());
+}
+
+#[test]
+fn test_follows_splits_and_merges() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"m"), (b"m", b"")]);
+
+    // [, m) splits into [, g) and [g, m)
+    fake.script_change_stream(
+        b"",
+        b"m",
+        json!([
+            data_change(b"a", "a1"),
+            {"heartbeat": {"continuationToken": token(b"", b"m", "a2")}}
+        ]),
+    );
+    fake.script_change_stream(
+        b"",
+        b"m",
+        json!([{"closeStream": {
+            "status": {},
+            "continuationTokens": [token(b"", b"g", "s1"), token(b"g", b"m", "s2")],
+            "newPartitions": [partition(b"", b"g"), partition(b"g", b"m")]
+        }}]),
+    );
+    fake.script_change_stream(b"", b"g", json!([data_change(b"b", "b1")]));
+
+    // [g, m) and [m, ) merge into [g, )
+    fake.script_change_stream(
+        b"g",
+        b"m",
+        json!([{"closeStream": {
+            "continuationTokens": [token(b"g", b"m", "g1")],
+            "newPartitions": [partition(b"g", b"")]
+        }}]),
+    );
+    fake.script_change_stream(
+        b"m",
+        b"",
+        json!([
+            data_change(b"n", "n1"),
+            {"closeStream": {
+                "continuationTokens": [token(b"m", b"", "m1")],
+                "newPartitions": [partition(b"g", b"")]
+            }}
+        ]),
+    );
+    fake.script_change_stream(b"g", b"", json!([data_change(b"z", "z1")]));
+
+    let token = common::token();
+    let mut events: BTreeMap<(Vec<u8>, Vec<u8>), Vec<String>> = BTreeMap::new();
+    let mut changes = 0;
+    ChangeStreamReader::new(&common::table(), &token)
+        .poll_window(Duration::from_millis(10))
+        .transport(&fake)
+        .run(|p, event| {
+            let label = match event {
+                ChangeEvent::Change(change) => {
+                    changes += 1;
+                    String::from_utf8(change.row_key).unwrap()
+                }
+                ChangeEvent::Heartbeat(_) => String::from("heartbeat"),
+                ChangeEvent::Closed(_) => String::from("closed"),
+                _ => return true,
+            };
+            events.entry(range_of(p)).or_default().push(label);
+            changes < 4
+        })
+        .unwrap();
+
+    assert_eq!(
+        events[&(vec![], b"m".to_vec())],
+        ["a", "heartbeat", "closed"]
+    );
+    assert_eq!(events[&(b"m".to_vec(), vec![])], ["n", "closed"]);
+    assert_eq!(events[&(vec![], b"g".to_vec())], ["b"]);
+    assert_eq!(events[&(b"g".to_vec(), vec![])], ["z"]);
+
+    // The merged partition resumes from both parents' tokens, and the split
+    // children from the token for their own range.
+    let mut resumed: BTreeMap<(Vec<u8>, Vec<u8>), Vec<String>> = BTreeMap::new();
+    for (method, body) in fake.requests() {
+        if method != "readChangeStream" {
+            continue;
+        }
+        let req: ReadChangeStreamRequest = protobuf_json_mapping::parse_from_str(&body).unwrap();
+        if let Some(Start_from::ContinuationTokens(tokens)) = req.start_from {
+            let mut names: Vec<String> = tokens.tokens.into_iter().map(|t| t.token).collect();
+            names.sort();
+            resumed
+                .entry(range_of(req.partition.as_ref().unwrap()))
+                .or_insert(names);
+        }
+    }
+    assert_eq!(resumed[&(vec![], b"m".to_vec())], ["a2"]);
+    assert_eq!(resumed[&(vec![], b"g".to_vec())], ["s1"]);
+    assert_eq!(resumed[&(b"g".to_vec(), vec![])], ["g1", "m1"]);
+}
+
+#[test]
+fn test_split_tokens_do_not_open_a_pending_merge() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"m"), (b"m", b"")]);
+
+    // [m, ) merges into [g, ) before [, m) splits into [, g) and [g, m), so
+    // the split's [g, m) token arrives while [g, ) waits for one.
+    fake.script_change_stream(
+        b"m",
+        b"",
+        json!([{"closeStream": {
+            "continuationTokens": [token(b"m", b"", "m1")],
+            "newPartitions": [partition(b"g", b"")]
+        }}]),
+    );
+    fake.script_change_stream(
+        b"g",
+        b"m",
+        json!([{"closeStream": {
+            "continuationTokens": [token(b"g", b"m", "g1")],
+            "newPartitions": [partition(b"g", b"")]
+        }}]),
+    );
+    fake.script_change_stream(b"g", b"", json!([data_change(b"z", "z1")]));
+
+    let auth = common::token();
+    ChangeStreamReader::new(&common::table(), &auth)
+        .poll_window(Duration::from_millis(10))
+        .transport(&fake)
+        .run(|p, event| match event {
+            ChangeEvent::Closed(_) if range_of(p) == (b"m".to_vec(), vec![]) => {
+                fake.script_change_stream(
+                    b"",
+                    b"m",
+                    json!([{"closeStream": {
+                        "continuationTokens": [token(b"", b"g", "s1"), token(b"g", b"m", "s2")],
+                        "newPartitions": [partition(b"", b"g"), partition(b"g", b"m")]
+                    }}]),
+                );
+                true
+            }
+            ChangeEvent::Change(_) => false,
+            _ => true,
+        })
+        .unwrap();
+
+    let mut resumed: BTreeMap<(Vec<u8>, Vec<u8>), Vec<String>> = BTreeMap::new();
+    for (method, body) in fake.requests() {
+        if method != "readChangeStream" {
+            continue;
+        }
+        let req: ReadChangeStreamRequest = protobuf_json_mapping::parse_from_str(&body).unwrap();
+        if let Some(Start_from::ContinuationTokens(tokens)) = req.start_from {
+            let mut names: Vec<String> = tokens.tokens.into_iter().map(|t| t.token).collect();
+            names.sort();
+            resumed
+                .entry(range_of(req.partition.as_ref().unwrap()))
+                .or_insert(names);
+        }
+    }
+    assert_eq!(resumed[&(b"g".to_vec(), b"m".to_vec())], ["s2"]);
+    assert_eq!(resumed[&(b"g".to_vec(), vec![])], ["g1", "m1"]);
+}
+
+#[test]
+fn test_close_stream_error_is_surfaced() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"")]);
+    fake.script_change_stream(
+        b"",
+        b"",
+        json!([{"closeStream": {"status": {"code": 3, "message": "bad token"}}}]),
+    );
+
+    let token = common::token();
+    let result = ChangeStreamReader::new(&common::table(), &token)
+        .transport(&fake)
+        .run(|_, _| true);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_split_with_non_ok_status_is_followed() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"")]);
+    // OUT_OF_RANGE
+    fake.script_close_stream(
+        b"",
+        b"",
+        11,
+        json!([token(b"", b"k", "s1"), token(b"k", b"", "s2")]),
+        json!([partition(b"", b"k"), partition(b"k", b"")]),
+    );
+    fake.script_change_stream(b"k", b"", json!([data_change(b"q", "q1")]));
+
+    let token = common::token();
+    let mut closed = 0;
+    ChangeStreamReader::new(&common::table(), &token)
+        .poll_window(Duration::from_millis(10))
+        .transport(&fake)
+        .run(|p, event| match event {
+            ChangeEvent::Closed(_) => {
+                closed += 1;
+                true
+            }
+            ChangeEvent::Change(change) => {
+                assert_eq!(range_of(p), (b"k".to_vec(), vec![]));
+                assert_eq!(change.row_key, b"q");
+                false
+            }
+            _ => true,
+        })
+        .unwrap();
+    assert_eq!(closed, 1);
+
+    // without tokens to continue from, the status is the stream's error
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"")]);
+    fake.script_close_stream(b"", b"", 11, json!([]), json!([]));
+    let err = ChangeStreamReader::new(&common::table(), &token)
+        .transport(&fake)
+        .run(|_, _| true)
+        .unwrap_err();
+    assert!(matches!(err, BTErr::ApiErr(ref e) if e.code == 11));
+}
+
+fn saved(store: &dyn CheckpointStore) -> Vec<String> {
+    let mut tokens: Vec<String> = store.load().unwrap().into_iter().map(|t| t.token).collect();
+    tokens.sort();
+    tokens
+}
+
+fn parse_token(json: Value) -> StreamContinuationToken {
+    protobuf_json_mapping::parse_from_str(&json.to_string()).unwrap()
+}
+
+#[test]
+fn test_reader_commits_and_resumes_from_checkpoints() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"")]);
+    fake.script_change_stream(
+        b"",
+        b"",
+        json!([
+            data_change(b"a", "t1"),
+            {"heartbeat": {"continuationToken": token(b"", b"", "t2")}}
+        ]),
+    );
+
+    let token = common::token();
+    let store = MemoryCheckpointStore::new();
+    ChangeStreamReader::new(&common::table(), &token)
+        .checkpoints(&store)
+        .checkpoint_interval(Duration::from_secs(3600))
+        .transport(&fake)
+        .run(|_, event| !matches!(event, ChangeEvent::Heartbeat(_)))
+        .unwrap();
+    // Uncommitted progress is flushed when the reader stops
+    assert_eq!(saved(&store), ["t2"]);
+
+    fake.script_change_stream(b"", b"", json!([data_change(b"b", "t3")]));
+    let first_run = fake.requests().len();
+    ChangeStreamReader::new(&common::table(), &token)
+        .checkpoints(&store)
+        .checkpoint_interval(Duration::from_millis(0))
+        .transport(&fake)
+        .run(|_, _| false)
+        .unwrap();
+    assert_eq!(saved(&store), ["t3"]);
+
+    let resumed: Vec<ReadChangeStreamRequest> = fake.requests()[first_run..]
+        .iter()
+        .filter(|(method, _)| method == "readChangeStream")
+        .map(|(_, body)| protobuf_json_mapping::parse_from_str(body).unwrap())
+        .collect();
+    match &resumed[0].start_from {
+        Some(Start_from::ContinuationTokens(tokens)) => assert_eq!(tokens.tokens[0].token, "t2"),
+        other => panic!("expected continuation tokens, got {:?}", other),
+    }
+    let generated = fake
+        .requests()
+        .iter()
+        .filter(|(method, _)| method == "generateInitialChangeStreamPartitions")
+        .count();
+    assert_eq!(generated, 1);
+}
+
+#[test]
+fn test_split_is_committed_immediately() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"")]);
+    fake.script_change_stream(
+        b"",
+        b"",
+        json!([
+            data_change(b"a", "t1"),
+            {"closeStream": {
+                "continuationTokens": [token(b"", b"k", "s1"), token(b"k", b"", "s2")],
+                "newPartitions": [partition(b"", b"k"), partition(b"k", b"")]
+            }}
+        ]),
+    );
+
+    let token = common::token();
+    let store = MemoryCheckpointStore::new();
+    ChangeStreamReader::new(&common::table(), &token)
+        .checkpoints(&store)
+        .checkpoint_interval(Duration::from_secs(3600))
+        .transport(&fake)
+        .run(|_, event| !matches!(event, ChangeEvent::Closed(_)))
+        .unwrap();
+    assert_eq!(saved(&store), ["s1", "s2"]);
+}
+
+#[test]
+fn test_merge_replaces_parent_checkpoints() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"m"), (b"m", b"")]);
+    // [, m) and [m, ) merge into [, )
+    fake.script_change_stream(
+        b"",
+        b"m",
+        json!([{"closeStream": {
+            "continuationTokens": [token(b"", b"m", "l1")],
+            "newPartitions": [partition(b"", b"")]
+        }}]),
+    );
+    fake.script_change_stream(
+        b"m",
+        b"",
+        json!([{"closeStream": {
+            "continuationTokens": [token(b"m", b"", "r1")],
+            "newPartitions": [partition(b"", b"")]
+        }}]),
+    );
+    fake.script_change_stream(b"", b"", json!([data_change(b"a", "t1")]));
+
+    let token = common::token();
+    let store = MemoryCheckpointStore::new();
+    let mut seen = Vec::new();
+    ChangeStreamReader::new(&common::table(), &token)
+        .checkpoints(&store)
+        .checkpoint_interval(Duration::from_secs(3600))
+        .poll_window(Duration::from_millis(10))
+        .transport(&fake)
+        .run(|_, event| match event {
+            ChangeEvent::Change(change) => {
+                seen.push(change.row_key);
+                false
+            }
+            _ => true,
+        })
+        .unwrap();
+    assert_eq!(saved(&store), ["t1"]);
+
+    // The restart reads only the merged partition, so nothing is seen twice
+    fake.script_change_stream(b"", b"", json!([data_change(b"b", "t2")]));
+    let first_run = fake.requests().len();
+    ChangeStreamReader::new(&common::table(), &token)
+        .checkpoints(&store)
+        .poll_window(Duration::from_millis(10))
+        .transport(&fake)
+        .run(|_, event| match event {
+            ChangeEvent::Change(change) => {
+                seen.push(change.row_key);
+                false
+            }
+            _ => true,
+        })
+        .unwrap();
+    assert_eq!(seen, [b"a".to_vec(), b"b".to_vec()]);
+    let read: Vec<(Vec<u8>, Vec<u8>)> = fake.requests()[first_run..]
+        .iter()
+        .filter(|(method, _)| method == "readChangeStream")
+        .map(|(_, body)| {
+            let req: ReadChangeStreamRequest = protobuf_json_mapping::parse_from_str(body).unwrap();
+            range_of(req.partition.as_ref().unwrap())
+        })
+        .collect();
+    assert_eq!(read, [(vec![], vec![])]);
+}
+
+#[test]
+fn test_file_checkpoint_store_round_trip() {
+    let path = std::env::temp_dir().join(format!("bt-checkpoints-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let store = FileCheckpointStore::open(&path).unwrap();
+    store.save(&parse_token(token(b"", b"k", "a"))).unwrap();
+    store.save(&parse_token(token(b"k", b"", "b"))).unwrap();
+    store.save(&parse_token(token(b"k", b"", "c"))).unwrap();
+    assert_eq!(
+        saved(&FileCheckpointStore::open(&path).unwrap()),
+        ["a", "c"]
+    );
+
+    store.remove(&common::stream_partition(b"", b"k")).unwrap();
+    assert_eq!(saved(&FileCheckpointStore::open(&path).unwrap()), ["c"]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_table_checkpoint_store_round_trip() {
+    let fake = FakeBigtable::new();
+    let auth = common::token();
+    let store = TableCheckpointStore::new(&common::table(), &auth, "cdc/").transport(&fake);
+    let other = TableCheckpointStore::new(&common::table(), &auth, "other/").transport(&fake);
+
+    store.save(&parse_token(token(b"", b"k", "a"))).unwrap();
+    store.save(&parse_token(token(b"k", b"", "b"))).unwrap();
+    store.save(&parse_token(token(b"k", b"", "c"))).unwrap();
+    other.save(&parse_token(token(b"", b"", "x"))).unwrap();
+    assert_eq!(saved(&store), ["a", "c"]);
+
+    store.remove(&common::stream_partition(b"k", b"")).unwrap();
+    assert_eq!(saved(&store), ["a"]);
+    assert_eq!(saved(&other), ["x"]);
+}
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn heartbeat(start: &[u8], end: &[u8], watermark: &str) -> Value {
+    json!({"heartbeat": {
+        "continuationToken": token(start, end, watermark),
+        "estimatedLowWatermark": watermark
+    }})
+}
+
+#[test]
+fn test_watermark_tracker_follows_splits_and_merges() {
+    let left = common::stream_partition(b"", b"m");
+    let right = common::stream_partition(b"m", b"");
+    let mut tracker = WatermarkTracker::new();
+    tracker.add(&left, None);
+    tracker.add(&right, None);
+
+    tracker.update(&left, at(20));
+    assert_eq!(tracker.watermark(), None);
+    tracker.update(&right, at(10));
+    assert_eq!(tracker.watermark(), Some(at(10)));
+
+    // right splits; its children start from its watermark
+    let split: CloseStream = protobuf_json_mapping::parse_from_str(
+        &json!({"newPartitions": [partition(b"m", b"t"), partition(b"t", b"")]}).to_string(),
+    )
+    .unwrap();
+    tracker.close(&right, &split);
+    assert_eq!(tracker.watermark(), Some(at(10)));
+    tracker.update(&common::stream_partition(b"m", b"t"), at(30));
+    tracker.update(&common::stream_partition(b"t", b""), at(25));
+    assert_eq!(tracker.watermark(), Some(at(20)));
+
+    // left and [m, t) merge; the merged partition holds the lower of the two
+    let merge: CloseStream = protobuf_json_mapping::parse_from_str(
+        &json!({"newPartitions": [partition(b"", b"t")]}).to_string(),
+    )
+    .unwrap();
+    tracker.close(&left, &merge);
+    tracker.close(&common::stream_partition(b"m", b"t"), &merge);
+    assert_eq!(tracker.watermark(), Some(at(20)));
+    tracker.update(&common::stream_partition(b"", b"t"), at(40));
+    assert_eq!(tracker.watermark(), Some(at(25)));
+}
+
+#[test]
+fn test_reader_emits_global_watermark() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"m"), (b"m", b"")]);
+    fake.script_change_stream(
+        b"",
+        b"m",
+        json!([
+            heartbeat(b"", b"m", "1970-01-01T00:16:40Z"),
+            heartbeat(b"", b"m", "1970-01-01T00:50:00Z")
+        ]),
+    );
+    fake.script_change_stream(
+        b"m",
+        b"",
+        json!([heartbeat(b"m", b"", "1970-01-01T00:33:20Z")]),
+    );
+
+    let token = common::token();
+    let mut watermarks = Vec::new();
+    ChangeStreamReader::new(&common::table(), &token)
+        .start_time(at(500))
+        .transport(&fake)
+        .run(|_, event| {
+            if let ChangeEvent::Watermark(w) = event {
+                watermarks.push(w);
+            }
+            watermarks.last() != Some(&at(2000))
+        })
+        .unwrap();
+
+    // Starts at start_time, never goes backwards, and ends at the lower partition
+    assert_eq!(watermarks.first(), Some(&at(500)));
+    assert!(watermarks.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(watermarks.last(), Some(&at(2000)));
+}
+
+fn data_change_at(row_key: &[u8], commit_timestamp: &str) -> Value {
+    let mut change = data_change(row_key, "t");
+    change["dataChange"]["commitTimestamp"] = json!(commit_timestamp);
+    change
+}
+
+#[test]
+fn test_replay_reads_every_partition_to_end_time() {
+    let fake = FakeBigtable::new();
+    fake.set_stream_partitions(&[(b"", b"m"), (b"m", b"")]);
+    fake.script_change_stream(
+        b"",
+        b"m",
+        json!([
+            data_change_at(b"b", "1970-01-01T00:33:20Z"),
+            {"closeStream": {
+                "continuationTokens": [token(b"", b"g", "s1"), token(b"g", b"m", "s2")],
+                "newPartitions": [partition(b"", b"g"), partition(b"g", b"m")]
+            }}
+        ]),
+    );
+    fake.script_change_stream(
+        b"",
+        b"g",
+        json!([data_change_at(b"d", "1970-01-01T01:06:40Z")]),
+    );
+    fake.script_change_stream(
+        b"m",
+        b"",
+        json!([
+            data_change_at(b"a", "1970-01-01T00:16:40Z"),
+            data_change_at(b"c", "1970-01-01T00:50:00Z"),
+            {"closeStream": {"status": {}}}
+        ]),
+    );
+
+    let token = common::token();
+    let records = ChangeStreamReader::new(&common::table(), &token)
+        .heartbeat_duration(Duration::from_millis(1500))
+        .transport(&fake)
+        .replay(at(500), at(5000))
+        .unwrap();
+
+    let keys: Vec<&[u8]> = records.iter().map(|r| r.row_key.as_slice()).collect();
+    assert_eq!(keys, [&b"a"[..], b"b", b"c", b"d"]);
+
+    // Every partition, including both halves of the split, is read up to end_time
+    let mut read = BTreeMap::new();
+    for (method, body) in fake.requests() {
+        if method == "readChangeStream" {
+            let req: ReadChangeStreamRequest =
+                protobuf_json_mapping::parse_from_str(&body).unwrap();
+            let end = req.end_time.as_ref().unwrap().seconds;
+            assert_eq!(req.heartbeat_duration.as_ref().unwrap().nanos, 500_000_000);
+            *read
+                .entry(range_of(req.partition.as_ref().unwrap()))
+                .or_insert(0) += 1;
+            assert_eq!(end, 5000);
+        }
+    }
+    assert_eq!(read.len(), 4);
+    assert!(read.values().all(|&n| n == 1));
+}
+
+fn record(kind: &str, row_key: &[u8], mutations: Value) -> ChangeRecord {
+    let chunks: Vec<Value> = mutations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| json!({ "mutation": m }))
+        .collect();
+    let change = parse_change(json!({
+        "type": kind,
+        "rowKey": STANDARD.encode(row_key),
+        "commitTimestamp": "1970-01-01T00:00:01Z",
+        "chunks": chunks,
+        "done": true
+    }));
+    ChangeDecoder::new().push(change).unwrap().unwrap()
+}
+
+fn set_cell(qualifier: &[u8], ts: i64, value: &[u8]) -> Value {
+    json!({"setCell": {
+        "familyName": "cf1",
+        "columnQualifier": STANDARD.encode(qualifier),
+        "timestampMicros": ts.to_string(),
+        "value": STANDARD.encode(value)
+    }})
+}
+
+fn cells(row: &Option<Row>) -> Vec<(Vec<u8>, i64, Vec<u8>)> {
+    let mut out = Vec::new();
+    for family in row.iter().flat_map(|r| r.families.iter()) {
+        for column in &family.columns {
+            for cell in &column.cells {
+                out.push((
+                    column.qualifier.clone(),
+                    cell.timestamp_micros,
+                    cell.value.clone(),
+                ));
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn test_materializer_yields_row_images() {
+    let mut images = Materializer::new();
+
+    let image = images
+        .apply(&record(
+            "USER",
+            b"r1",
+            json!([
+                set_cell(b"a", 10, b"x"),
+                set_cell(b"a", 20, b"y"),
+                set_cell(b"b", -1, b"z")
+            ]),
+        ))
+        .unwrap();
+    assert_eq!(image.before, None);
+    assert_eq!(
+        cells(&image.after),
+        vec![
+            (b"a".to_vec(), 20, b"y".to_vec()),
+            (b"a".to_vec(), 10, b"x".to_vec()),
+            (b"b".to_vec(), 1_000_000, b"z".to_vec()),
+        ]
+    );
+
+    // Same timestamp replaces; the time range end is exclusive
+    let image = images
+        .apply(&record(
+            "USER",
+            b"r1",
+            json!([
+                set_cell(b"a", 20, b"w"),
+                {"deleteFromColumn": {"familyName": "cf1", "columnQualifier": STANDARD.encode(b"a"),
+                    "timeRange": {"startTimestampMicros": "0", "endTimestampMicros": "20"}}}
+            ]),
+        ))
+        .unwrap();
+    assert_eq!(cells(&image.before).len(), 3);
+    assert_eq!(
+        cells(&image.after),
+        vec![
+            (b"a".to_vec(), 20, b"w".to_vec()),
+            (b"b".to_vec(), 1_000_000, b"z".to_vec())
+        ]
+    );
+
+    images
+        .apply(&record("USER", b"r2", json!([set_cell(b"a", 1, b"v")])))
+        .unwrap();
+    let keys: Vec<&[u8]> = images.store().rows().map(|r| r.key.as_slice()).collect();
+    assert_eq!(keys, [&b"r1"[..], b"r2"]);
+
+    // A row left without cells is dropped from the store
+    let image = images
+        .apply(&record(
+            "GARBAGE_COLLECTION",
+            b"r1",
+            json!([{"deleteFromFamily": {"familyName": "cf1"}}]),
+        ))
+        .unwrap();
+    assert!(image.before.is_some());
+    assert_eq!(image.after, None);
+    assert_eq!(images.store().len(), 1);
+}
+
+#[test]
+fn test_materializer_combines_aggregates() {
+    let add = |input: i64| {
+        json!({"addToCell": {
+            "familyName": "counters",
+            "columnQualifier": {"rawValue": STANDARD.encode(b"n")},
+            "timestamp": {"rawTimestampMicros": "0"},
+            "input": {"intValue": input.to_string()}
+        }})
+    };
+    let mut images = Materializer::new().aggregate("counters", Aggregator::Sum);
+    images
+        .apply(&record("USER", b"r", json!([add(2)])))
+        .unwrap();
+    let image = images
+        .apply(&record("USER", b"r", json!([add(3)])))
+        .unwrap();
+    let cell = latest_cell(image.after.as_ref().unwrap(), "counters", b"n").unwrap();
+    assert_eq!(
+        AggregateValue::decode(Aggregator::Sum, &cell.value).unwrap(),
+        AggregateValue::Sum(5)
+    );
+
+    // Undeclared aggregate families cannot be applied
+    assert!(Materializer::new()
+        .apply(&record("USER", b"r", json!([add(1)])))
+        .is_err());
+}
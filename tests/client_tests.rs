@@ -0,0 +1,230 @@
+// AIDEV-NOTE: BigtableClient tests run against the in-memory stand-in in tests/common.
+
+mod common;
+
+use bigtable::client::{BigtableClient, TableHandle};
+use bigtable::error::BTErr;
+use bigtable::filters;
+use bigtable::protos::bigtable::mutate_rows_request;
+use bigtable::protos::data::RowSet;
+use bigtable::wraps::{ReadModifyWrite, RowMutation};
+use bigtable_derive::BigtableRow;
+use common::FakeBigtable;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn handle(fake: &Arc<FakeBigtable>) -> TableHandle {
+    BigtableClient::new(&common::table().instance, common::token())
+        .app_profile("batch")
+        .retry_delay(Duration::from_millis(1))
+        .transport(fake.clone())
+        .table("my-table")
+        .unwrap()
+}
+
+fn methods(fake: &FakeBigtable) -> Vec<String> {
+    fake.requests().into_iter().map(|(m, _)| m).collect()
+}
+
+fn entry(row_key: &[u8], mutations: RowMutation) -> mutate_rows_request::Entry {
+    let mut entry = mutate_rows_request::Entry::new();
+    entry.row_key = row_key.to_vec();
+    entry.mutations = mutations.mutations;
+    entry
+}
+
+#[test]
+fn test_table_handle_calls() {
+    let fake = Arc::new(FakeBigtable::new());
+    let table = handle(&fake);
+    assert_eq!(table.table().name, "my-table");
+
+    table
+        .mutate_row(b"r1", RowMutation::new().set_cell("cf1", b"q", b"v1"))
+        .unwrap();
+    assert!(table
+        .check_and_mutate(
+            b"r1",
+            filters::column("cf1", b"q"),
+            RowMutation::new().set_cell("cf1", b"status", b"seen"),
+            RowMutation::new(),
+        )
+        .unwrap());
+    let row = table
+        .read_modify_write(ReadModifyWrite::new(b"r2").increment("cf1", b"hits", 2))
+        .unwrap();
+    assert_eq!(row.int("cf1", b"hits").unwrap(), Some(2));
+
+    let row = table.read_row(b"r1", None).unwrap().unwrap();
+    assert_eq!(
+        bigtable::rows::latest_cell(&row, "cf1", b"status")
+            .unwrap()
+            .value,
+        b"seen"
+    );
+    assert!(table.read_row(b"missing", None).unwrap().is_none());
+    assert_eq!(table.read_rows(RowSet::new(), None, 0).unwrap().len(), 2);
+    assert_eq!(
+        table.sample_row_keys().unwrap().last().unwrap().row_key,
+        b""
+    );
+
+    // every request names the app profile
+    for (method, body) in fake.requests() {
+        assert!(
+            body.contains(r#""appProfileId": "batch""#),
+            "{}: {}",
+            method,
+            body
+        );
+    }
+}
+
+#[test]
+fn test_retries_only_idempotent_requests() {
+    let fake = Arc::new(FakeBigtable::new());
+    let table = handle(&fake);
+
+    fake.fail_next("readRows", "UNAVAILABLE");
+    assert!(table.read_row(b"r1", None).unwrap().is_none());
+    assert_eq!(methods(&fake), vec!["readRows", "readRows"]);
+
+    // a server-assigned timestamp would write a second version
+    fake.fail_next("mutateRow", "UNAVAILABLE");
+    let err = table
+        .mutate_row(b"r1", RowMutation::new().set_cell("cf1", b"q", b"v"))
+        .unwrap_err();
+    assert!(matches!(err, BTErr::ApiErr(ref e) if e.status == "UNAVAILABLE"));
+    assert_eq!(methods(&fake).len(), 3);
+
+    fake.fail_next("mutateRow", "UNAVAILABLE");
+    table
+        .mutate_row(
+            b"r1",
+            RowMutation::new().set_cell_at("cf1", b"q", 1000, b"v"),
+        )
+        .unwrap();
+    assert_eq!(methods(&fake).len(), 5);
+    assert_eq!(fake.cells(b"r1").len(), 1);
+
+    // not retryable
+    fake.fail_next("readRows", "PERMISSION_DENIED");
+    assert!(table.read_row(b"r1", None).is_err());
+    assert_eq!(methods(&fake).len(), 6);
+
+    let table = BigtableClient::new(&common::table().instance, common::token())
+        .max_retries(1)
+        .retry_delay(Duration::from_millis(1))
+        .transport(fake.clone())
+        .table("my-table")
+        .unwrap();
+    fake.fail_next("readRows", "UNAVAILABLE");
+    fake.fail_next("readRows", "UNAVAILABLE");
+    assert!(table.read_row(b"r1", None).is_err());
+    assert_eq!(methods(&fake).len(), 8);
+}
+
+#[test]
+fn test_bulk_mutate_retries_failed_entries() {
+    let fake = Arc::new(FakeBigtable::new());
+    let table = handle(&fake);
+    fake.fail_entry(b"b", 14); // UNAVAILABLE
+    fake.fail_entry(b"c", 3); // INVALID_ARGUMENT
+    fake.fail_entry(b"d", 14);
+
+    let failed = table
+        .bulk_mutate(vec![
+            entry(
+                b"a",
+                RowMutation::new().set_cell_at("cf1", b"q", 1000, b"a"),
+            ),
+            entry(
+                b"b",
+                RowMutation::new().set_cell_at("cf1", b"q", 1000, b"b"),
+            ),
+            entry(
+                b"c",
+                RowMutation::new().set_cell_at("cf1", b"q", 1000, b"c"),
+            ),
+            entry(b"d", RowMutation::new().set_cell("cf1", b"q", b"d")),
+        ])
+        .unwrap();
+
+    let failed: Vec<(usize, String)> = failed
+        .into_iter()
+        .map(|f| (f.index, f.error.status))
+        .collect();
+    assert_eq!(
+        failed,
+        vec![
+            (2, String::from("INVALID_ARGUMENT")),
+            (3, String::from("UNAVAILABLE"))
+        ]
+    );
+    assert_eq!(methods(&fake), vec!["mutateRows", "mutateRows"]);
+    assert_eq!(fake.row_keys(), vec![b"a".to_vec(), b"b".to_vec()]);
+}
+
+#[test]
+fn test_bulk_mutate_reports_entries_without_status() {
+    let fake = Arc::new(FakeBigtable::new());
+    let table = handle(&fake);
+    fake.omit_entry(b"b");
+
+    let failed = table
+        .bulk_mutate(vec![
+            entry(
+                b"a",
+                RowMutation::new().set_cell_at("cf1", b"q", 1000, b"a"),
+            ),
+            entry(
+                b"b",
+                RowMutation::new().set_cell_at("cf1", b"q", 1000, b"b"),
+            ),
+            entry(
+                b"c",
+                RowMutation::new().set_cell_at("cf1", b"q", 1000, b"c"),
+            ),
+        ])
+        .unwrap();
+
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].index, 1);
+    assert_eq!(failed[0].error.status, "UNKNOWN");
+    assert_eq!(failed[0].error.message, "no status returned for entry");
+    assert_eq!(methods(&fake), vec!["mutateRows"]);
+    assert_eq!(fake.row_keys(), vec![b"a".to_vec(), b"c".to_vec()]);
+}
+
+#[test]
+fn test_put_and_get_mapped_rows() {
+    #[derive(BigtableRow, Debug, PartialEq)]
+    #[bigtable(family = "cf1")]
+    struct User {
+        #[bigtable(row_key)]
+        id: String,
+        name: String,
+        visits: i64,
+        logins: Vec<(SystemTime, String)>,
+    }
+
+    let fake = Arc::new(FakeBigtable::new());
+    let table = handle(&fake);
+    let mut user = User {
+        id: String::from("user#1"),
+        name: String::from("Ann"),
+        visits: 3,
+        logins: vec![
+            (UNIX_EPOCH + Duration::from_secs(2), String::from("web")),
+            (UNIX_EPOCH + Duration::from_secs(1), String::from("cli")),
+        ],
+    };
+    table.put(&user).unwrap();
+    assert_eq!(table.get::<User>(b"user#1").unwrap().as_ref(), Some(&user));
+    assert_eq!(table.get::<User>(b"user#2").unwrap(), None);
+
+    // versions dropped from the field are deleted on the next put
+    user.logins.pop();
+    table.put(&user).unwrap();
+    assert_eq!(table.get::<User>(b"user#1").unwrap(), Some(user));
+}
@@ -0,0 +1,852 @@
+// AIDEV-NOTE: In-memory stand-in for the Bigtable REST endpoint, plugged in via
+// `request::Transport`. It implements enough of the Data API (row storage,
+// RowFilter evaluation with RE2-like full-match regexes, mutations) to exercise
+// the higher-level modules without credentials. Not a faithful emulator.
+#![allow(dead_code)]
+
+use bigtable::error::BTErr;
+use bigtable::protos::bigtable::*;
+use bigtable::protos::data::{
+    column_range, mutation, read_modify_write_rule, row_filter, row_range, value, value_range,
+    Cell, Column, Family, Mutation, Row, RowFilter, RowRange, RowSet, StreamPartition,
+};
+use bigtable::protos::status::Status;
+use bigtable::request::Transport;
+use bigtable::support::{Instance, Project, Table};
+use goauth::auth::Token;
+use protobuf::MessageFull;
+use regex::bytes::Regex;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn token() -> Token {
+    serde_json::from_str(r#"{"access_token": "test", "token_type": "Bearer", "expires_in": 3600}"#)
+        .unwrap()
+}
+
+pub fn table() -> Table {
+    Table {
+        name: String::from("my-table"),
+        instance: Instance {
+            name: String::from("test-inst"),
+            project: Project {
+                name: String::from("test-project"),
+            },
+        },
+        app_profile_id: String::new(),
+        view: None,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeCell {
+    pub family: String,
+    pub qualifier: Vec<u8>,
+    pub timestamp_micros: i64,
+    pub value: Vec<u8>,
+    pub labels: Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+    // row key -> cells ordered by (family, qualifier, timestamp desc)
+    rows: BTreeMap<Vec<u8>, Vec<FakeCell>>,
+    requests: Vec<(String, String)>,
+    headers: Vec<Vec<String>>,
+    // change stream partitions as [start, end) and scripted responses per partition
+    stream_partitions: Vec<(Vec<u8>, Vec<u8>)>,
+    stream_scripts: BTreeMap<(Vec<u8>, Vec<u8>), VecDeque<String>>,
+    // PrepareQuery response and scripted ExecuteQuery responses
+    prepared_query: Option<String>,
+    query_scripts: VecDeque<String>,
+    // injected failures: (method, status), gRPC codes of MutateRows entries and
+    // MutateRows entries left out of the response
+    failures: VecDeque<(String, String)>,
+    entry_failures: BTreeMap<Vec<u8>, VecDeque<i32>>,
+    omitted_entries: BTreeSet<Vec<u8>>,
+}
+
+pub struct FakeBigtable {
+    state: Mutex<State>,
+    /// `SampleRowKeys` returns a sample after every `sample_every` rows.
+    pub sample_every: usize,
+}
+
+impl Default for FakeBigtable {
+    fn default() -> Self {
+        FakeBigtable {
+            state: Default::default(),
+            sample_every: 2,
+        }
+    }
+}
+
+impl FakeBigtable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn set_cell(&self, row_key: &[u8], family: &str, qualifier: &[u8], ts: i64, value: &[u8]) {
+        let mut set_cell = mutation::SetCell::new();
+        set_cell.family_name = String::from(family);
+        set_cell.column_qualifier = qualifier.to_vec();
+        set_cell.timestamp_micros = ts;
+        set_cell.value = value.to_vec();
+        let mut m = Mutation::new();
+        m.mutation = Some(mutation::Mutation::SetCell(set_cell));
+        let mut state = self.state.lock().unwrap();
+        apply_mutations(&mut state, row_key, &[m]).unwrap();
+    }
+
+    pub fn cells(&self, row_key: &[u8]) -> Vec<FakeCell> {
+        let state = self.state.lock().unwrap();
+        state.rows.get(row_key).cloned().unwrap_or_default()
+    }
+
+    pub fn row_keys(&self) -> Vec<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.rows.keys().cloned().collect()
+    }
+
+    /// Partitions returned by `GenerateInitialChangeStreamPartitions`.
+    pub fn set_stream_partitions(&self, ranges: &[(&[u8], &[u8])]) {
+        let mut state = self.state.lock().unwrap();
+        state.stream_partitions = ranges
+            .iter()
+            .map(|(s, e)| (s.to_vec(), e.to_vec()))
+            .collect();
+    }
+
+    /// Queues the JSON response for the next `ReadChangeStream` request on the
+    /// partition `[start, end)`. Requests with nothing queued get an empty stream.
+    pub fn script_change_stream(&self, start: &[u8], end: &[u8], responses: serde_json::Value) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .stream_scripts
+            .entry((start.to_vec(), end.to_vec()))
+            .or_default()
+            .push_back(responses.to_string());
+    }
+
+    /// Queues a `CloseStream` with gRPC status `code` on `[start, end)`, moving
+    /// `tokens` (`StreamContinuationToken` JSON) onto `new_partitions`, as the
+    /// service does for splits and merges.
+    pub fn script_close_stream(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        code: i32,
+        tokens: serde_json::Value,
+        new_partitions: serde_json::Value,
+    ) {
+        let mut status = Status::new();
+        status.code = code;
+        if code != 0 {
+            status.message = String::from("partition closed");
+        }
+        let status: serde_json::Value = serde_json::from_str(&print(&status).unwrap()).unwrap();
+        self.script_change_stream(
+            start,
+            end,
+            serde_json::json!([{"closeStream": {
+                "status": status,
+                "continuationTokens": tokens,
+                "newPartitions": new_partitions
+            }}]),
+        );
+    }
+
+    /// JSON response returned by every `PrepareQuery` request.
+    pub fn set_prepared_query(&self, response: serde_json::Value) {
+        self.state.lock().unwrap().prepared_query = Some(response.to_string());
+    }
+
+    /// Queues the JSON response for the next `ExecuteQuery` request. Requests
+    /// with nothing queued get an empty stream.
+    pub fn script_query(&self, responses: serde_json::Value) {
+        let mut state = self.state.lock().unwrap();
+        state.query_scripts.push_back(responses.to_string());
+    }
+
+    /// Headers of every request received so far.
+    pub fn headers(&self) -> Vec<Vec<String>> {
+        self.state.lock().unwrap().headers.clone()
+    }
+
+    /// Fails the next `method` request with `status` (e.g. "UNAVAILABLE")
+    /// without applying it.
+    pub fn fail_next(&self, method: &str, status: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .failures
+            .push_back((String::from(method), String::from(status)));
+    }
+
+    /// Fails the next `MutateRows` entry for `row_key` with gRPC status `code`
+    /// without applying it.
+    pub fn fail_entry(&self, row_key: &[u8], code: i32) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .entry_failures
+            .entry(row_key.to_vec())
+            .or_default()
+            .push_back(code);
+    }
+
+    /// Leaves the next `MutateRows` entry for `row_key` out of the response
+    /// without applying it.
+    pub fn omit_entry(&self, row_key: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.omitted_entries.insert(row_key.to_vec());
+    }
+
+    /// `(method, JSON body)` of every request received so far.
+    pub fn requests(&self) -> Vec<(String, String)> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    fn handle(&self, method: &str, body: &str) -> Result<String, String> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .requests
+            .push((String::from(method), String::from(body)));
+        if let Some(i) = state.failures.iter().position(|(m, _)| m == method) {
+            let (_, status) = state.failures.remove(i).unwrap();
+            return Ok(serde_json::json!({
+                "error": {"code": 503, "message": "injected failure", "status": status}
+            })
+            .to_string());
+        }
+        match method {
+            "mutateRow" => {
+                let req: MutateRowRequest = parse(body)?;
+                apply_mutations(&mut state, &req.row_key, &req.mutations)?;
+                print(&MutateRowResponse::new())
+            }
+            "mutateRows" => {
+                let req: MutateRowsRequest = parse(body)?;
+                let mut response = MutateRowsResponse::new();
+                for (i, entry) in req.entries.iter().enumerate() {
+                    if state.omitted_entries.remove(&entry.row_key) {
+                        continue;
+                    }
+                    let mut status = Status::new();
+                    let failure = state
+                        .entry_failures
+                        .get_mut(&entry.row_key)
+                        .and_then(|q| q.pop_front());
+                    match failure {
+                        Some(code) => {
+                            status.code = code;
+                            status.message = String::from("injected failure");
+                        }
+                        None => apply_mutations(&mut state, &entry.row_key, &entry.mutations)?,
+                    }
+                    let mut e = mutate_rows_response::Entry::new();
+                    e.index = i as i64;
+                    e.status = Some(status).into();
+                    response.entries.push(e);
+                }
+                Ok(format!("[{}]", print(&response)?))
+            }
+            "checkAndMutateRow" => {
+                let req: CheckAndMutateRowRequest = parse(body)?;
+                let cells = state.rows.get(&req.row_key).cloned().unwrap_or_default();
+                let matched = match req.predicate_filter.as_ref() {
+                    Some(f) => !apply_filter(f, &req.row_key, cells)?.is_empty(),
+                    None => !cells.is_empty(),
+                };
+                let mutations = if matched {
+                    &req.true_mutations
+                } else {
+                    &req.false_mutations
+                };
+                apply_mutations(&mut state, &req.row_key, mutations)?;
+                let mut response = CheckAndMutateRowResponse::new();
+                response.predicate_matched = matched;
+                print(&response)
+            }
+            "readModifyWriteRow" => {
+                let req: ReadModifyWriteRowRequest = parse(body)?;
+                let row = read_modify_write(&mut state, &req)?;
+                let mut response = ReadModifyWriteRowResponse::new();
+                response.row = Some(row).into();
+                print(&response)
+            }
+            "readRows" => {
+                let req: ReadRowsRequest = parse(body)?;
+                read_rows(&state, &req)
+            }
+            "sampleRowKeys" => {
+                let mut samples = Vec::new();
+                let mut offset = 0i64;
+                for (i, (key, cells)) in state.rows.iter().enumerate() {
+                    offset += cells.iter().map(|c| c.value.len() as i64 + 16).sum::<i64>();
+                    if (i + 1) % self.sample_every.max(1) == 0 {
+                        let mut sample = SampleRowKeysResponse::new();
+                        sample.row_key = key.clone();
+                        sample.offset_bytes = offset;
+                        samples.push(print(&sample)?);
+                    }
+                }
+                let mut last = SampleRowKeysResponse::new();
+                last.offset_bytes = offset;
+                samples.push(print(&last)?);
+                Ok(format!("[{}]", samples.join(",")))
+            }
+            "generateInitialChangeStreamPartitions" => {
+                let mut responses = Vec::new();
+                for (start, end) in &state.stream_partitions {
+                    let mut response = GenerateInitialChangeStreamPartitionsResponse::new();
+                    response.partition = Some(stream_partition(start, end)).into();
+                    responses.push(print(&response)?);
+                }
+                Ok(format!("[{}]", responses.join(",")))
+            }
+            "readChangeStream" => {
+                let req: ReadChangeStreamRequest = parse(body)?;
+                let (start, end) = partition_range(req.partition.as_ref());
+                let scripted = state
+                    .stream_scripts
+                    .get_mut(&(start, end))
+                    .and_then(|q| q.pop_front());
+                match scripted {
+                    Some(json) => Ok(json),
+                    None => {
+                        drop(state);
+                        std::thread::sleep(std::time::Duration::from_millis(1));
+                        Ok(String::from("[]"))
+                    }
+                }
+            }
+            "prepareQuery" => state
+                .prepared_query
+                .clone()
+                .ok_or_else(|| String::from("no prepared query scripted")),
+            "executeQuery" => Ok(state
+                .query_scripts
+                .pop_front()
+                .unwrap_or_else(|| String::from("[]"))),
+            other => Err(format!("method {} is not supported", other)),
+        }
+    }
+}
+
+impl Transport for FakeBigtable {
+    fn send(
+        &self,
+        url: &str,
+        headers: &[String],
+        body: &[u8],
+        _post: bool,
+    ) -> Result<Vec<u8>, BTErr> {
+        self.state.lock().unwrap().headers.push(headers.to_vec());
+        let method = url.rsplit(':').next().unwrap_or_default();
+        let body = std::str::from_utf8(body)?;
+        let response = match self.handle(method, body) {
+            Ok(json) => json,
+            Err(message) => serde_json::json!({
+                "error": {"code": 400, "message": message, "status": "INVALID_ARGUMENT"}
+            })
+            .to_string(),
+        };
+        Ok(response.into_bytes())
+    }
+}
+
+fn parse<M: MessageFull>(body: &str) -> Result<M, String> {
+    protobuf_json_mapping::parse_from_str(body).map_err(|e| e.to_string())
+}
+
+fn print<M: MessageFull>(m: &M) -> Result<String, String> {
+    protobuf_json_mapping::print_to_string(m).map_err(|e| e.to_string())
+}
+
+fn now_micros() -> i64 {
+    let micros = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as i64;
+    micros - micros % 1000
+}
+
+fn sort_cells(cells: &mut [FakeCell]) {
+    cells.sort_by(|a, b| {
+        (&a.family, &a.qualifier, b.timestamp_micros).cmp(&(
+            &b.family,
+            &b.qualifier,
+            a.timestamp_micros,
+        ))
+    });
+}
+
+fn put_cell(cells: &mut Vec<FakeCell>, cell: FakeCell) {
+    cells.retain(|c| {
+        !(c.family == cell.family
+            && c.qualifier == cell.qualifier
+            && c.timestamp_micros == cell.timestamp_micros)
+    });
+    cells.push(cell);
+    sort_cells(cells);
+}
+
+fn raw_bytes(v: &bigtable::protos::data::Value) -> Vec<u8> {
+    match &v.kind {
+        Some(value::Kind::RawValue(b)) | Some(value::Kind::BytesValue(b)) => b.clone(),
+        Some(value::Kind::StringValue(s)) => s.clone().into_bytes(),
+        Some(value::Kind::IntValue(i)) => i.to_be_bytes().to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+fn be_i64(bytes: &[u8]) -> Result<i64, String> {
+    match bytes.len() {
+        0 => Ok(0),
+        8 => {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(bytes);
+            Ok(i64::from_be_bytes(buf))
+        }
+        n => Err(format!("cannot increment a {} byte value", n)),
+    }
+}
+
+fn apply_mutations(
+    state: &mut State,
+    row_key: &[u8],
+    mutations: &[Mutation],
+) -> Result<(), String> {
+    let cells = state.rows.entry(row_key.to_vec()).or_default();
+    for m in mutations {
+        match &m.mutation {
+            Some(mutation::Mutation::SetCell(c)) => {
+                let ts = if c.timestamp_micros == -1 {
+                    now_micros()
+                } else {
+                    c.timestamp_micros
+                };
+                put_cell(
+                    cells,
+                    FakeCell {
+                        family: c.family_name.clone(),
+                        qualifier: c.column_qualifier.clone(),
+                        timestamp_micros: ts,
+                        value: c.value.clone(),
+                        labels: Vec::new(),
+                    },
+                );
+            }
+            Some(mutation::Mutation::AddToCell(a)) => aggregate(
+                cells,
+                &a.family_name,
+                &a.column_qualifier,
+                &a.timestamp,
+                &a.input,
+            )?,
+            Some(mutation::Mutation::MergeToCell(a)) => aggregate(
+                cells,
+                &a.family_name,
+                &a.column_qualifier,
+                &a.timestamp,
+                &a.input,
+            )?,
+            Some(mutation::Mutation::DeleteFromColumn(d)) => {
+                let (start, end) = d
+                    .time_range
+                    .as_ref()
+                    .map(|r| (r.start_timestamp_micros, r.end_timestamp_micros))
+                    .unwrap_or((0, 0));
+                cells.retain(|c| {
+                    !(c.family == d.family_name
+                        && c.qualifier == d.column_qualifier
+                        && c.timestamp_micros >= start
+                        && (end == 0 || c.timestamp_micros < end))
+                });
+            }
+            Some(mutation::Mutation::DeleteFromFamily(d)) => {
+                cells.retain(|c| c.family != d.family_name)
+            }
+            Some(mutation::Mutation::DeleteFromRow(_)) => cells.clear(),
+            Some(_) | None => return Err(String::from("unsupported mutation")),
+        }
+    }
+    if cells.is_empty() {
+        state.rows.remove(row_key);
+    }
+    Ok(())
+}
+
+// Aggregate families in the stand-in always behave as Int64 sums.
+fn aggregate(
+    cells: &mut Vec<FakeCell>,
+    family: &str,
+    qualifier: &bigtable::protos::data::Value,
+    timestamp: &bigtable::protos::data::Value,
+    input: &bigtable::protos::data::Value,
+) -> Result<(), String> {
+    let qualifier = raw_bytes(qualifier);
+    let ts = match timestamp.kind {
+        Some(value::Kind::RawTimestampMicros(ts)) => ts,
+        _ => 0,
+    };
+    let delta = be_i64(&raw_bytes(input))?;
+    let current = cells
+        .iter()
+        .find(|c| c.family == family && c.qualifier == qualifier && c.timestamp_micros == ts)
+        .map(|c| be_i64(&c.value))
+        .transpose()?
+        .unwrap_or(0);
+    put_cell(
+        cells,
+        FakeCell {
+            family: String::from(family),
+            qualifier,
+            timestamp_micros: ts,
+            value: (current + delta).to_be_bytes().to_vec(),
+            labels: Vec::new(),
+        },
+    );
+    Ok(())
+}
+
+fn read_modify_write(state: &mut State, req: &ReadModifyWriteRowRequest) -> Result<Row, String> {
+    let mut modified: Vec<FakeCell> = Vec::new();
+    let cells = state.rows.entry(req.row_key.clone()).or_default();
+    for rule in &req.rules {
+        let current = cells
+            .iter()
+            .find(|c| c.family == rule.family_name && c.qualifier == rule.column_qualifier)
+            .cloned();
+        let old_value = current
+            .as_ref()
+            .map(|c| c.value.clone())
+            .unwrap_or_default();
+        let new_value = match &rule.rule {
+            Some(read_modify_write_rule::Rule::AppendValue(v)) => [old_value, v.clone()].concat(),
+            Some(read_modify_write_rule::Rule::IncrementAmount(d)) => {
+                (be_i64(&old_value)? + d).to_be_bytes().to_vec()
+            }
+            Some(_) | None => return Err(String::from("unsupported rule")),
+        };
+        let ts = now_micros().max(current.map(|c| c.timestamp_micros).unwrap_or(0));
+        let cell = FakeCell {
+            family: rule.family_name.clone(),
+            qualifier: rule.column_qualifier.clone(),
+            timestamp_micros: ts,
+            value: new_value,
+            labels: Vec::new(),
+        };
+        put_cell(cells, cell.clone());
+        modified.retain(|c| !(c.family == cell.family && c.qualifier == cell.qualifier));
+        modified.push(cell);
+    }
+    sort_cells(&mut modified);
+    Ok(to_row(&req.row_key, &modified))
+}
+
+fn to_row(key: &[u8], cells: &[FakeCell]) -> Row {
+    let mut row = Row::new();
+    row.key = key.to_vec();
+    for c in cells {
+        if row.families.last().map(|f| &f.name) != Some(&c.family) {
+            let mut family = Family::new();
+            family.name = c.family.clone();
+            row.families.push(family);
+        }
+        let family = row.families.last_mut().unwrap();
+        if family.columns.last().map(|col| &col.qualifier) != Some(&c.qualifier) {
+            let mut column = Column::new();
+            column.qualifier = c.qualifier.clone();
+            family.columns.push(column);
+        }
+        let mut cell = Cell::new();
+        cell.timestamp_micros = c.timestamp_micros;
+        cell.value = c.value.clone();
+        cell.labels = c.labels.clone();
+        family.columns.last_mut().unwrap().cells.push(cell);
+    }
+    row
+}
+
+pub fn stream_partition(start: &[u8], end: &[u8]) -> StreamPartition {
+    let mut range = RowRange::new();
+    range.start_key = Some(row_range::Start_key::StartKeyClosed(start.to_vec()));
+    range.end_key = Some(row_range::End_key::EndKeyOpen(end.to_vec()));
+    let mut partition = StreamPartition::new();
+    partition.row_range = Some(range).into();
+    partition
+}
+
+fn partition_range(partition: Option<&StreamPartition>) -> (Vec<u8>, Vec<u8>) {
+    let range = match partition.and_then(|p| p.row_range.as_ref()) {
+        Some(range) => range,
+        None => return Default::default(),
+    };
+    let start = match &range.start_key {
+        Some(row_range::Start_key::StartKeyClosed(k)) => k.clone(),
+        _ => Vec::new(),
+    };
+    let end = match &range.end_key {
+        Some(row_range::End_key::EndKeyOpen(k)) => k.clone(),
+        _ => Vec::new(),
+    };
+    (start, end)
+}
+
+pub fn row_set_contains(rows: &RowSet, key: &[u8]) -> bool {
+    if rows.row_keys.is_empty() && rows.row_ranges.is_empty() {
+        return true;
+    }
+    rows.row_keys.iter().any(|k| k == key)
+        || rows.row_ranges.iter().any(|r| {
+            let after_start = match &r.start_key {
+                Some(row_range::Start_key::StartKeyClosed(s)) => key >= s.as_slice(),
+                Some(row_range::Start_key::StartKeyOpen(s)) => key > s.as_slice(),
+                Some(_) | None => true,
+            };
+            let before_end = match &r.end_key {
+                Some(row_range::End_key::EndKeyClosed(e)) => e.is_empty() || key <= e.as_slice(),
+                Some(row_range::End_key::EndKeyOpen(e)) => e.is_empty() || key < e.as_slice(),
+                Some(_) | None => true,
+            };
+            after_start && before_end
+        })
+}
+
+fn read_rows(state: &State, req: &ReadRowsRequest) -> Result<String, String> {
+    let all = RowSet::new();
+    let row_set = req.rows.as_ref().unwrap_or(&all);
+    let mut keys: Vec<&Vec<u8>> = state
+        .rows
+        .keys()
+        .filter(|k| row_set_contains(row_set, k))
+        .collect();
+    if req.reversed {
+        keys.reverse();
+    }
+
+    let mut responses = Vec::new();
+    for key in keys {
+        if req.rows_limit > 0 && responses.len() as i64 >= req.rows_limit {
+            break;
+        }
+        let mut cells = state.rows[key].clone();
+        if let Some(f) = req.filter.as_ref() {
+            cells = apply_filter(f, key, cells)?;
+        }
+        if cells.is_empty() {
+            continue;
+        }
+        let mut response = ReadRowsResponse::new();
+        let mut prev: Option<&FakeCell> = None;
+        for (i, c) in cells.iter().enumerate() {
+            let mut chunk = read_rows_response::CellChunk::new();
+            if prev.is_none() {
+                chunk.row_key = key.clone();
+            }
+            if prev.map(|p| &p.family) != Some(&c.family) {
+                let mut family = protobuf::well_known_types::wrappers::StringValue::new();
+                family.value = c.family.clone();
+                chunk.family_name = Some(family).into();
+            }
+            if prev.map(|p| (&p.family, &p.qualifier)) != Some((&c.family, &c.qualifier)) {
+                let mut qualifier = protobuf::well_known_types::wrappers::BytesValue::new();
+                qualifier.value = c.qualifier.clone();
+                chunk.qualifier = Some(qualifier).into();
+            }
+            chunk.timestamp_micros = c.timestamp_micros;
+            chunk.value = c.value.clone();
+            chunk.labels = c.labels.clone();
+            if i == cells.len() - 1 {
+                chunk.row_status =
+                    Some(read_rows_response::cell_chunk::Row_status::CommitRow(true));
+            }
+            response.chunks.push(chunk);
+            prev = Some(c);
+        }
+        responses.push(print(&response)?);
+    }
+    Ok(format!("[{}]", responses.join(",")))
+}
+
+// Bigtable regexes are RE2 full matches over raw bytes.
+fn full_match(pattern: &[u8], subject: &[u8]) -> Result<bool, String> {
+    let mut translated = String::new();
+    let mut i = 0;
+    while i < pattern.len() {
+        let b = pattern[i];
+        if b == b'\\' && i + 1 < pattern.len() && !pattern[i + 1].is_ascii_alphanumeric() {
+            let next = pattern[i + 1];
+            if next.is_ascii() && next != b'<' && next != b'>' && next.is_ascii_graphic() {
+                translated.push('\\');
+                translated.push(next as char);
+            } else {
+                translated.push_str(&format!("\\x{:02x}", next));
+            }
+            i += 2;
+            continue;
+        }
+        if b.is_ascii() && !b.is_ascii_control() {
+            translated.push(b as char);
+        } else {
+            translated.push_str(&format!("\\x{:02x}", b));
+        }
+        i += 1;
+    }
+    let re = Regex::new(&format!("(?s-u)^(?:{})$", translated)).map_err(|e| e.to_string())?;
+    Ok(re.is_match(subject))
+}
+
+pub fn apply_filter(
+    filter: &RowFilter,
+    key: &[u8],
+    cells: Vec<FakeCell>,
+) -> Result<Vec<FakeCell>, String> {
+    use row_filter::Filter;
+    Ok(match &filter.filter {
+        None
+        | Some(Filter::PassAllFilter(_))
+        | Some(Filter::Sink(_))
+        | Some(Filter::RowSampleFilter(_)) => cells,
+        Some(Filter::BlockAllFilter(_)) => Vec::new(),
+        Some(Filter::Chain(chain)) => {
+            let mut out = cells;
+            for f in &chain.filters {
+                out = apply_filter(f, key, out)?;
+            }
+            out
+        }
+        Some(Filter::Interleave(interleave)) => {
+            let mut out = Vec::new();
+            for f in &interleave.filters {
+                out.extend(apply_filter(f, key, cells.clone())?);
+            }
+            sort_cells(&mut out);
+            out
+        }
+        Some(Filter::Condition(c)) => {
+            let predicate = c.predicate_filter.as_ref().cloned().unwrap_or_default();
+            let branch = if apply_filter(&predicate, key, cells.clone())?.is_empty() {
+                c.false_filter.as_ref()
+            } else {
+                c.true_filter.as_ref()
+            };
+            match branch {
+                Some(f) => apply_filter(f, key, cells)?,
+                None => Vec::new(),
+            }
+        }
+        Some(Filter::RowKeyRegexFilter(re)) => {
+            if full_match(re, key)? {
+                cells
+            } else {
+                Vec::new()
+            }
+        }
+        Some(Filter::FamilyNameRegexFilter(re)) => {
+            let mut out = Vec::new();
+            for c in cells {
+                if full_match(re.as_bytes(), c.family.as_bytes())? {
+                    out.push(c);
+                }
+            }
+            out
+        }
+        Some(Filter::ColumnQualifierRegexFilter(re)) => {
+            let mut out = Vec::new();
+            for c in cells {
+                if full_match(re, &c.qualifier)? {
+                    out.push(c);
+                }
+            }
+            out
+        }
+        Some(Filter::ValueRegexFilter(re)) => {
+            let mut out = Vec::new();
+            for c in cells {
+                if full_match(re, &c.value)? {
+                    out.push(c);
+                }
+            }
+            out
+        }
+        Some(Filter::ColumnRangeFilter(r)) => cells
+            .into_iter()
+            .filter(|c| {
+                let q = c.qualifier.as_slice();
+                c.family == r.family_name
+                    && match &r.start_qualifier {
+                        Some(column_range::Start_qualifier::StartQualifierClosed(s)) => {
+                            q >= s.as_slice()
+                        }
+                        Some(column_range::Start_qualifier::StartQualifierOpen(s)) => {
+                            q > s.as_slice()
+                        }
+                        Some(_) | None => true,
+                    }
+                    && match &r.end_qualifier {
+                        Some(column_range::End_qualifier::EndQualifierClosed(e)) => {
+                            q <= e.as_slice()
+                        }
+                        Some(column_range::End_qualifier::EndQualifierOpen(e)) => q < e.as_slice(),
+                        Some(_) | None => true,
+                    }
+            })
+            .collect(),
+        Some(Filter::TimestampRangeFilter(r)) => cells
+            .into_iter()
+            .filter(|c| {
+                c.timestamp_micros >= r.start_timestamp_micros
+                    && (r.end_timestamp_micros == 0 || c.timestamp_micros < r.end_timestamp_micros)
+            })
+            .collect(),
+        Some(Filter::ValueRangeFilter(r)) => cells
+            .into_iter()
+            .filter(|c| {
+                let v = c.value.as_slice();
+                (match &r.start_value {
+                    Some(value_range::Start_value::StartValueClosed(s)) => v >= s.as_slice(),
+                    Some(value_range::Start_value::StartValueOpen(s)) => v > s.as_slice(),
+                    Some(_) | None => true,
+                }) && (match &r.end_value {
+                    Some(value_range::End_value::EndValueClosed(e)) => v <= e.as_slice(),
+                    Some(value_range::End_value::EndValueOpen(e)) => v < e.as_slice(),
+                    Some(_) | None => true,
+                })
+            })
+            .collect(),
+        Some(Filter::CellsPerRowOffsetFilter(n)) => cells.into_iter().skip(*n as usize).collect(),
+        Some(Filter::CellsPerRowLimitFilter(n)) => cells.into_iter().take(*n as usize).collect(),
+        Some(Filter::CellsPerColumnLimitFilter(n)) => {
+            let mut out: Vec<FakeCell> = Vec::new();
+            let mut seen = 0;
+            for c in cells {
+                let same_column = out
+                    .last()
+                    .map(|p| p.family == c.family && p.qualifier == c.qualifier)
+                    .unwrap_or(false);
+                seen = if same_column { seen + 1 } else { 1 };
+                if seen <= *n {
+                    out.push(c);
+                }
+            }
+            out
+        }
+        Some(Filter::StripValueTransformer(_)) => cells
+            .into_iter()
+            .map(|mut c| {
+                c.value.clear();
+                c
+            })
+            .collect(),
+        Some(Filter::ApplyLabelTransformer(label)) => cells
+            .into_iter()
+            .map(|mut c| {
+                c.labels.push(label.clone());
+                c
+            })
+            .collect(),
+        Some(_) => return Err(String::from("unsupported filter")),
+    })
+}
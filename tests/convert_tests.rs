@@ -0,0 +1,130 @@
+// AIDEV-NOTE: Round trips between Rust values and Bigtable `Value`/`Type`.
+
+use bigtable::convert::{decode, typed_value, Date, FromValue, SqlType, ToValue};
+use bigtable::error::BTErr;
+use bigtable::protos::data::{value, Value};
+use bigtable::protos::types::type_;
+use bigtable::utils;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn round_trip<T: ToValue + FromValue + SqlType + PartialEq + Debug>(v: T) {
+    let value = v.to_value();
+    assert_eq!(decode::<T>(&value, &T::sql_type()).unwrap(), v);
+}
+
+#[test]
+fn test_values_round_trip() {
+    round_trip(String::from("text"));
+    round_trip(b"\x00\xff".to_vec());
+    round_trip(-7i64);
+    round_trip(7i32);
+    round_trip(1.5f64);
+    round_trip(0.25f32);
+    round_trip(true);
+    round_trip(UNIX_EPOCH + Duration::new(1_700_000_000, 123_000));
+    round_trip(Date {
+        year: 2024,
+        month: 2,
+        day: 29,
+    });
+    round_trip(Some(3i64));
+    round_trip(None::<String>);
+    round_trip(vec![1i64, 2, 3]);
+    round_trip(vec![b"a".to_vec(), b"b".to_vec()]);
+    round_trip(vec![Some(true), None]);
+    round_trip(HashMap::from([
+        (String::from("a"), 1i64),
+        (String::from("b"), 2),
+    ]));
+    round_trip(BTreeMap::from([(b"q".to_vec(), vec![1.0f64])]));
+
+    assert!(matches!(
+        "s".to_value().kind,
+        Some(value::Kind::StringValue(_))
+    ));
+    assert!(matches!(
+        b"b"[..].to_value().kind,
+        Some(value::Kind::BytesValue(_))
+    ));
+}
+
+#[test]
+fn test_types_describe_nested_values() {
+    let ty = <HashMap<String, Vec<i64>>>::sql_type();
+    let map = match &ty.kind {
+        Some(type_::Kind::MapType(map)) => map,
+        other => panic!("not a map: {:?}", other),
+    };
+    assert!(matches!(
+        map.key_type.kind,
+        Some(type_::Kind::StringType(_))
+    ));
+    match &map.value_type.kind {
+        Some(type_::Kind::ArrayType(array)) => {
+            assert!(matches!(
+                array.element_type.kind,
+                Some(type_::Kind::Int64Type(_))
+            ))
+        }
+        other => panic!("not an array: {:?}", other),
+    }
+    // Bytes are a scalar, not an array of u8
+    assert!(matches!(
+        <Vec<u8>>::sql_type().kind,
+        Some(type_::Kind::BytesType(_))
+    ));
+
+    let value = typed_value(&5i64);
+    assert_eq!(value.type_.as_ref(), Some(&i64::sql_type()));
+}
+
+#[test]
+fn test_decode_checks_types() {
+    let int64 = i64::sql_type();
+
+    // Declared type and Rust type disagree
+    assert!(matches!(
+        decode::<String>(&"s".to_value(), &int64),
+        Err(BTErr::TypeErr(_))
+    ));
+    // Value does not match its declared type
+    assert!(matches!(
+        decode::<i64>(&"s".to_value(), &int64),
+        Err(BTErr::TypeErr(_))
+    ));
+    assert!(matches!(
+        decode::<Vec<i64>>(&vec![String::from("x")].to_value(), &<Vec<i64>>::sql_type()),
+        Err(BTErr::TypeErr(_))
+    ));
+    // NULL only reads as Option
+    let null = Value::new();
+    assert_eq!(decode::<Option<i64>>(&null, &int64).unwrap(), None);
+    assert!(matches!(
+        decode::<i64>(&null, &int64),
+        Err(BTErr::TypeErr(_))
+    ));
+    // Out of range for i32
+    assert!(matches!(
+        decode::<i32>(&(1i64 << 40).to_value(), &int64),
+        Err(BTErr::TypeErr(_))
+    ));
+    // Timestamps before the epoch round-trip
+    let before = UNIX_EPOCH - Duration::from_millis(10_250);
+    assert_eq!(
+        decode::<SystemTime>(&before.to_value(), &SystemTime::sql_type()).unwrap(),
+        before
+    );
+    for t in [
+        before,
+        UNIX_EPOCH - Duration::from_nanos(1),
+        UNIX_EPOCH + Duration::new(5, 7),
+    ] {
+        let ts = utils::to_timestamp(t);
+        assert!((0..1_000_000_000).contains(&ts.nanos), "{:?}", ts);
+        assert_eq!(utils::from_timestamp(&ts), t);
+    }
+    let ts = utils::to_timestamp(UNIX_EPOCH - Duration::from_millis(1500));
+    assert_eq!((ts.seconds, ts.nanos), (-2, 500_000_000));
+}
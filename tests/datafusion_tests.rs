@@ -0,0 +1,287 @@
+// AIDEV-NOTE: DataFusion TableProvider tests only build with `--features datafusion`.
+// SQL runs against the in-memory stand-in in tests/common; the ReadRows requests
+// it records show what was pushed down.
+#![cfg(feature = "datafusion")]
+
+mod common;
+
+use bigtable::client::BigtableClient;
+use bigtable::datafusion::BigtableTable;
+use bigtable::protos::bigtable::ReadRowsRequest;
+use common::FakeBigtable;
+use datafusion::arrow::array::{Array, BinaryArray, Int64Array, RecordBatch};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::prelude::{SessionConfig, SessionContext};
+use std::sync::Arc;
+
+fn context(fake: &Arc<FakeBigtable>) -> SessionContext {
+    context_with(fake, SessionConfig::new().with_target_partitions(4))
+}
+
+fn context_with(fake: &Arc<FakeBigtable>, config: SessionConfig) -> SessionContext {
+    let handle = BigtableClient::new(&common::table().instance, common::token())
+        .transport(fake.clone())
+        .table("my-table")
+        .unwrap();
+    let users = BigtableTable::new(handle)
+        .column("cf1", b"name")
+        .column("cf1", b"email");
+    let ctx = SessionContext::new_with_config(config);
+    ctx.register_table("users", Arc::new(users)).unwrap();
+    ctx
+}
+
+fn users() -> Arc<FakeBigtable> {
+    let fake = Arc::new(FakeBigtable::new());
+    for i in 1..=6 {
+        let key = format!("user#{}", i);
+        if i != 3 {
+            fake.set_cell(key.as_bytes(), "cf1", b"name", 1000, b"old");
+            fake.set_cell(
+                key.as_bytes(),
+                "cf1",
+                b"name",
+                2000,
+                format!("n{}", i).as_bytes(),
+            );
+        }
+        fake.set_cell(key.as_bytes(), "cf1", b"email", 1000, b"e");
+    }
+    fake.set_cell(b"zz", "cf2", b"other", 1000, b"x");
+    fake
+}
+
+async fn sql(ctx: &SessionContext, query: &str) -> Vec<Vec<Option<String>>> {
+    let batches = ctx.sql(query).await.unwrap().collect().await.unwrap();
+    values(&batches)
+}
+
+async fn count(ctx: &SessionContext, query: &str) -> i64 {
+    let batches = ctx.sql(query).await.unwrap().collect().await.unwrap();
+    batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap()
+        .value(0)
+}
+
+// Rows of `batches`, binary values read as UTF-8.
+fn values(batches: &[RecordBatch]) -> Vec<Vec<Option<String>>> {
+    let mut rows = Vec::new();
+    for batch in batches {
+        for i in 0..batch.num_rows() {
+            let row = batch
+                .columns()
+                .iter()
+                .map(|column| {
+                    assert_eq!(column.data_type(), &DataType::Binary);
+                    let column = column.as_any().downcast_ref::<BinaryArray>().unwrap();
+                    if column.is_null(i) {
+                        None
+                    } else {
+                        Some(String::from_utf8_lossy(column.value(i)).into_owned())
+                    }
+                })
+                .collect();
+            rows.push(row);
+        }
+    }
+    rows
+}
+
+fn s(v: &str) -> Option<String> {
+    Some(String::from(v))
+}
+
+fn read_requests(fake: &FakeBigtable) -> Vec<ReadRowsRequest> {
+    fake.requests()
+        .into_iter()
+        .filter(|(method, _)| method == "readRows")
+        .map(|(_, body)| protobuf_json_mapping::parse_from_str(&body).unwrap())
+        .collect()
+}
+
+fn methods(fake: &FakeBigtable) -> Vec<String> {
+    fake.requests().into_iter().map(|(m, _)| m).collect()
+}
+
+#[tokio::test]
+async fn test_sql_reads_newest_values_of_projected_columns() {
+    let fake = users();
+    let ctx = context(&fake);
+
+    // Rows without the projected column still come back, with nulls
+    let rows = sql(
+        &ctx,
+        r#"SELECT row_key, "cf1:name" FROM users ORDER BY row_key"#,
+    )
+    .await;
+    assert_eq!(
+        rows,
+        vec![
+            vec![s("user#1"), s("n1")],
+            vec![s("user#2"), s("n2")],
+            vec![s("user#3"), None],
+            vec![s("user#4"), s("n4")],
+            vec![s("user#5"), s("n5")],
+            vec![s("user#6"), s("n6")],
+            vec![s("zz"), None],
+        ]
+    );
+    // Partitions come from SampleRowKeys
+    assert_eq!(methods(&fake)[0], "sampleRowKeys");
+    assert!(read_requests(&fake).len() > 1);
+
+    assert_eq!(count(&ctx, "SELECT count(*) FROM users").await, 7);
+    assert_eq!(
+        count(&ctx, r#"SELECT count("cf1:name") FROM users"#).await,
+        5
+    );
+}
+
+#[tokio::test]
+async fn test_row_key_predicates_are_pushed_down() {
+    let fake = users();
+    let ctx = context(&fake);
+
+    let rows = sql(
+        &ctx,
+        "SELECT row_key FROM users WHERE row_key LIKE 'user#%' AND row_key >= 'user#4' \
+         ORDER BY row_key",
+    )
+    .await;
+    assert_eq!(
+        rows,
+        vec![vec![s("user#4")], vec![s("user#5")], vec![s("user#6")]]
+    );
+    for request in read_requests(&fake) {
+        let rows = request.rows.unwrap();
+        assert!(!common::row_set_contains(&rows, b"user#3"));
+        assert!(!common::row_set_contains(&rows, b"zz"));
+    }
+
+    // Point lookups need neither sampling nor more than one request
+    let fake = users();
+    let ctx = context(&fake);
+    let rows = sql(
+        &ctx,
+        r#"SELECT row_key, "cf1:email" FROM users WHERE row_key IN ('user#5', 'user#2', 'nope')
+           ORDER BY row_key"#,
+    )
+    .await;
+    assert_eq!(
+        rows,
+        vec![vec![s("user#2"), s("e")], vec![s("user#5"), s("e")]]
+    );
+    assert_eq!(methods(&fake), ["readRows"]);
+    let request = &read_requests(&fake)[0];
+    assert_eq!(
+        request.rows.row_keys,
+        vec![b"nope".to_vec(), b"user#2".to_vec(), b"user#5".to_vec()]
+    );
+
+    // Longer IN lists stay IN lists
+    let rows = sql(
+        &ctx,
+        "SELECT row_key FROM users \
+         WHERE row_key IN ('user#1', 'user#2', 'user#3', 'user#4') AND row_key > 'user#2' \
+         ORDER BY row_key",
+    )
+    .await;
+    assert_eq!(rows, vec![vec![s("user#3")], vec![s("user#4")]]);
+    let request = read_requests(&fake).pop().unwrap();
+    assert_eq!(
+        request.rows.row_keys,
+        vec![b"user#3".to_vec(), b"user#4".to_vec()]
+    );
+
+    // No key can match both
+    let fake = users();
+    let ctx = context(&fake);
+    let rows = sql(
+        &ctx,
+        "SELECT row_key FROM users WHERE row_key = 'user#1' AND row_key BETWEEN 'user#2' AND 'user#5'",
+    )
+    .await;
+    assert!(rows.is_empty());
+    assert!(methods(&fake).is_empty());
+
+    // Other predicates are evaluated by DataFusion
+    let fake = users();
+    let ctx = context(&fake);
+    let rows = sql(
+        &ctx,
+        r#"SELECT row_key FROM users WHERE "cf1:name" = CAST('n2' AS BYTEA) OR row_key = 'zz'
+           ORDER BY row_key"#,
+    )
+    .await;
+    assert_eq!(rows, vec![vec![s("user#2")], vec![s("zz")]]);
+}
+
+#[tokio::test]
+async fn test_limit_is_pushed_down() {
+    let fake = users();
+    let ctx = context(&fake);
+
+    let rows = sql(&ctx, "SELECT row_key FROM users LIMIT 2").await;
+    assert_eq!(rows.len(), 2);
+    let requests = read_requests(&fake);
+    assert!(!requests.is_empty());
+    for request in requests {
+        assert!(
+            (1..=2).contains(&request.rows_limit),
+            "{}",
+            request.rows_limit
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_shards_are_read_in_pages() {
+    let fake = users();
+    let config = SessionConfig::new()
+        .with_target_partitions(1)
+        .with_batch_size(2);
+    let ctx = context_with(&fake, config);
+
+    let rows = sql(&ctx, "SELECT row_key FROM users").await;
+    assert_eq!(rows.len(), 7);
+    assert_eq!(rows[6], vec![s("zz")]);
+    let requests = read_requests(&fake);
+    // Three full pages, then the last row
+    assert_eq!(requests.len(), 4);
+    assert!(requests.iter().all(|r| r.rows_limit == 2));
+    assert!(!common::row_set_contains(
+        requests[3].rows.as_ref().unwrap(),
+        b"user#6"
+    ));
+
+    let fake = users();
+    let config = SessionConfig::new()
+        .with_target_partitions(1)
+        .with_batch_size(2);
+    let ctx = context_with(&fake, config);
+    let rows = sql(&ctx, "SELECT row_key FROM users LIMIT 3").await;
+    assert_eq!(rows.len(), 3);
+    let limits: Vec<i64> = read_requests(&fake).iter().map(|r| r.rows_limit).collect();
+    assert_eq!(limits, [2, 1]);
+}
+
+#[tokio::test]
+async fn test_read_errors_fail_the_query() {
+    let fake = users();
+    let ctx = context(&fake);
+    fake.fail_next("sampleRowKeys", "PERMISSION_DENIED");
+    let df = ctx.sql("SELECT row_key FROM users").await.unwrap();
+    assert!(df.collect().await.is_err());
+
+    let fake = users();
+    let ctx = context(&fake);
+    fake.fail_next("readRows", "PERMISSION_DENIED");
+    let df = ctx
+        .sql("SELECT row_key FROM users WHERE row_key = 'user#1'")
+        .await
+        .unwrap();
+    assert!(df.collect().await.is_err());
+}
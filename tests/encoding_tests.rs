@@ -0,0 +1,177 @@
+// AIDEV-NOTE: Cell encoding tests follow the examples in types.proto.
+
+use bigtable::convert::{SqlType, ToValue};
+use bigtable::encoding::{decode, encode};
+use bigtable::error::BTErr;
+use bigtable::keys::KeyBuilder;
+use bigtable::protos::data::{value, ArrayValue, Value};
+use bigtable::protos::types::type_::{bytes, int64, string, struct_, timestamp};
+use bigtable::protos::types::{type_, Type};
+use protobuf::well_known_types::timestamp::Timestamp;
+
+fn ordered_int64() -> Type {
+    let mut encoding = int64::Encoding::new();
+    encoding.encoding = Some(int64::encoding::Encoding::OrderedCodeBytes(
+        Default::default(),
+    ));
+    let mut int = type_::Int64::new();
+    int.encoding = Some(encoding).into();
+    let mut ty = Type::new();
+    ty.kind = Some(type_::Kind::Int64Type(int));
+    ty
+}
+
+fn escaped_bytes() -> Type {
+    let mut raw = bytes::encoding::Raw::new();
+    raw.escape_nulls = true;
+    let mut encoding = bytes::Encoding::new();
+    encoding.encoding = Some(bytes::encoding::Encoding::Raw(raw));
+    let mut b = type_::Bytes::new();
+    b.encoding = Some(encoding).into();
+    let mut ty = Type::new();
+    ty.kind = Some(type_::Kind::BytesType(b));
+    ty
+}
+
+fn escaped_string(escape: &str) -> Type {
+    let mut utf8 = string::encoding::Utf8Bytes::new();
+    utf8.null_escape_char = String::from(escape);
+    let mut encoding = string::Encoding::new();
+    encoding.encoding = Some(string::encoding::Encoding::Utf8Bytes(utf8));
+    let mut s = type_::String::new();
+    s.encoding = Some(encoding).into();
+    let mut ty = Type::new();
+    ty.kind = Some(type_::Kind::StringType(s));
+    ty
+}
+
+fn struct_type(fields: Vec<Type>, encoding: struct_::encoding::Encoding) -> Type {
+    let mut st = type_::Struct::new();
+    for (i, ty) in fields.into_iter().enumerate() {
+        let mut field = struct_::Field::new();
+        field.field_name = format!("f{}", i);
+        field.type_ = Some(ty).into();
+        st.fields.push(field);
+    }
+    let mut e = struct_::Encoding::new();
+    e.encoding = Some(encoding);
+    st.encoding = Some(e).into();
+    let mut ty = Type::new();
+    ty.kind = Some(type_::Kind::StructType(st));
+    ty
+}
+
+fn struct_value(values: Vec<Value>) -> Value {
+    let mut array = ArrayValue::new();
+    array.values = values;
+    let mut v = Value::new();
+    v.kind = Some(value::Kind::ArrayValue(array));
+    v
+}
+
+fn round_trip(v: &Value, ty: &Type) -> Vec<u8> {
+    let bytes = encode(v, ty).unwrap();
+    assert_eq!(&decode(&bytes, ty).unwrap(), v);
+    bytes
+}
+
+#[test]
+fn test_int64_and_timestamp_encodings() {
+    assert_eq!(
+        round_trip(&1i64.to_value(), &i64::sql_type()),
+        vec![0, 0, 0, 0, 0, 0, 0, 1]
+    );
+    assert_eq!(
+        round_trip(&64i64.to_value(), &ordered_int64()),
+        vec![0xc0, 0x40]
+    );
+    assert_eq!(
+        round_trip(&(-1i64).to_value(), &ordered_int64()),
+        vec![0x7f]
+    );
+    assert!(decode(&[0x80, 0], &ordered_int64()).is_err());
+    assert!(decode(&[1, 2], &i64::sql_type()).is_err());
+
+    let mut encoding = timestamp::Encoding::new();
+    encoding.encoding = Some(timestamp::encoding::Encoding::UnixMicrosInt64(
+        match ordered_int64().kind {
+            Some(type_::Kind::Int64Type(i)) => i.encoding.unwrap(),
+            _ => unreachable!(),
+        },
+    ));
+    let mut ts_type = type_::Timestamp::new();
+    ts_type.encoding = Some(encoding).into();
+    let mut ty = Type::new();
+    ty.kind = Some(type_::Kind::TimestampType(ts_type));
+
+    let mut ts = Timestamp::new();
+    ts.seconds = -2;
+    ts.nanos = 500_000_000;
+    let mut v = Value::new();
+    v.kind = Some(value::Kind::TimestampValue(ts.clone()));
+    assert_eq!(
+        round_trip(&v, &ty),
+        bigtable::keys::encode_ordered_i64(-1_500_000)
+    );
+
+    ts.nanos = 1;
+    v.kind = Some(value::Kind::TimestampValue(ts));
+    assert!(matches!(encode(&v, &ty), Err(BTErr::TypeErr(_))));
+}
+
+#[test]
+fn test_null_escaping() {
+    let ty = escaped_bytes();
+    assert_eq!(round_trip(&Value::new(), &ty), b"");
+    assert_eq!(round_trip(&b"".to_vec().to_value(), &ty), b"\x00");
+    assert_eq!(round_trip(&b"\x00".to_vec().to_value(), &ty), b"\x00\x00");
+    assert_eq!(round_trip(&b"a\x00".to_vec().to_value(), &ty), b"a\x00");
+
+    let ty = escaped_string("#");
+    assert_eq!(round_trip(&Value::new(), &ty), b"");
+    assert_eq!(round_trip(&"##".to_value(), &ty), b"###");
+    assert_eq!(round_trip(&"a#".to_value(), &ty), b"a#");
+    assert!(encode(&Value::new(), &String::sql_type()).is_err());
+    assert!(encode(&Value::new(), &escaped_string("ab")).is_err());
+}
+
+#[test]
+fn test_struct_encodings() {
+    let ordered = struct_type(
+        vec![String::sql_type(), ordered_int64(), String::sql_type()],
+        struct_::encoding::Encoding::OrderedCodeBytes(Default::default()),
+    );
+    let v = struct_value(vec!["user\x00".to_value(), 42i64.to_value(), "".to_value()]);
+    assert_eq!(
+        round_trip(&v, &ordered),
+        KeyBuilder::new().string("user\x00").int64(42).build()
+    );
+
+    let delimited = struct_type(
+        vec![String::sql_type(), String::sql_type()],
+        struct_::encoding::Encoding::DelimitedBytes({
+            let mut d = struct_::encoding::DelimitedBytes::new();
+            d.delimiter = b"#".to_vec();
+            d
+        }),
+    );
+    let v = struct_value(vec!["a".to_value(), "b".to_value()]);
+    assert_eq!(round_trip(&v, &delimited), b"a#b");
+    let v = struct_value(vec!["a#".to_value(), "b".to_value()]);
+    assert!(matches!(encode(&v, &delimited), Err(BTErr::TypeErr(_))));
+    assert!(decode(b"a#b#c", &delimited).is_err());
+
+    let singleton = struct_type(
+        vec![ordered_int64()],
+        struct_::encoding::Encoding::Singleton(Default::default()),
+    );
+    assert_eq!(
+        round_trip(&struct_value(vec![5i64.to_value()]), &singleton),
+        vec![0x85]
+    );
+
+    assert!(matches!(
+        encode(&true.to_value(), &bool::sql_type()),
+        Err(BTErr::TypeErr(_))
+    ));
+}
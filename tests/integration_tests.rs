@@ -3,10 +3,11 @@
 // - Credentials file: "Rust Bigtable IAM Admin.json"
 // - Project: gen-lang-client-0421059902
 // - Instance: test-inst
-// - Table: my-table with column family cf1
+// - Table: my-table with column family cf1 and aggregate (intsum) family agg
 //
 // Run with: cargo test --test integration_tests -- --ignored --test-threads=1
 
+use bigtable::aggregate::{AggregateValue, Aggregator};
 use bigtable::error::BTErr;
 use bigtable::method::{
     BigTable, CheckAndMutateRow, ExecuteQuery, GenerateInitialChangeStreamPartitions,
@@ -14,10 +15,12 @@ use bigtable::method::{
     ReadRows, SampleRowKeys,
 };
 use bigtable::protos::bigtable::mutate_rows_request;
-use bigtable::protos::data::{mutation, row_filter, Mutation, ReadModifyWriteRule, RowFilter, read_modify_write_rule};
+use bigtable::protos::data::{mutation, row_filter, Mutation, ReadModifyWriteRule, RowFilter, RowSet, read_modify_write_rule};
 use bigtable::request::BTRequest;
+use bigtable::scan;
 use bigtable::support::{Instance, Project, Table};
 use bigtable::utils::{encode_str, get_auth_token};
+use bigtable::wraps;
 use goauth::auth::Token;
 use serde_json::Value;
 
@@ -26,6 +29,7 @@ const PROJECT_ID: &str = "gen-lang-client-0421059902";
 const INSTANCE_ID: &str = "test-inst";
 const TABLE_NAME: &str = "my-table";
 const COLUMN_FAMILY: &str = "cf1";
+const AGGREGATE_FAMILY: &str = "agg";
 
 fn get_token() -> Result<Token, BTErr> {
     get_auth_token(CREDENTIALS_FILE, true)
@@ -40,6 +44,8 @@ fn get_table() -> Table {
                 name: String::from(PROJECT_ID),
             },
         },
+        app_profile_id: String::new(),
+        view: None,
     }
 }
 
@@ -235,6 +241,73 @@ fn test_read_modify_write_row() {
     assert!(!is_error_response(&response), "ReadModifyWriteRow returned error");
 }
 
+#[test]
+#[ignore]
+fn test_put_if_absent_and_compare_and_set() {
+    let token = get_token().expect("Failed to get token");
+    let table = get_table();
+    let row_key = format!("test_row_cas_{}", std::process::id());
+    let row_key = row_key.as_bytes();
+
+    let written = wraps::put_if_absent(&table, &token, row_key, COLUMN_FAMILY, b"state", b"new")
+        .expect("put_if_absent failed");
+    assert!(written);
+    let written = wraps::put_if_absent(&table, &token, row_key, COLUMN_FAMILY, b"state", b"other")
+        .expect("put_if_absent failed");
+    assert!(!written);
+
+    let swapped = wraps::compare_and_set(&table, &token, row_key, COLUMN_FAMILY, b"state", b"stale", b"x")
+        .expect("compare_and_set failed");
+    assert!(!swapped);
+    let swapped = wraps::compare_and_set(&table, &token, row_key, COLUMN_FAMILY, b"state", b"new", b"done.*")
+        .expect("compare_and_set failed");
+    assert!(swapped);
+
+    wraps::check_and_mutate(
+        &table,
+        &token,
+        row_key,
+        bigtable::filters::pass_all(),
+        wraps::RowMutation::new().delete_row(),
+        wraps::RowMutation::new(),
+    )
+    .expect("check_and_mutate failed");
+}
+
+#[test]
+#[ignore]
+fn test_increment_and_append() {
+    let token = get_token().expect("Failed to get token");
+    let table = get_table();
+    let row_key = b"test_row_counter";
+
+    let first = wraps::increment(&table, &token, row_key, COLUMN_FAMILY, b"counter", 1)
+        .expect("Increment failed");
+    let second = wraps::increment(&table, &token, row_key, COLUMN_FAMILY, b"counter", 10)
+        .expect("Increment failed");
+    assert_eq!(second, first + 10);
+
+    let before = wraps::append(&table, &token, row_key, COLUMN_FAMILY, b"log", b"a")
+        .expect("Append failed");
+    let after = wraps::append(&table, &token, row_key, COLUMN_FAMILY, b"log", b"b")
+        .expect("Append failed");
+    assert_eq!(after, [before, b"b".to_vec()].concat());
+}
+
+#[test]
+#[ignore]
+fn test_parallel_scan_matches_read_rows() {
+    let token = get_token().expect("Failed to get token");
+    let table = get_table();
+
+    let samples = wraps::sample_row_keys(&table, &token).expect("SampleRowKeys failed");
+    assert!(samples.last().is_some_and(|s| s.row_key.is_empty()));
+
+    let rows = scan::parallel_scan(&table, &token, RowSet::new(), 4).expect("Scan failed");
+    let keys: Vec<&Vec<u8>> = rows.iter().map(|r| &r.key).collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+}
+
 // ============================================================================
 // Connection Management
 // ============================================================================
@@ -403,3 +476,27 @@ fn test_write_then_read() {
     assert!(read_response.is_array() || read_response.is_object(),
             "Expected array or object response");
 }
+
+// ============================================================================
+// Aggregate Column Families
+// ============================================================================
+
+#[test]
+#[ignore]
+fn test_add_to_cell_sum() {
+    let token = get_token().expect("Failed to get token");
+    let table = get_table();
+    let row_key = b"test_row_aggregate";
+
+    let before = wraps::read_aggregate(&table, &token, row_key, AGGREGATE_FAMILY, b"hits", Aggregator::Sum)
+        .expect("Read aggregate failed")
+        .and_then(|v| v.as_i64())
+        .unwrap_or(0);
+
+    wraps::add_to_cell(&table, &token, row_key, AGGREGATE_FAMILY, b"hits", 0, 5).expect("AddToCell failed");
+    wraps::add_to_cell(&table, &token, row_key, AGGREGATE_FAMILY, b"hits", 0, -2).expect("AddToCell failed");
+
+    let after = wraps::read_aggregate(&table, &token, row_key, AGGREGATE_FAMILY, b"hits", Aggregator::Sum)
+        .expect("Read aggregate failed");
+    assert_eq!(after, Some(AggregateValue::Sum(before + 3)));
+}
@@ -0,0 +1,165 @@
+// AIDEV-NOTE: Key codec tests use the examples from types.proto; the salted scan
+// runs against the in-memory stand-in in tests/common.
+
+mod common;
+
+use bigtable::keys::{self, KeyBuilder, KeyReader, Salt};
+use std::ops::Bound;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn test_ordered_i64_sorts_and_round_trips() {
+    assert_eq!(keys::encode_ordered_i64(0), vec![0x80]);
+    assert_eq!(keys::encode_ordered_i64(-1), vec![0x7f]);
+    assert_eq!(keys::encode_ordered_i64(64), vec![0xc0, 0x40]);
+    assert_eq!(keys::encode_ordered_i64(i64::MAX).len(), 10);
+
+    let mut values = vec![i64::MIN, i64::MAX, 0, -1, 1, -64, -65, 63, 64];
+    for shift in 0..63 {
+        values.push(1 << shift);
+        values.push(-(1 << shift));
+        values.push((1 << shift) - 1);
+    }
+    values.sort();
+    values.dedup();
+    let encoded: Vec<Vec<u8>> = values
+        .iter()
+        .map(|v| keys::encode_ordered_i64(*v))
+        .collect();
+    for (v, bytes) in values.iter().zip(&encoded) {
+        assert_eq!(keys::decode_ordered_i64(bytes).unwrap(), (*v, bytes.len()));
+    }
+    assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+
+    assert!(keys::decode_ordered_i64(&[]).is_err());
+    assert!(keys::decode_ordered_i64(&[0xc0]).is_err());
+}
+
+#[test]
+fn test_composite_keys_match_struct_encoding() {
+    let key = |fields: &[&str]| {
+        fields
+            .iter()
+            .fold(KeyBuilder::new(), |b, f| b.string(f))
+            .build()
+    };
+    assert_eq!(key(&[]), b"\x00\x00");
+    assert_eq!(key(&["", ""]), b"\x00\x00");
+    assert_eq!(key(&["", "B"]), b"\x00\x00\x00\x01B");
+    assert_eq!(key(&["A", ""]), b"A");
+    assert_eq!(key(&["A", "", "C"]), b"A\x00\x01\x00\x00\x00\x01C");
+    assert_eq!(key(&["a\x00b"]), b"a\x00\xffb");
+
+    // Element-wise order, strict prefixes first
+    assert!(key(&["a"]) < key(&["a", "b"]));
+    assert!(key(&["a", "z"]) < key(&["a\x00"]));
+    assert!(
+        KeyBuilder::new().string("u").int64(-5).build()
+            < KeyBuilder::new().string("u").int64(3).build()
+    );
+
+    let at = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+    let built = KeyBuilder::new()
+        .string("user\x00x")
+        .int64(-42)
+        .bytes(b"")
+        .reverse_timestamp(at)
+        .build();
+    let mut reader = KeyReader::new(&built).unwrap();
+    assert_eq!(reader.string().unwrap(), "user\x00x");
+    assert_eq!(reader.int64().unwrap(), -42);
+    assert_eq!(reader.bytes(), b"");
+    assert_eq!(reader.reverse_timestamp().unwrap(), at);
+    assert!(reader.is_empty());
+    assert_eq!(reader.string().unwrap(), "");
+
+    assert!(KeyReader::new(b"a\x00\x02").is_err());
+}
+
+#[test]
+fn test_pre_epoch_timestamps_round_trip() {
+    let before = UNIX_EPOCH - Duration::from_micros(86_400_000_001);
+    assert_eq!(keys::timestamp_micros(before), -86_400_000_001);
+    assert_eq!(keys::from_timestamp_micros(-86_400_000_001), before);
+    // Sub-microsecond parts round towards the earlier time
+    assert_eq!(
+        keys::timestamp_micros(UNIX_EPOCH - Duration::from_nanos(1500)),
+        -2
+    );
+
+    let key = KeyBuilder::new()
+        .timestamp(before)
+        .timestamp(UNIX_EPOCH)
+        .build();
+    assert!(key < KeyBuilder::new().timestamp(UNIX_EPOCH).build());
+    let mut reader = KeyReader::new(&key).unwrap();
+    assert_eq!(reader.timestamp().unwrap(), before);
+    assert_eq!(reader.timestamp().unwrap(), UNIX_EPOCH);
+}
+
+#[test]
+fn test_reverse_timestamps_sort_newest_first() {
+    let older = UNIX_EPOCH + Duration::from_secs(1000);
+    let newer = UNIX_EPOCH + Duration::from_secs(2000);
+    assert!(keys::reverse_timestamp(newer) < keys::reverse_timestamp(older));
+    assert_eq!(
+        keys::from_reverse_timestamp(keys::reverse_timestamp(older)),
+        older
+    );
+
+    let prefix = KeyBuilder::new().string("sensor-1");
+    let rows = prefix.prefix_row_set();
+    let key = prefix.clone().reverse_timestamp(newer).build();
+    let range = &rows.row_ranges[0];
+    assert_eq!(
+        range.start_key,
+        Some(
+            bigtable::protos::data::row_range::Start_key::StartKeyClosed(
+                b"sensor-1\x00\x01".to_vec()
+            )
+        )
+    );
+    assert!(key.starts_with(b"sensor-1\x00\x01"));
+}
+
+#[test]
+fn test_salted_keys_fan_out_across_buckets() {
+    let salt = Salt::new(12);
+    assert_eq!(salt.prefix(3), b"03#");
+    let salted = salt.salt(b"2024-06-01");
+    assert_eq!(salt.unsalt(&salted).unwrap(), b"2024-06-01");
+    assert!(salt.unsalt(b"2024-06-01").is_err());
+
+    let fake = common::FakeBigtable::new();
+    for day in 1..=20 {
+        let key = format!("2024-06-{:02}", day);
+        fake.set_cell(&salt.salt(key.as_bytes()), "cf1", b"q", 1000, b"v");
+    }
+    let token = common::token();
+    let mut rows = salt
+        .scan(
+            &common::table(),
+            &token,
+            Bound::Included(b"2024-06-05"),
+            Bound::Excluded(b"2024-06-10"),
+        )
+        .transport(&fake)
+        .collect()
+        .unwrap();
+    assert_eq!(
+        salt.row_set(Bound::Unbounded, Bound::Unbounded)
+            .row_ranges
+            .len(),
+        12
+    );
+
+    salt.unsalted_order(&mut rows);
+    let keys: Vec<Vec<u8>> = rows
+        .iter()
+        .map(|r| salt.unsalt(&r.key).unwrap().to_vec())
+        .collect();
+    let expected: Vec<Vec<u8>> = (5..10)
+        .map(|day| format!("2024-06-{:02}", day).into_bytes())
+        .collect();
+    assert_eq!(keys, expected);
+}
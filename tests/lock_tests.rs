@@ -0,0 +1,99 @@
+// AIDEV-NOTE: Lease tests run against the in-memory stand-in in tests/common.
+
+mod common;
+
+use bigtable::lock::{Lease, LeaseTable};
+use common::FakeBigtable;
+use std::thread::sleep;
+use std::time::Duration;
+
+const TTL: Duration = Duration::from_millis(200);
+
+#[test]
+fn test_acquire_is_exclusive() {
+    let fake = FakeBigtable::new();
+    let token = common::token();
+    let leases = LeaseTable::new(&common::table(), &token).transport(&fake);
+
+    let lease = Lease::acquire(&leases, b"job", b"worker-1", TTL).unwrap();
+    assert!(lease.is_some());
+    assert!(Lease::acquire(&leases, b"job", b"worker-2", TTL)
+        .unwrap()
+        .is_none());
+    assert!(Lease::acquire(&leases, b"other-job", b"worker-2", TTL)
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_expired_lease_can_be_taken_over() {
+    let fake = FakeBigtable::new();
+    let token = common::token();
+    let leases = LeaseTable::new(&common::table(), &token).transport(&fake);
+
+    let mut first = Lease::acquire(&leases, b"job", b"worker-1", TTL)
+        .unwrap()
+        .unwrap();
+    sleep(TTL + Duration::from_millis(50));
+    assert!(first.is_expired());
+
+    let second = Lease::acquire(&leases, b"job", b"worker-2", TTL)
+        .unwrap()
+        .unwrap();
+    assert!(second.fencing_token > first.fencing_token);
+
+    // The stale holder can neither renew nor release the new holder's lease
+    assert!(!first.renew(&leases, TTL).unwrap());
+    assert!(!first.release(&leases).unwrap());
+    assert!(Lease::acquire(&leases, b"job", b"worker-3", TTL)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_renew_and_release() {
+    let fake = FakeBigtable::new();
+    let token = common::token();
+    let leases = LeaseTable::new(&common::table(), &token)
+        .family("locks")
+        .transport(&fake);
+
+    let mut lease = Lease::acquire(&leases, b"job", b"worker-1", TTL)
+        .unwrap()
+        .unwrap();
+    let expires = lease.expires_at_micros;
+    sleep(Duration::from_millis(20));
+    assert!(lease.renew(&leases, TTL).unwrap());
+    assert!(lease.expires_at_micros > expires);
+
+    let token_before = lease.fencing_token;
+    assert!(lease.release(&leases).unwrap());
+    assert!(fake
+        .cells(b"job")
+        .iter()
+        .all(|c| c.family == "locks" && c.qualifier == b"fence"));
+
+    let next = Lease::acquire(&leases, b"job", b"worker-2", TTL)
+        .unwrap()
+        .unwrap();
+    assert!(next.fencing_token > token_before);
+}
+
+#[test]
+fn test_owner_can_reacquire() {
+    let fake = FakeBigtable::new();
+    let token = common::token();
+    let leases = LeaseTable::new(&common::table(), &token).transport(&fake);
+
+    let first = Lease::acquire(&leases, b"job", b"worker.1", TTL)
+        .unwrap()
+        .unwrap();
+    // Regex metacharacters in the owner must not let a different owner match
+    assert!(Lease::acquire(&leases, b"job", b"workerX1", TTL)
+        .unwrap()
+        .is_none());
+    let again = Lease::acquire(&leases, b"job", b"worker.1", TTL)
+        .unwrap()
+        .unwrap();
+    assert!(again.fencing_token > first.fencing_token);
+}
@@ -0,0 +1,278 @@
+// AIDEV-NOTE: #[derive(BigtableRow)] tests. Rows are built by hand in the
+// shape ReadRows returns them, so these run without a transport.
+
+use bigtable::error::BTErr;
+use bigtable::mapping::BigtableRow as _;
+use bigtable::mapping::CellTimestamp;
+use bigtable::protos::data::{mutation, Cell, Column, Family, Row};
+use bigtable_derive::BigtableRow;
+use serde_derive::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(BigtableRow, Debug, PartialEq)]
+#[bigtable(family = "cf1")]
+struct User {
+    #[bigtable(row_key)]
+    id: String,
+    name: String,
+    age: i64,
+    #[bigtable(qualifier = "mail")]
+    email: Option<String>,
+    #[bigtable(family = "meta", with = "bigtable::mapping::json")]
+    prefs: Prefs,
+    logins: Vec<(SystemTime, String)>,
+    #[bigtable(skip)]
+    cached: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Prefs {
+    theme: String,
+}
+
+fn user() -> User {
+    User {
+        id: String::from("user#1"),
+        name: String::from("Ann"),
+        age: 42,
+        email: Some(String::from("ann@example.com")),
+        prefs: Prefs {
+            theme: String::from("dark"),
+        },
+        logins: vec![
+            (UNIX_EPOCH + Duration::from_secs(2), String::from("web")),
+            (UNIX_EPOCH + Duration::from_secs(1), String::from("cli")),
+        ],
+        cached: 7,
+    }
+}
+
+// The row `user.to_mutations()` writes, as ReadRows would return it.
+fn apply(key: &[u8], mutations: &[bigtable::protos::data::Mutation]) -> Row {
+    let mut row = Row::new();
+    row.key = key.to_vec();
+    for m in mutations {
+        let set = match &m.mutation {
+            Some(mutation::Mutation::SetCell(set)) => set,
+            _ => continue,
+        };
+        let family = match row.families.iter().position(|f| f.name == set.family_name) {
+            Some(i) => &mut row.families[i],
+            None => {
+                let mut f = Family::new();
+                f.name = set.family_name.clone();
+                row.families.push(f);
+                row.families.last_mut().unwrap()
+            }
+        };
+        let column = match family
+            .columns
+            .iter()
+            .position(|c| c.qualifier == set.column_qualifier)
+        {
+            Some(i) => &mut family.columns[i],
+            None => {
+                let mut c = Column::new();
+                c.qualifier = set.column_qualifier.clone();
+                family.columns.push(c);
+                family.columns.last_mut().unwrap()
+            }
+        };
+        let mut cell = Cell::new();
+        // -1 is the server time
+        cell.timestamp_micros = match set.timestamp_micros {
+            -1 => 100,
+            ts => ts,
+        };
+        cell.value = set.value.clone();
+        column.cells.push(cell);
+    }
+    row
+}
+
+#[test]
+fn test_derived_row_round_trip() {
+    let user = user();
+    assert_eq!(user.row_key().unwrap(), b"user#1");
+
+    let mutations = user.to_mutations().unwrap().mutations;
+    let columns: Vec<(String, Vec<u8>, i64)> = mutations
+        .iter()
+        .map(|m| match &m.mutation {
+            Some(mutation::Mutation::SetCell(s)) => (
+                s.family_name.clone(),
+                s.column_qualifier.clone(),
+                s.timestamp_micros,
+            ),
+            // versioned columns are cleared before their versions are written
+            Some(mutation::Mutation::DeleteFromColumn(d)) => {
+                (d.family_name.clone(), d.column_qualifier.clone(), 0)
+            }
+            other => panic!("unexpected mutation {:?}", other),
+        })
+        .collect();
+    assert_eq!(
+        columns,
+        vec![
+            (String::from("cf1"), b"name".to_vec(), -1),
+            (String::from("cf1"), b"age".to_vec(), -1),
+            (String::from("cf1"), b"mail".to_vec(), -1),
+            (String::from("meta"), b"prefs".to_vec(), -1),
+            (String::from("cf1"), b"logins".to_vec(), 0),
+            (String::from("cf1"), b"logins".to_vec(), 2_000_000),
+            (String::from("cf1"), b"logins".to_vec(), 1_000_000),
+        ]
+    );
+
+    let row = apply(&user.row_key().unwrap(), &mutations);
+    assert_eq!(
+        bigtable::rows::latest_cell(&row, "cf1", b"age")
+            .unwrap()
+            .value,
+        vec![0, 0, 0, 0, 0, 0, 0, 42]
+    );
+    assert_eq!(
+        bigtable::rows::latest_cell(&row, "meta", b"prefs")
+            .unwrap()
+            .value,
+        br#"{"theme":"dark"}"#
+    );
+
+    let read = User::from_row(&row).unwrap();
+    assert_eq!(read, User { cached: 0, ..user });
+
+    let entry = read.to_entry().unwrap();
+    assert_eq!(entry.row_key, b"user#1");
+    assert_eq!(entry.mutations.len(), 7);
+}
+
+#[test]
+fn test_derived_row_optional_and_versioned_fields() {
+    let mut user = user();
+    user.email = None;
+    user.logins.clear();
+    let mutations = user.to_mutations().unwrap().mutations;
+    assert_eq!(mutations.len(), 5);
+    match &mutations[2].mutation {
+        Some(mutation::Mutation::DeleteFromColumn(d)) => {
+            assert_eq!(d.family_name, "cf1");
+            assert_eq!(d.column_qualifier, b"mail");
+        }
+        other => panic!("expected DeleteFromColumn, got {:?}", other),
+    }
+
+    // An older write still in the row is not the latest version
+    let mut row = apply(&user.row_key().unwrap(), &mutations);
+    let mut old = Cell::new();
+    old.timestamp_micros = 5;
+    old.value = b"Bob".to_vec();
+    row.families[0].columns[0].cells.push(old);
+    for ts in [3_000_000, 9_000_000] {
+        let mut cell = Cell::new();
+        cell.timestamp_micros = ts;
+        cell.value = format!("v{}", ts).into_bytes();
+        let mut column = Column::new();
+        column.qualifier = b"logins".to_vec();
+        column.cells.push(cell);
+        row.families[0].columns.push(column);
+    }
+
+    let read = User::from_row(&row).unwrap();
+    assert_eq!(read.name, "Ann");
+    assert_eq!(read.email, None);
+    assert_eq!(
+        read.logins,
+        vec![
+            (
+                UNIX_EPOCH + Duration::from_secs(9),
+                String::from("v9000000")
+            ),
+            (
+                UNIX_EPOCH + Duration::from_secs(3),
+                String::from("v3000000")
+            ),
+        ]
+    );
+
+    // Cell timestamps are whole milliseconds
+    user.logins = vec![(
+        UNIX_EPOCH + Duration::from_micros(4_000_123),
+        String::from("api"),
+    )];
+    let timestamps: Vec<i64> = user
+        .to_mutations()
+        .unwrap()
+        .mutations
+        .iter()
+        .filter_map(|m| match &m.mutation {
+            Some(mutation::Mutation::SetCell(s)) if s.column_qualifier == b"logins" => {
+                Some(s.timestamp_micros)
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(timestamps, [4_000_000]);
+
+    // Versions before the epoch keep their time
+    let before = UNIX_EPOCH - Duration::from_millis(1500);
+    assert_eq!(before.to_micros(), -1_500_000);
+    assert_eq!(SystemTime::from_micros(-1_500_000), before);
+}
+
+#[test]
+fn test_derived_row_decode_errors() {
+    #[derive(BigtableRow, Debug)]
+    struct Counter {
+        #[bigtable(row_key)]
+        key: Vec<u8>,
+        #[bigtable(family = "stats")]
+        hits: u64,
+        #[bigtable(family = "stats")]
+        history: Vec<(i64, bool)>,
+    }
+
+    let counter = Counter {
+        key: b"c1".to_vec(),
+        hits: 3,
+        history: vec![(10, true)],
+    };
+    let mut row = apply(
+        &counter.row_key().unwrap(),
+        &counter.to_mutations().unwrap().mutations,
+    );
+    let read = Counter::from_row(&row).unwrap();
+    assert_eq!(
+        (read.key, read.hits, read.history),
+        (b"c1".to_vec(), 3, vec![(10, true)])
+    );
+
+    row.families[0].columns[0].cells[0].value = vec![1, 2];
+    assert!(matches!(Counter::from_row(&row), Err(BTErr::DecodeErr(_))));
+
+    row.families[0].columns.remove(0);
+    match Counter::from_row(&row) {
+        Err(BTErr::DecodeErr(message)) => assert!(message.contains("stats:hits")),
+        other => panic!("expected DecodeErr, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_derived_row_encode_errors() {
+    #[derive(BigtableRow, Debug)]
+    struct Scores {
+        #[bigtable(row_key)]
+        key: String,
+        // JSON object keys must be strings
+        #[bigtable(family = "cf1", with = "bigtable::mapping::json")]
+        by_pair: std::collections::BTreeMap<(u8, u8), u32>,
+    }
+
+    let mut scores = Scores {
+        key: String::from("s1"),
+        by_pair: Default::default(),
+    };
+    assert!(scores.to_mutations().is_ok());
+    scores.by_pair.insert((1, 2), 3);
+    assert!(matches!(scores.to_mutations(), Err(BTErr::SerdeErr(_))));
+    assert!(scores.to_entry().is_err());
+}
@@ -0,0 +1,595 @@
+// AIDEV-NOTE: Query tests feed hand-built PartialResultSet streams to the decoder
+// and to the in-memory stand-in in tests/common.
+
+mod common;
+
+use bigtable::convert::{Date, SqlType, ToValue};
+use bigtable::error::BTErr;
+use bigtable::protos::bigtable::{
+    execute_query_response, ExecuteQueryRequest, ExecuteQueryResponse,
+};
+use bigtable::protos::data::{
+    partial_result_set, result_set_metadata, value, ArrayValue, ColumnMetadata, PartialResultSet,
+    ProtoRows, ProtoRowsBatch, ProtoSchema, ResultSetMetadata, Value,
+};
+use bigtable::protos::types::{type_, Type};
+use bigtable::query::{PreparedStatement, Query, QueryRow, ResultDecoder, ResultSet};
+use common::FakeBigtable;
+use protobuf::Message;
+use serde_derive::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn int(i: i64) -> Value {
+    let mut v = Value::new();
+    v.kind = Some(value::Kind::IntValue(i));
+    v
+}
+
+fn ints(row: &bigtable::query::QueryRow) -> Vec<i64> {
+    row.values
+        .iter()
+        .map(|v| match v.kind {
+            Some(value::Kind::IntValue(i)) => i,
+            _ => panic!("not an int: {:?}", v),
+        })
+        .collect()
+}
+
+fn metadata(names: &[&str]) -> ResultSetMetadata {
+    let mut schema = ProtoSchema::new();
+    for name in names {
+        let mut column = ColumnMetadata::new();
+        column.name = String::from(*name);
+        column.type_ = Some(i64::sql_type()).into();
+        schema.columns.push(column);
+    }
+    let mut metadata = ResultSetMetadata::new();
+    metadata.schema = Some(result_set_metadata::Schema::ProtoSchema(schema));
+    metadata
+}
+
+fn batch(values: &[i64]) -> Vec<u8> {
+    let mut rows = ProtoRows::new();
+    rows.values = values.iter().map(|i| int(*i)).collect();
+    rows.write_to_bytes().unwrap()
+}
+
+fn results(data: &[u8], checksum: Option<u32>, token: &[u8], reset: bool) -> ExecuteQueryResponse {
+    let mut batch = ProtoRowsBatch::new();
+    batch.batch_data = data.to_vec();
+    let mut results = PartialResultSet::new();
+    results.partial_rows = Some(partial_result_set::Partial_rows::ProtoRowsBatch(batch));
+    results.batch_checksum = checksum;
+    results.resume_token = token.to_vec();
+    results.reset = reset;
+    let mut response = ExecuteQueryResponse::new();
+    response.response = Some(execute_query_response::Response::Results(results));
+    response
+}
+
+#[test]
+fn test_decoder_buffers_batches_until_commit() {
+    let mut decoder = ResultDecoder::with_metadata(&metadata(&["a", "b"])).unwrap();
+
+    // One batch split over three messages, committed by the last one
+    let data = batch(&[1, 2, 3, 4]);
+    let (first, rest) = data.split_at(3);
+    let (second, third) = rest.split_at(4);
+    assert!(decoder
+        .push(results(first, None, b"", false))
+        .unwrap()
+        .is_empty());
+    assert!(decoder
+        .push(results(second, None, b"", false))
+        .unwrap()
+        .is_empty());
+    let rows = decoder
+        .push(results(third, Some(crc32c::crc32c(&data)), b"t1", false))
+        .unwrap();
+    assert_eq!(rows.iter().map(ints).collect::<Vec<_>>(), [[1, 2], [3, 4]]);
+    assert_eq!(decoder.resume_token(), b"t1");
+
+    // A complete but uncommitted batch is dropped by a reset
+    let stale = batch(&[9, 9]);
+    decoder
+        .push(results(&stale, Some(crc32c::crc32c(&stale)), b"", false))
+        .unwrap();
+    let fresh = batch(&[5, 6]);
+    let rows = decoder
+        .push(results(&fresh, Some(crc32c::crc32c(&fresh)), b"t2", true))
+        .unwrap();
+    assert_eq!(rows.iter().map(ints).collect::<Vec<_>>(), [[5, 6]]);
+    decoder.finish().unwrap();
+}
+
+#[test]
+fn test_decoder_rejects_bad_batches() {
+    let data = batch(&[1, 2]);
+
+    let mut decoder = ResultDecoder::with_metadata(&metadata(&["a", "b"])).unwrap();
+    assert!(decoder
+        .push(results(&data, Some(crc32c::crc32c(&data) ^ 1), b"t", false))
+        .is_err());
+
+    // Values must fill whole rows
+    let mut decoder = ResultDecoder::with_metadata(&metadata(&["a", "b", "c"])).unwrap();
+    assert!(decoder
+        .push(results(&data, Some(crc32c::crc32c(&data)), b"t", false))
+        .is_err());
+
+    // Rows need the schema
+    let mut decoder = ResultDecoder::new();
+    assert!(decoder
+        .push(results(&data, Some(crc32c::crc32c(&data)), b"t", false))
+        .is_err());
+
+    // The stream must not end before the commit
+    let mut decoder = ResultDecoder::with_metadata(&metadata(&["a", "b"])).unwrap();
+    decoder
+        .push(results(&data, Some(crc32c::crc32c(&data)), b"", false))
+        .unwrap();
+    assert!(decoder.finish().is_err());
+}
+
+fn print<M: protobuf::MessageFull>(m: &M) -> serde_json::Value {
+    serde_json::from_str(&protobuf_json_mapping::print_to_string(m).unwrap()).unwrap()
+}
+
+#[test]
+fn test_query_prepares_and_executes() {
+    let fake = FakeBigtable::new();
+    fake.set_prepared_query(json!({
+        "metadata": print(&metadata(&["_key", "n"])),
+        "preparedQuery": "cHE="
+    }));
+    let first = batch(&[1, 10]);
+    let second = batch(&[2, 20]);
+    fake.script_query(json!([
+        print(&results(&first, Some(crc32c::crc32c(&first)), b"", false)),
+        print(&results(
+            &second,
+            Some(crc32c::crc32c(&second)),
+            b"t",
+            false
+        )),
+    ]));
+
+    let token = common::token();
+    let result = Query::new(&common::table().instance, &token, "SELECT _key, n FROM t")
+        .transport(&fake)
+        .execute()
+        .unwrap();
+    assert_eq!(result.columns.len(), 2);
+    assert_eq!(
+        result.rows.iter().map(ints).collect::<Vec<_>>(),
+        [[1, 10], [2, 20]]
+    );
+    assert_eq!(result.value(1, "n"), Some(&int(20)));
+    assert_eq!(result.get::<i64>(1, "n").unwrap(), 20);
+    assert!(matches!(
+        result.get::<String>(1, "n"),
+        Err(BTErr::TypeErr(_))
+    ));
+    assert_eq!(result.value(2, "n"), None);
+    assert!(matches!(result.get::<i64>(2, "n"), Err(BTErr::TypeErr(_))));
+
+    let requests = fake.requests();
+    assert_eq!(requests[0].0, "prepareQuery");
+    let execute: ExecuteQueryRequest =
+        protobuf_json_mapping::parse_from_str(&requests[1].1).unwrap();
+    assert_eq!(execute.prepared_query, b"pq");
+}
+
+fn prepared(fake: &FakeBigtable) {
+    fake.set_prepared_query(json!({
+        "metadata": print(&metadata(&["_key", "n"])),
+        "preparedQuery": "cHE="
+    }));
+}
+
+fn committed(values: &[i64], token: &[u8]) -> serde_json::Value {
+    let data = batch(values);
+    print(&results(&data, Some(crc32c::crc32c(&data)), token, false))
+}
+
+#[test]
+fn test_query_resumes_after_last_commit() {
+    let fake = FakeBigtable::new();
+    prepared(&fake);
+    fake.script_query(json!([
+        committed(&[1, 10], b"t1"),
+        committed(&[2, 20], b""),
+        {"error": {"code": 503, "message": "stream reset", "status": "UNAVAILABLE"}}
+    ]));
+    fake.script_query(json!([committed(&[2, 20, 3, 30], b"t2")]));
+
+    let token = common::token();
+    let mut rows = Vec::new();
+    Query::new(&common::table().instance, &token, "SELECT _key, n FROM t")
+        .retry_delay(Duration::from_millis(1))
+        .transport(&fake)
+        .for_each(|row| rows.push(ints(&row)))
+        .unwrap();
+    // The uncommitted row of the broken stream is not repeated
+    assert_eq!(rows, [[1, 10], [2, 20], [3, 30]]);
+
+    let tokens: Vec<Vec<u8>> = fake
+        .requests()
+        .iter()
+        .filter(|(method, _)| method == "executeQuery")
+        .map(|(_, body)| {
+            let req: ExecuteQueryRequest = protobuf_json_mapping::parse_from_str(body).unwrap();
+            req.resume_token
+        })
+        .collect();
+    assert_eq!(tokens, [b"".to_vec(), b"t1".to_vec()]);
+}
+
+#[test]
+fn test_query_uses_app_profile() {
+    let fake = FakeBigtable::new();
+    prepared(&fake);
+    fake.script_query(json!([committed(&[1, 10], b"t")]));
+
+    let token = common::token();
+    Query::new(&common::table().instance, &token, "SELECT _key, n FROM t")
+        .app_profile("batch")
+        .transport(&fake)
+        .execute()
+        .unwrap();
+    for (_, body) in fake.requests() {
+        assert!(body.contains(r#""appProfileId": "batch""#), "{}", body);
+    }
+    for headers in fake.headers() {
+        assert!(headers.contains(&String::from(
+            "x-goog-request-params: name=projects%2Ftest-project%2Finstances%2Ftest-inst&app_profile_id=batch"
+        )));
+    }
+}
+
+#[test]
+fn test_query_gives_up_on_permanent_errors() {
+    let fake = FakeBigtable::new();
+    prepared(&fake);
+    fake.script_query(json!([
+        {"error": {"code": 400, "message": "bad query", "status": "INVALID_ARGUMENT"}}
+    ]));
+
+    let token = common::token();
+    let err = Query::new(&common::table().instance, &token, "SELECT _key, n FROM t")
+        .transport(&fake)
+        .execute()
+        .unwrap_err();
+    assert!(matches!(err, BTErr::ApiErr(ref e) if e.status == "INVALID_ARGUMENT"));
+
+    // Retryable errors are retried only `max_retries` times
+    for _ in 0..2 {
+        fake.script_query(json!([
+            {"error": {"code": 503, "message": "unavailable", "status": "UNAVAILABLE"}}
+        ]));
+    }
+    let err = Query::new(&common::table().instance, &token, "SELECT _key, n FROM t")
+        .max_retries(1)
+        .retry_delay(Duration::from_millis(1))
+        .transport(&fake)
+        .execute()
+        .unwrap_err();
+    assert!(err.is_retryable());
+}
+
+fn int64_type() -> Type {
+    let mut ty = Type::new();
+    ty.kind = Some(type_::Kind::Int64Type(Default::default()));
+    ty
+}
+
+fn string(s: &str) -> Value {
+    let mut v = Value::new();
+    v.kind = Some(value::Kind::StringValue(String::from(s)));
+    v
+}
+
+fn array(values: Vec<Value>) -> Value {
+    let mut array = ArrayValue::new();
+    array.values = values;
+    let mut v = Value::new();
+    v.kind = Some(value::Kind::ArrayValue(array));
+    v
+}
+
+#[test]
+fn test_bind_checks_declared_types() {
+    let fake = FakeBigtable::new();
+    prepared(&fake);
+    let mut ids = type_::Array::new();
+    ids.element_type = Some(int64_type()).into();
+    let mut ids_type = Type::new();
+    ids_type.kind = Some(type_::Kind::ArrayType(ids));
+
+    let token = common::token();
+    let mut stmt = PreparedStatement::new(
+        &common::table().instance,
+        &token,
+        "SELECT _key, n FROM t WHERE n > @min AND n IN UNNEST(@ids)",
+    )
+    .param("min", int64_type())
+    .param("ids", ids_type)
+    .transport(&fake)
+    .prepare()
+    .unwrap();
+
+    assert!(matches!(
+        stmt.bind("min", string("1")),
+        Err(BTErr::TypeErr(_))
+    ));
+    assert!(matches!(stmt.bind("max", int(1)), Err(BTErr::TypeErr(_))));
+    assert!(matches!(
+        stmt.bind("ids", array(vec![int(1), string("2")])),
+        Err(BTErr::TypeErr(_))
+    ));
+
+    stmt.bind("min", int(5)).unwrap();
+    // Every declared parameter must be bound
+    assert!(matches!(stmt.execute(), Err(BTErr::TypeErr(_))));
+    stmt.bind("ids", array(vec![int(1), int(2)])).unwrap();
+    stmt.execute().unwrap();
+
+    // Rust values bind through `ToValue`
+    assert!(matches!(stmt.bind("min", "5"), Err(BTErr::TypeErr(_))));
+    stmt.bind("min", 5i64)
+        .unwrap()
+        .bind("ids", vec![1i64, 2])
+        .unwrap();
+    stmt.execute().unwrap();
+
+    let (_, body) = fake.requests().pop().unwrap();
+    let req: ExecuteQueryRequest = protobuf_json_mapping::parse_from_str(&body).unwrap();
+    assert_eq!(req.params["min"].type_.as_ref(), Some(&int64_type()));
+    assert_eq!(req.params["ids"].array_value().values.len(), 2);
+}
+
+#[test]
+fn test_statement_is_prepared_again_when_expired() {
+    let fake = FakeBigtable::new();
+    fake.set_prepared_query(json!({
+        "metadata": print(&metadata(&["_key", "n"])),
+        "preparedQuery": "cHE=",
+        "validUntil": "2000-01-01T00:00:00Z"
+    }));
+    let token = common::token();
+    let mut stmt =
+        PreparedStatement::new(&common::table().instance, &token, "SELECT _key, n FROM t")
+            .transport(&fake)
+            .prepare()
+            .unwrap();
+
+    // The service drops the plan before the first row; without details the
+    // passed valid_until marks it as expired
+    fake.script_query(json!([
+        {"error": {"code": 400, "message": "Prepared query expired", "status": "FAILED_PRECONDITION"}}
+    ]));
+    fake.script_query(json!([committed(&[1, 10], b"t")]));
+    let result = stmt.execute().unwrap();
+    assert_eq!(result.rows.iter().map(ints).collect::<Vec<_>>(), [[1, 10]]);
+
+    let methods: Vec<String> = fake.requests().into_iter().map(|(m, _)| m).collect();
+    // Prepared on creation, again because valid_until has passed, and again
+    // after the service reported the plan as expired
+    assert_eq!(
+        methods,
+        [
+            "prepareQuery",
+            "prepareQuery",
+            "executeQuery",
+            "prepareQuery",
+            "executeQuery"
+        ]
+    );
+
+    // A plan the service keeps rejecting is prepared again only once
+    for _ in 0..2 {
+        fake.script_query(json!([
+            {"error": {"code": 400, "message": "Prepared query expired", "status": "FAILED_PRECONDITION"}}
+        ]));
+    }
+    match stmt.execute() {
+        Err(BTErr::ApiErr(e)) => assert_eq!(e.status, "FAILED_PRECONDITION"),
+        other => panic!("expected the expired plan error, got {:?}", other),
+    }
+    assert_eq!(fake.requests().len(), methods.len() + 4);
+}
+
+#[test]
+fn test_statement_is_prepared_again_when_service_drops_plan() {
+    let fake = FakeBigtable::new();
+    fake.set_prepared_query(json!({
+        "metadata": print(&metadata(&["_key", "n"])),
+        "preparedQuery": "cHE=",
+        "validUntil": "2999-01-01T00:00:00Z"
+    }));
+    let token = common::token();
+    let mut stmt =
+        PreparedStatement::new(&common::table().instance, &token, "SELECT _key, n FROM t")
+            .transport(&fake)
+            .prepare()
+            .unwrap();
+
+    // The plan is still valid locally, but the service reports it expired
+    fake.script_query(json!([
+        {"error": {
+            "code": 400,
+            "message": "Precondition check failed.",
+            "status": "FAILED_PRECONDITION",
+            "details": [{
+                "@type": "type.googleapis.com/google.rpc.PreconditionFailure",
+                "violations": [{
+                    "type": "PREPARED_QUERY_EXPIRED",
+                    "subject": "prepared_query",
+                    "description": "The prepared query has expired."
+                }]
+            }]
+        }}
+    ]));
+    fake.script_query(json!([committed(&[1, 10], b"t")]));
+    let result = stmt.execute().unwrap();
+    assert_eq!(result.rows.iter().map(ints).collect::<Vec<_>>(), [[1, 10]]);
+    let methods: Vec<String> = fake.requests().into_iter().map(|(m, _)| m).collect();
+    assert_eq!(
+        methods,
+        [
+            "prepareQuery",
+            "executeQuery",
+            "prepareQuery",
+            "executeQuery"
+        ]
+    );
+
+    // Other preconditions fail the run, whatever the message says
+    fake.script_query(json!([
+        {"error": {"code": 400, "message": "Table has expired rows", "status": "FAILED_PRECONDITION"}}
+    ]));
+    match stmt.execute() {
+        Err(BTErr::ApiErr(e)) => {
+            assert_eq!(e.status, "FAILED_PRECONDITION");
+            assert!(e.details.is_empty());
+        }
+        other => panic!("expected the precondition error, got {:?}", other),
+    }
+    assert_eq!(fake.requests().len(), methods.len() + 1);
+}
+
+fn column(name: &str, ty: Type) -> ColumnMetadata {
+    let mut column = ColumnMetadata::new();
+    column.name = String::from(name);
+    column.type_ = Some(ty).into();
+    column
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Address {
+    city: String,
+    zip: i64,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+enum Status {
+    Open,
+    #[allow(dead_code)]
+    Closed,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Order {
+    #[serde(rename = "_key")]
+    key: Vec<u8>,
+    total: Option<i64>,
+    tags: Vec<String>,
+    counts: BTreeMap<String, i64>,
+    address: Address,
+    created: SystemTime,
+    day: Date,
+    status: Status,
+}
+
+#[test]
+fn test_rows_deserialize_by_column_name() {
+    let mut address = type_::Struct::new();
+    for (name, ty) in [("city", String::sql_type()), ("zip", i64::sql_type())] {
+        let mut field = type_::struct_::Field::new();
+        field.field_name = String::from(name);
+        field.type_ = Some(ty).into();
+        address.fields.push(field);
+    }
+    let mut address_type = Type::new();
+    address_type.kind = Some(type_::Kind::StructType(address));
+
+    let created = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let day = Date {
+        year: 2024,
+        month: 5,
+        day: 1,
+    };
+    let result = ResultSet {
+        // Columns in a different order than the struct fields
+        columns: vec![
+            column("status", String::sql_type()),
+            column("_key", <Vec<u8>>::sql_type()),
+            column("total", i64::sql_type()),
+            column("tags", <Vec<String>>::sql_type()),
+            column("counts", <BTreeMap<String, i64>>::sql_type()),
+            column("address", address_type),
+            column("created", SystemTime::sql_type()),
+            column("day", Date::sql_type()),
+        ],
+        rows: vec![QueryRow {
+            values: vec![
+                "Open".to_value(),
+                b"order#1".to_vec().to_value(),
+                None::<i64>.to_value(),
+                vec![String::from("a"), String::from("b")].to_value(),
+                BTreeMap::from([(String::from("x"), 2i64)]).to_value(),
+                array(vec!["Zagreb".to_value(), 10000i64.to_value()]),
+                created.to_value(),
+                day.to_value(),
+            ],
+        }],
+    };
+
+    let orders: Vec<Order> = result.deserialize().unwrap();
+    assert_eq!(
+        orders,
+        [Order {
+            key: b"order#1".to_vec(),
+            total: None,
+            tags: vec![String::from("a"), String::from("b")],
+            counts: BTreeMap::from([(String::from("x"), 2)]),
+            address: Address {
+                city: String::from("Zagreb"),
+                zip: 10000,
+            },
+            created,
+            day,
+            status: Status::Open,
+        }]
+    );
+
+    // A NULL cannot fill a required field
+    #[derive(Debug, Deserialize)]
+    struct Required {
+        #[allow(dead_code)]
+        total: i64,
+    }
+    assert!(result.deserialize::<Required>().is_err());
+
+    // Tuples take the columns in order, and strings can be borrowed
+    let pairs = ResultSet {
+        columns: vec![
+            column("k", String::sql_type()),
+            column("n", i64::sql_type()),
+        ],
+        rows: vec![QueryRow {
+            values: vec!["a".to_value(), 1i64.to_value()],
+        }],
+    };
+    let rows: Vec<(&str, i64)> = pairs.deserialize().unwrap();
+    assert_eq!(rows, [("a", 1)]);
+
+    // serde's SystemTime starts at the epoch, so earlier times are an error
+    let before = UNIX_EPOCH - Duration::from_millis(1500);
+    let old = ResultSet {
+        columns: vec![column("created", SystemTime::sql_type())],
+        rows: vec![QueryRow {
+            values: vec![before.to_value()],
+        }],
+    };
+    match &old.rows[0].values[0].kind {
+        Some(value::Kind::TimestampValue(ts)) => assert_eq!(ts.seconds, -2),
+        other => panic!("not a timestamp: {:?}", other),
+    }
+    match old.deserialize::<(SystemTime,)>() {
+        Err(BTErr::DecodeErr(msg)) => assert!(msg.contains("before the epoch"), "{}", msg),
+        other => panic!("expected DecodeErr, got {:?}", other),
+    }
+}
@@ -0,0 +1,182 @@
+// AIDEV-NOTE: Request routing tests: app profiles and the x-goog-request-params
+// header and views, checked on BTRequest directly and end to end against
+// tests/common.
+
+mod common;
+
+use bigtable::client::BigtableClient;
+use bigtable::error::BTErr;
+use bigtable::method::{BigTable, MutateRow, ReadChangeStream, ReadRows, SampleRowKeys};
+use bigtable::protos::data::RowSet;
+use bigtable::request::BTRequest;
+use bigtable::scan::ParallelScan;
+use bigtable::wraps::RowMutation;
+use common::FakeBigtable;
+use std::sync::Arc;
+
+#[test]
+fn test_routing_params() {
+    let mut req = BTRequest {
+        base: None,
+        table: common::table(),
+        method: ReadRows::new(),
+    };
+    assert_eq!(
+        req.resource_name(),
+        "projects/test-project/instances/test-inst/tables/my-table"
+    );
+    assert_eq!(
+        req.routing_params(),
+        "table_name=projects%2Ftest-project%2Finstances%2Ftest-inst%2Ftables%2Fmy-table"
+    );
+
+    req.table = common::table().app_profile("batch jobs");
+    assert_eq!(req.app_profile_id(), "batch jobs");
+    assert!(req
+        .routing_params()
+        .ends_with("%2Ftables%2Fmy-table&app_profile_id=batch%20jobs"));
+
+    // a profile set on the request itself wins
+    let mut req = BTRequest {
+        base: None,
+        table: common::table().app_profile("batch"),
+        method: MutateRow::new(),
+    };
+    req.method.payload_mut().app_profile_id = String::from("serving");
+    assert_eq!(req.app_profile_id(), "serving");
+}
+
+#[test]
+fn test_table_app_profile_applies_to_every_request() {
+    let fake = FakeBigtable::new();
+    for key in [&b"a"[..], b"b", b"c"] {
+        fake.set_cell(key, "cf1", b"q", 1000, b"v");
+    }
+    let token = common::token();
+    let table = common::table().app_profile("batch");
+    let rows = ParallelScan::new(&table, &token)
+        .rows(RowSet::new())
+        .concurrency(2)
+        .transport(&fake)
+        .collect()
+        .unwrap();
+    assert_eq!(rows.len(), 3);
+
+    let requests = fake.requests();
+    assert!(requests.iter().any(|(m, _)| m == "sampleRowKeys"));
+    for (method, body) in &requests {
+        assert!(
+            body.contains(r#""appProfileId": "batch""#),
+            "{}: {}",
+            method,
+            body
+        );
+    }
+    for headers in fake.headers() {
+        assert!(headers.iter().any(|h| h
+            .starts_with("x-goog-request-params: table_name=projects%2Ftest-project")
+            && h.ends_with("&app_profile_id=batch")));
+    }
+}
+
+#[test]
+fn test_view_urls() {
+    let view = common::table().authorized_view("eu-only").unwrap();
+    let req = BTRequest {
+        base: None,
+        table: view.clone(),
+        method: MutateRow::new(),
+    };
+    assert_eq!(
+        req.form_url().unwrap(),
+        "https://bigtable.googleapis.com/v2/projects/test-project/instances/test-inst/tables/my-table/authorizedViews/eu-only:mutateRow"
+    );
+    assert_eq!(
+        req.routing_params(),
+        "table_name=projects%2Ftest-project%2Finstances%2Ftest-inst%2Ftables%2Fmy-table"
+    );
+
+    let req = BTRequest {
+        base: None,
+        table: view,
+        method: ReadChangeStream::new(),
+    };
+    assert!(matches!(req.form_url(), Err(BTErr::TypeErr(_))));
+
+    let totals = common::table()
+        .instance
+        .materialized_view("daily_totals")
+        .unwrap();
+    let req = BTRequest {
+        base: None,
+        table: totals.clone(),
+        method: SampleRowKeys::new(),
+    };
+    assert_eq!(
+        req.form_url().unwrap(),
+        "https://bigtable.googleapis.com/v2/projects/test-project/instances/test-inst/materializedViews/daily_totals:sampleRowKeys"
+    );
+    assert_eq!(
+        req.routing_params(),
+        "name=projects%2Ftest-project%2Finstances%2Ftest-inst"
+    );
+    let req = BTRequest {
+        base: None,
+        table: totals,
+        method: MutateRow::new(),
+    };
+    assert!(matches!(req.form_url(), Err(BTErr::TypeErr(_))));
+}
+
+#[test]
+fn test_client_reads_views() {
+    let fake = Arc::new(FakeBigtable::new());
+    fake.set_cell(b"r1", "cf1", b"q", 1000, b"v");
+    let client = BigtableClient::new(&common::table().instance, common::token())
+        .app_profile("batch")
+        .transport(fake.clone());
+
+    let rows = client
+        .materialized_view("daily_totals")
+        .unwrap()
+        .read_rows(RowSet::new(), None, 0)
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    let view = client.authorized_view("my-table", "eu-only").unwrap();
+    view.mutate_row(b"r2", RowMutation::new().set_cell("cf1", b"q", b"v"))
+        .unwrap();
+    assert!(client
+        .materialized_view("daily_totals")
+        .unwrap()
+        .mutate_row(b"r3", RowMutation::new())
+        .is_err());
+    // Invalid IDs fail before any request is sent
+    assert!(matches!(
+        client.table("bad name"),
+        Err(BTErr::ResourceErr(_))
+    ));
+    assert!(matches!(
+        client.authorized_view("my-table", "-eu"),
+        Err(BTErr::ResourceErr(_))
+    ));
+    assert!(matches!(
+        client.materialized_view("daily/totals"),
+        Err(BTErr::ResourceErr(_))
+    ));
+
+    let routing: Vec<String> = fake
+        .headers()
+        .into_iter()
+        .flat_map(|h| {
+            h.into_iter()
+                .filter(|h| h.starts_with("x-goog-request-params"))
+        })
+        .collect();
+    assert_eq!(
+        routing,
+        vec![
+            "x-goog-request-params: name=projects%2Ftest-project%2Finstances%2Ftest-inst&app_profile_id=batch",
+            "x-goog-request-params: table_name=projects%2Ftest-project%2Finstances%2Ftest-inst%2Ftables%2Fmy-table&app_profile_id=batch",
+        ]
+    );
+}
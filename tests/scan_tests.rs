@@ -0,0 +1,174 @@
+// AIDEV-NOTE: Parallel scan tests run against the in-memory stand-in in tests/common.
+
+mod common;
+
+use bigtable::filters;
+use bigtable::protos::bigtable::SampleRowKeysResponse;
+use bigtable::protos::data::{row_range, RowRange, RowSet};
+use bigtable::scan::{self, KeyPredicate, ParallelScan};
+use common::FakeBigtable;
+use std::ops::Bound;
+
+fn fake_with_rows(n: usize) -> FakeBigtable {
+    let fake = FakeBigtable::new();
+    for i in 0..n {
+        let key = format!("row{:03}", i);
+        fake.set_cell(key.as_bytes(), "cf1", b"q", 1000, b"value");
+    }
+    fake
+}
+
+fn sample(key: &[u8], offset_bytes: i64) -> SampleRowKeysResponse {
+    let mut sample = SampleRowKeysResponse::new();
+    sample.row_key = key.to_vec();
+    sample.offset_bytes = offset_bytes;
+    sample
+}
+
+fn range(start: &[u8], end: &[u8]) -> RowRange {
+    let mut range = RowRange::new();
+    range.start_key = Some(row_range::Start_key::StartKeyClosed(start.to_vec()));
+    range.end_key = Some(row_range::End_key::EndKeyOpen(end.to_vec()));
+    range
+}
+
+#[test]
+fn test_split_weights_by_offset_bytes() {
+    // "c" holds most of the data, so it gets a shard of its own
+    let samples = vec![
+        sample(b"b", 10),
+        sample(b"c", 20),
+        sample(b"d", 100),
+        sample(b"", 110),
+    ];
+    let shards = scan::split_row_set(&RowSet::new(), &samples, 4);
+
+    assert_eq!(shards.len(), 3);
+    assert_eq!(shards[0].row_ranges[0], {
+        let mut r = RowRange::new();
+        r.end_key = Some(row_range::End_key::EndKeyOpen(b"c".to_vec()));
+        r
+    });
+    assert_eq!(shards[1].row_ranges[0], range(b"c", b"d"));
+    assert_eq!(
+        shards[2].row_ranges[0].start_key,
+        Some(row_range::Start_key::StartKeyClosed(b"d".to_vec()))
+    );
+    assert_eq!(shards[2].row_ranges[0].end_key, None);
+}
+
+#[test]
+fn test_split_clips_requested_row_set() {
+    let samples = vec![sample(b"b", 10), sample(b"d", 20), sample(b"", 30)];
+    let mut rows = RowSet::new();
+    rows.row_ranges.push(range(b"a", b"c"));
+    rows.row_keys.push(b"e".to_vec());
+
+    let shards = scan::split_row_set(&rows, &samples, 8);
+    assert_eq!(shards.len(), 3);
+    assert_eq!(shards[0].row_ranges[0], range(b"a", b"b"));
+    assert_eq!(shards[1].row_ranges[0], range(b"b", b"c"));
+    assert!(shards[1].row_keys.is_empty());
+    assert!(shards[2].row_ranges.is_empty());
+    assert_eq!(shards[2].row_keys, vec![b"e".to_vec()]);
+}
+
+#[test]
+fn test_parallel_scan_in_key_order() {
+    let fake = fake_with_rows(25);
+    let token = common::token();
+    let scan = ParallelScan::new(&common::table(), &token)
+        .concurrency(3)
+        .page_size(2)
+        .transport(&fake);
+
+    assert!(scan.shards().unwrap().len() > 3);
+    let keys: Vec<Vec<u8>> = scan.collect().unwrap().into_iter().map(|r| r.key).collect();
+    assert_eq!(keys, fake.row_keys());
+}
+
+#[test]
+fn test_parallel_scan_unordered_reads_every_row_once() {
+    let fake = fake_with_rows(25);
+    let token = common::token();
+    let mut rows = RowSet::new();
+    rows.row_ranges.push(range(b"row005", b"row020"));
+
+    let mut keys = Vec::new();
+    ParallelScan::new(&common::table(), &token)
+        .rows(rows)
+        .concurrency(4)
+        .page_size(3)
+        .transport(&fake)
+        .for_each_unordered(|row| keys.push(row.key))
+        .unwrap();
+
+    keys.sort();
+    let expected: Vec<Vec<u8>> = (5..20)
+        .map(|i| format!("row{:03}", i).into_bytes())
+        .collect();
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn test_key_predicates_narrow_row_set() {
+    let rows = scan::key_row_set(&[
+        KeyPredicate::Prefix(b"row01".to_vec()),
+        KeyPredicate::Range(Bound::Excluded(b"row012".to_vec()), Bound::Unbounded),
+    ])
+    .unwrap();
+    assert_eq!(rows.row_ranges.len(), 1);
+    assert_eq!(
+        rows.row_ranges[0].start_key,
+        Some(row_range::Start_key::StartKeyOpen(b"row012".to_vec()))
+    );
+    assert_eq!(
+        rows.row_ranges[0].end_key,
+        Some(row_range::End_key::EndKeyOpen(b"row02".to_vec()))
+    );
+
+    let keys = scan::key_row_set(&[
+        KeyPredicate::In(vec![b"b".to_vec(), b"a".to_vec(), b"z".to_vec()]),
+        KeyPredicate::Range(Bound::Unbounded, Bound::Excluded(b"c".to_vec())),
+    ])
+    .unwrap();
+    assert!(keys.row_ranges.is_empty());
+    assert_eq!(keys.row_keys, vec![b"a".to_vec(), b"b".to_vec()]);
+
+    assert_eq!(
+        scan::key_row_set(&[
+            KeyPredicate::Equals(b"a".to_vec()),
+            KeyPredicate::Prefix(b"b".to_vec()),
+        ]),
+        None
+    );
+    assert_eq!(scan::key_row_set(&[]), Some(RowSet::new()));
+}
+
+#[test]
+fn test_parallel_scan_pushes_down_limit_and_projection() {
+    let fake = fake_with_rows(25);
+    for i in 0..25 {
+        let key = format!("row{:03}", i);
+        fake.set_cell(key.as_bytes(), "cf1", b"other", 1000, b"skipped");
+    }
+    let token = common::token();
+    let rows = ParallelScan::new(&common::table(), &token)
+        .rows(scan::key_row_set(&[KeyPredicate::Prefix(b"row01".to_vec())]).unwrap())
+        .filter(filters::columns(&[("cf1", b"q")]))
+        .limit(4)
+        .concurrency(3)
+        .page_size(3)
+        .transport(&fake)
+        .collect()
+        .unwrap();
+
+    let keys: Vec<Vec<u8>> = rows.iter().map(|r| r.key.clone()).collect();
+    let expected: Vec<Vec<u8>> = (10..14)
+        .map(|i| format!("row{:03}", i).into_bytes())
+        .collect();
+    assert_eq!(keys, expected);
+    assert!(rows
+        .iter()
+        .all(|r| r.families[0].columns.len() == 1 && r.families[0].columns[0].qualifier == b"q"));
+}
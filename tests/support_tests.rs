@@ -0,0 +1,116 @@
+// AIDEV-NOTE: Resource name parsing, printing and ID validation in support.rs.
+
+use bigtable::error::BTErr;
+use bigtable::support::{Instance, Project, Table, View};
+
+#[test]
+fn test_resource_names_round_trip() {
+    let table = Project::new("my-project")
+        .unwrap()
+        .instance("my-instance")
+        .unwrap()
+        .table("my_table.v2")
+        .unwrap();
+    assert_eq!(
+        table.to_string(),
+        "projects/my-project/instances/my-instance/tables/my_table.v2"
+    );
+    assert_eq!(table.to_string().parse::<Table>().unwrap(), table);
+    assert_eq!(
+        table.instance.to_string().parse::<Instance>().unwrap(),
+        table.instance
+    );
+    assert_eq!(
+        "projects/example.com:my-project"
+            .parse::<Project>()
+            .unwrap()
+            .name,
+        "example.com:my-project"
+    );
+
+    for name in &[
+        "projects/my-project/instances/my-instance/tables/orders/authorizedViews/eu-only",
+        "projects/my-project/instances/my-instance/materializedViews/daily_totals",
+    ] {
+        let view: Table = name.parse().unwrap();
+        assert_eq!(view.to_string(), *name);
+    }
+    let view: Table = "projects/my-project/instances/my-instance/materializedViews/daily_totals"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        view.view,
+        Some(View::MaterializedView(String::from("daily_totals")))
+    );
+}
+
+#[test]
+fn test_invalid_resource_names() {
+    fn rejected<T: std::str::FromStr<Err = BTErr>>(name: &str) -> String {
+        match name.parse::<T>() {
+            Err(BTErr::ResourceErr(message)) => message,
+            Err(e) => panic!("{}: unexpected error {}", name, e),
+            Ok(_) => panic!("{} was accepted", name),
+        }
+    }
+
+    for project in &[
+        "abc",
+        "1project",
+        "My-Project",
+        "my-project-",
+        ":my-project",
+    ] {
+        let message = rejected::<Project>(&format!("projects/{}", project));
+        assert!(message.contains("project ID"), "{}", message);
+    }
+    for instance in &["inst", "my_instance", "my-instance-", &"i".repeat(34)] {
+        let message = rejected::<Instance>(&format!("projects/my-project/instances/{}", instance));
+        assert!(message.contains("instance ID"), "{}", message);
+    }
+    for table in &[
+        "",
+        "-orders",
+        ".orders",
+        "or/ders",
+        "or ders",
+        &"t".repeat(51),
+    ] {
+        rejected::<Table>(&format!(
+            "projects/my-project/instances/my-instance/tables/{}",
+            table
+        ));
+    }
+    let message = rejected::<Table>(
+        "projects/my-project/instances/my-instance/tables/orders/authorizedViews/.eu",
+    );
+    assert!(message.contains("authorized view ID"), "{}", message);
+
+    for name in &[
+        "my-project/my-instance/orders",
+        "projects/my-project/instances/my-instance",
+        "projects/my-project/instances/my-instance/tables/orders/",
+        "projects/my-project/instances/my-instance/views/orders",
+    ] {
+        let message = rejected::<Table>(name);
+        assert!(message.starts_with("expected projects/"), "{}", message);
+    }
+    assert!(Project::new("my-project")
+        .unwrap()
+        .instance("Instance")
+        .is_err());
+
+    let instance = Project::new("my-project")
+        .unwrap()
+        .instance("my-instance")
+        .unwrap();
+    let table = instance.table("orders").unwrap();
+    assert!(matches!(
+        table.authorized_view("eu only"),
+        Err(BTErr::ResourceErr(_))
+    ));
+    assert!(matches!(
+        instance.materialized_view(""),
+        Err(BTErr::ResourceErr(_))
+    ));
+}
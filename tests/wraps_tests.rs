@@ -0,0 +1,66 @@
+// AIDEV-NOTE: Offline tests for wraps helpers that decode responses.
+// These run without credentials.
+
+use bigtable::filters::escape_regex;
+use bigtable::protos::bigtable::ReadModifyWriteRowResponse;
+use bigtable::protos::data::mutation;
+use bigtable::request::parse_message;
+use bigtable::wraps::{ModifiedRow, RowMutation};
+use serde_json::json;
+
+#[test]
+fn test_modified_row_decodes_new_values() {
+    let response = json!({
+        "row": {
+            "key": "cjE=",
+            "families": [{
+                "name": "cf1",
+                "columns": [
+                    {"qualifier": "aGl0cw==", "cells": [{"timestampMicros": "1000", "value": "AAAAAAAAACo="}]},
+                    {"qualifier": "bG9n", "cells": [{"timestampMicros": "1000", "value": "YSxi"}]}
+                ]
+            }]
+        }
+    });
+
+    let mut decoded = parse_message::<ReadModifyWriteRowResponse>(&response).unwrap();
+    let row = ModifiedRow {
+        row: decoded.row.take().unwrap(),
+    };
+
+    assert_eq!(row.int("cf1", b"hits").unwrap(), Some(42));
+    assert_eq!(row.value("cf1", b"log"), Some(&b"a,b"[..]));
+    assert_eq!(row.value("cf1", b"missing"), None);
+    assert!(row.int("cf1", b"log").is_err());
+}
+
+#[test]
+fn test_escape_regex() {
+    assert_eq!(escape_regex(b"abc_XYZ09"), b"abc_XYZ09".to_vec());
+    assert_eq!(escape_regex(b"a.b*c"), br"a\.b\*c".to_vec());
+    assert_eq!(escape_regex(b"$1 (x)"), br"\$1\ \(x\)".to_vec());
+    assert_eq!(escape_regex(br"\x00"), br"\\x00".to_vec());
+    assert_eq!(escape_regex(&[0, b'a']), br"\x00a".to_vec());
+    assert_eq!(escape_regex("é".as_bytes()), "é".as_bytes().to_vec());
+}
+
+#[test]
+fn test_row_mutation_builder() {
+    let m = RowMutation::new()
+        .set_cell("cf1", b"q", b"v")
+        .delete_cells("cf1", b"old")
+        .delete_row();
+
+    assert_eq!(m.mutations.len(), 3);
+    match &m.mutations[0].mutation {
+        Some(mutation::Mutation::SetCell(c)) => {
+            assert_eq!(c.timestamp_micros, -1);
+            assert_eq!(c.value, b"v".to_vec());
+        }
+        other => panic!("unexpected mutation {:?}", other),
+    }
+    assert!(matches!(
+        m.mutations[2].mutation,
+        Some(mutation::Mutation::DeleteFromRow(_))
+    ));
+}